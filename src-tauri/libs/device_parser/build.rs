@@ -54,12 +54,14 @@ fn main() {
     to_mod +=format!("\npub const MCU_STRUCT: phf::Map<&'static str,&'static AvrDeviceFile>= phf_map!{{{}}};", mods.iter().map(|f| format!("\"{0}\"=>&{0}::{1}", f, f.to_uppercase())).collect::<Vec<String>>().join(",")).as_str();
     to_mod +=format!("\npub const MCU_REGISTER_STRUCT: phf::Map<&'static str,&'static phf::Map<u64,&'static Register>>= phf_map!{{{}}};", mods.iter().map(|f| format!("\"{0}\"=>&{0}::REGISTERMAP", f)).collect::<Vec<String>>().join(",")).as_str();
     to_mod +=format!("\npub const MCU_COMMON_REGISTER_STRUCT: phf::Map<&'static str,&'static CommonRegisters>= phf_map!{{{}}};", success.iter().map(|f| format!("\"{0}\"=>&{0}::COMMONREGISTERS", f)).collect::<Vec<String>>().join(",")).as_str();
+    to_mod +=format!("\npub const MCU_PARSE_ERRORS: &[(&str,&str)] = &[{}];", get_parse_errors().iter().map(|(name,e)| format!("({:?},{:?})", name, e)).collect::<Vec<String>>().join(",")).as_str();
     fs::write(Path::new(&out_dir).join("avr/mod.rs"), to_mod).unwrap();
     //info!("sucess:{} from:{}",c2,c1);
 }
 
 
 static mut TREE_MAP:Option<HashMap<String,AvrDeviceFile>> = None;
+static mut PARSE_ERRORS:Vec<(String,String)> = vec![];
 #[allow(static_mut_refs)]
 pub fn get_tree_map() ->Result<&'static HashMap<String,AvrDeviceFile>,xmltree::Error>{
     if unsafe{ TREE_MAP.is_none()}{
@@ -67,7 +69,16 @@ pub fn get_tree_map() ->Result<&'static HashMap<String,AvrDeviceFile>,xmltree::E
         let mut map = HashMap::new();
         for file in files{
             let dir_entry = file?;
-            map.insert(dir_entry.file_name().to_str().unwrap().to_string().strip_suffix(".atdf").unwrap().to_lowercase(),get_tree(&dir_entry)?);
+            let name = dir_entry.file_name().to_str().unwrap().to_string().strip_suffix(".atdf").unwrap().to_lowercase();
+            match get_tree(&dir_entry) {
+                Ok(tree) => {
+                    map.insert(name, tree);
+                }
+                Err(e) => {
+                    warn!("{}",e);
+                    unsafe { PARSE_ERRORS.push((name, e.to_string())); }
+                }
+            }
         }
         unsafe { TREE_MAP = Some(map);}
     }
@@ -76,62 +87,28 @@ pub fn get_tree_map() ->Result<&'static HashMap<String,AvrDeviceFile>,xmltree::E
     }
 }
 
-pub fn get_tree(file:&DirEntry) -> Result<AvrDeviceFile,xmltree::Error>{
-    let data = &*fs::read_to_string(file.path()).unwrap();
-    let elem = Element::parse(data.as_bytes()).unwrap();
+/// devices that failed to parse with the reason, by lowercase file name
+#[allow(static_mut_refs)]
+pub fn get_parse_errors()->&'static Vec<(String,String)>{
+    unsafe { &PARSE_ERRORS }
+}
+
+pub fn get_tree(file:&DirEntry) -> anyhow::Result<AvrDeviceFile>{
+    let file_name = file.file_name().to_string_lossy().to_string();
+    let data = &*fs::read_to_string(file.path())?;
+    let elem = Element::parse(data.as_bytes()).map_err(|e| anyhow::anyhow!("{}: {}", file_name, e))?;
     let b :&'static Element=Box::leak(Box::from(elem));
-    let a = AvrDeviceFile::from(b);
+    let a = AvrDeviceFile::try_from(b).map_err(|e| e.in_file(&file_name))?;
     Ok(a)
 }
 
 pub fn get_register_map(device_name:&String)->HashMap<u64,&'static Register>{
     match get_tree_map().unwrap().get(device_name.as_str()){
-        Some(t)=>{
-            let mut reg_map = HashMap::<u64,&'static Register>::new();
-            t.modules.iter().for_each(|x| {
-                x.register_group.iter().for_each(|x1| {
-                    x1.register.iter().for_each(|x2| {
-                        match x2.size{
-                            1=>{
-                                reg_map.insert(x2.offset,x2);
-                            }
-                            2=>{
-                                if x2.size ==2{
-                                    let leaked1: &'static Register = Box::leak(Box::new(Register {
-                                        caption: x2.caption.clone(),
-                                        name: &*(Box::leak(Box::new(x2.name.to_owned() + "H"))),
-                                        offset: x2.offset+1,
-                                        size: 1,
-                                        initval: x2.initval,
-                                        bitfields: x2.bitfields.clone(),
-                                    }));
-                                    let leaked2: &'static Register = Box::leak(Box::new(Register {
-                                        caption:x2.caption.clone(),
-                                        name: &*(Box::leak(Box::new(x2.name.to_owned() + "L"))),
-                                        offset: x2.offset,
-                                        size: 1,
-                                        initval: x2.initval,
-                                        bitfields: x2.bitfields.clone(),
-                                    }));
-
-                                    reg_map.insert(x2.offset+1, leaked1);
-                                    reg_map.insert(x2.offset, leaked2);
-                                }
-                            }
-                            _=>{}
-                        }
-                    })
-                })
-            });
-
-            reg_map
-        }
+        Some(t)=>t.register_map(),
         None=>{
             panic!("device not found");
         }
     }
-
-
 }
 
 pub fn get_mcu_list()->Vec<&'static String>{
@@ -142,8 +119,7 @@ pub fn get_mcu_list()->Vec<&'static String>{
 pub fn get_common_registers(device_name:&String,reg_map:&HashMap<u64,&'static Register>) ->Option<CommonRegisters>{
     let tree = get_tree_map().unwrap().get(device_name.as_str()).unwrap();
     let mut a:Vec<u8> =Vec::new();
-    a.resize((tree.devices.address_spaces.iter().find(|x| {x.id == "data" })?
-        .memory_segments.iter().find(|x1| {  "MAPPED_IO".to_string().eq(x1.name) })?.size + 20) as usize,0);
+    a.resize((tree.io_size()? + 20) as usize,0);
     match CommonRegisters::init(tree,reg_map,&mut a){
         Ok(t)=>{
             Some(t)
@@ -0,0 +1,154 @@
+use crate::AvrDeviceFile;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+/// summary of a device for tooling, sizes are in bytes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    pub name: &'static str,
+    pub architecture: &'static str, // core variant, e.g. AVR8, AVR8X or AVR8L
+    pub family: &'static str,
+    pub flash_size: u64,
+    pub flash_page_size: u64,
+    pub sram_size: u64,
+    pub eeprom_size: u64,
+    pub eeprom_page_size: u64,
+    pub eind: bool,
+    pub ramp: Vec<&'static str>, // RAMPX, RAMPY, RAMPZ and RAMPD the device has
+    pub pc_bits: u32,            // width of the word addressed program counter
+    pub vector_size: u32,        // bytes per interrupt vector
+    pub vector_count: u32,
+    pub instances: Vec<&'static str>,
+    pub max_speed: Option<u64>, // Hz, fastest variant
+    pub vcc_min: Option<f64>,
+    pub vcc_max: Option<f64>,
+}
+
+/// what the ui filters the mcu list with, unset fields match every device
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CapabilityFilter {
+    pub architecture: Option<String>,
+    pub min_flash: Option<u64>,
+    pub min_sram: Option<u64>,
+    pub min_eeprom: Option<u64>,
+    pub min_speed: Option<u64>,
+    pub instances: Vec<String>, // e.g. USART0, every one has to be present
+}
+
+impl Capabilities {
+    pub fn new(atdf: &'static AvrDeviceFile) -> Capabilities {
+        let registers: Vec<&'static str> = atdf
+            .modules
+            .iter()
+            .filter(|x| x.name == "CPU")
+            .flat_map(|x| x.register_group.iter())
+            .flat_map(|x| x.register.iter())
+            .map(|x| x.name)
+            .collect();
+        let flash_size = atdf.flash().map_or(0, |x| x.size);
+        let eeprom = atdf.eeprom();
+        let variants = atdf.variants;
+        Capabilities {
+            name: atdf.devices.name,
+            architecture: atdf.devices.architecture,
+            family: atdf.devices.family,
+            flash_size,
+            flash_page_size: atdf.flash().and_then(|x| x.page_size).unwrap_or(0),
+            sram_size: atdf.ram().map_or(0, |x| x.size),
+            eeprom_size: eeprom.map_or(0, |x| x.size),
+            eeprom_page_size: eeprom.and_then(|x| x.page_size).unwrap_or(0),
+            eind: registers.contains(&"EIND"),
+            ramp: ["RAMPX", "RAMPY", "RAMPZ", "RAMPD"]
+                .into_iter()
+                .filter(|x| registers.contains(x))
+                .collect(),
+            pc_bits: (flash_size / 2).next_power_of_two().trailing_zeros(),
+            // devices above 8KB use two word vectors to fit a jmp
+            vector_size: if flash_size > 0x2000 { 4 } else { 2 },
+            vector_count: atdf.devices.interrupts.iter().map(|x| x.index as u32 + 1).max().unwrap_or(0),
+            instances: atdf
+                .devices
+                .peripherals
+                .iter()
+                .flat_map(|x| x.instances.iter().map(|x| x.name))
+                .collect(),
+            max_speed: variants.iter().filter_map(|x| x.max_speed).max().map(|x| x as u64),
+            vcc_min: variants.iter().filter_map(|x| x.vcc_min).reduce(f64::min),
+            vcc_max: variants.iter().filter_map(|x| x.vcc_max).reduce(f64::max),
+        }
+    }
+
+    pub fn matches(&self, filter: &CapabilityFilter) -> bool {
+        let at_least = |value: u64, min: Option<u64>| min.is_none_or(|x| value >= x);
+        filter.architecture.as_ref().is_none_or(|x| x.eq_ignore_ascii_case(self.architecture))
+            && at_least(self.flash_size, filter.min_flash)
+            && at_least(self.sram_size, filter.min_sram)
+            && at_least(self.eeprom_size, filter.min_eeprom)
+            && filter.min_speed.is_none_or(|x| self.max_speed.is_some_and(|y| y >= x))
+            && filter.instances.iter().all(|x| self.instances.contains(&x.as_str()))
+    }
+
+    /// rejects a clock faster than every variant of the device
+    pub fn check_frequency(&self, freq: u32) -> anyhow::Result<()> {
+        if freq == 0 {
+            return Err(anyhow!("invalid frequency:0"));
+        }
+        match self.max_speed {
+            Some(max) if freq as u64 > max => Err(anyhow!(
+                "{} Hz exceeds the {} Hz maximum of the {}",
+                freq,
+                max,
+                self.name
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_capabilities;
+
+    #[test]
+    fn test_capabilities() {
+        let atmega328p = get_capabilities("atmega328p").unwrap();
+        assert_eq!(
+            (atmega328p.flash_size, atmega328p.flash_page_size, atmega328p.sram_size, atmega328p.eeprom_size),
+            (0x8000, 0x80, 0x800, 0x400)
+        );
+        assert_eq!((atmega328p.pc_bits, atmega328p.vector_size, atmega328p.vector_count), (14, 4, 26));
+        assert_eq!((atmega328p.eind, atmega328p.ramp.len(), atmega328p.max_speed), (false, 0, Some(20_000_000)));
+        assert_eq!((atmega328p.vcc_min, atmega328p.vcc_max), (Some(1.8), Some(5.5)));
+        assert!(atmega328p.instances.contains(&"USART0"));
+        assert!(atmega328p.check_frequency(16_000_000).is_ok());
+        assert!(atmega328p.check_frequency(32_000_000).is_err());
+
+        let atmega2560 = get_capabilities("atmega2560").unwrap();
+        assert_eq!((atmega2560.eind, atmega2560.ramp.clone(), atmega2560.pc_bits), (true, vec!["RAMPZ"], 17));
+
+        let atmega4809 = get_capabilities("atmega4809").unwrap();
+        assert_eq!((atmega4809.sram_size, atmega4809.eeprom_size, atmega4809.eeprom_page_size), (0x1800, 0x100, 0x40));
+
+        let filter = CapabilityFilter {
+            min_flash: Some(0x8000),
+            instances: vec!["USART0".to_string()],
+            ..Default::default()
+        };
+        assert!(atmega328p.matches(&filter) && atmega4809.matches(&filter));
+        assert!(!get_capabilities("attiny85").unwrap().matches(&filter));
+        let filter = CapabilityFilter {
+            architecture: Some("avr8x".to_string()),
+            ..Default::default()
+        };
+        assert!(atmega4809.matches(&filter) && !atmega328p.matches(&filter));
+
+        // attributes missing from an imported file stay unknown
+        let xml = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/atdf/ATmega328P.atdf")).unwrap();
+        let xml = xml.replace(" vccmin=\"1.8\"", "").replace(" speedmax=\"20000000\"", "");
+        let imported = Capabilities::new(crate::loader::parse(&xml).unwrap());
+        assert_eq!((imported.max_speed, imported.vcc_min, imported.vcc_max), (None, None, Some(5.5)));
+    }
+}
@@ -5,27 +5,168 @@ pub mod r#struct;
 #[path = "../utils.rs"]
 pub mod utils;
 
+pub mod loader;
+
+pub mod capabilities;
+
 include!(concat!(env!("OUT_DIR"), "/avr/mod.rs"));
 
 
+/// the devices generated by build.rs, see `get_device` for loaded ones too
 pub const fn get_tree_map() ->&'static phf::Map<&'static str,&'static AvrDeviceFile>{
     &MCU_STRUCT
 }
-pub fn get_register_map(device_name:&String)->Option<&'static phf::Map<u64,&'static Register>>{
+/// a built-in device or one loaded at runtime by `loader`
+pub fn get_device(mcu:&str)->Option<&'static AvrDeviceFile>{
+    match MCU_STRUCT.get(mcu){
+        Some(t)=>Some(*t),
+        None=>loader::get(mcu).map(|x| x.atdf)
+    }
+}
+pub fn get_register_map(device_name:&String)->Option<RegisterMap>{
     match MCU_REGISTER_STRUCT.get(&device_name){
-        None=>None,
-        Some(t)=>Some(*t)
+        None=>loader::get(device_name).map(|x| RegisterMap::Loaded(x.registers)),
+        Some(t)=>Some(RegisterMap::Static(t))
     }
 }
-pub const fn get_mcu_list()->&'static[&'static str]{
-    MCU_LIST
+pub fn get_mcu_list()->Vec<&'static str>{
+    let mut list = MCU_LIST.to_vec();
+    list.extend(loader::names());
+    list
+}
+/// capability summary of a built-in or loaded device
+pub fn get_capabilities(mcu:&str)->Option<capabilities::Capabilities>{
+    get_device(mcu).map(capabilities::Capabilities::new)
+}
+/// the devices of `get_mcu_list` that match `filter`
+pub fn filter_mcu_list(filter:&capabilities::CapabilityFilter)->Vec<&'static str>{
+    get_mcu_list().into_iter().filter(|x| get_capabilities(x).is_some_and(|x1| x1.matches(filter))).collect()
 }
 pub fn get_common_registers(mcu:&str)->Option<&'static CommonRegisters>{match MCU_COMMON_REGISTER_STRUCT.get(mcu){
-    None=>None,
+    None=>loader::get(mcu)?.common_registers.map(|x| x.0),
     Some(t)=>Some(*t)
 }}
 
+/// devices that failed to parse with the reason, built-in ones at build time and files of `loader::load_dir`
+pub fn get_parse_errors()->Vec<(String,String)>{
+    let mut errors:Vec<(String,String)> = MCU_PARSE_ERRORS.iter().map(|(x,e)| (x.to_string(),e.to_string())).collect();
+    errors.extend(loader::errors());
+    errors
+}
+
+/// registers of a device by data address, generated or built when the device was loaded
+#[derive(Debug,Clone,Copy)]
+pub enum RegisterMap{
+    Static(&'static phf::Map<u64,&'static Register>),
+    Loaded(&'static std::collections::HashMap<u64,&'static Register>),
+}
+impl RegisterMap{
+    pub fn get(&self, address:&u64)->Option<&'static Register>{
+        match self{
+            RegisterMap::Static(x)=>x.get(address).copied(),
+            RegisterMap::Loaded(x)=>x.get(address).copied(),
+        }
+    }
+    pub fn entries(&self)->Box<dyn Iterator<Item=(u64,&'static Register)>>{
+        match *self{
+            RegisterMap::Static(x)=>Box::new(x.entries().map(|(k,v)| (*k,*v))),
+            RegisterMap::Loaded(x)=>Box::new(x.iter().map(|(k,v)| (*k,*v))),
+        }
+    }
+}
+
+/// decodes `value` of a register of `mcu` with the value groups of the module describing it
+pub fn decode_register(mcu:&str, register:&Register, value:u64)->Option<Vec<DecodedField>>{
+    get_device(mcu)?.modules.iter().find(|x| {
+        x.register_group.iter().flat_map(|x1| x1.register.iter()).any(|x1| x1.name == register.name)
+    }).map(|x| x.decode(register, value))
+}
+
 pub type AvrDeviceFile= crate::r#struct::avr_device_file::AvrDeviceFile;
 pub type Register= crate::r#struct::module::Register;
+pub type DecodedField= crate::r#struct::module::DecodedField;
 pub type CommonRegisters = crate::r#struct::common_registers::CommonRegisters;
-pub type Error = String;
\ No newline at end of file
+pub type Error = String;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::r#struct::module::Module;
+
+    fn register(mcu: &str, module: &str, name: &str) -> &'static Register {
+        let atdf = get_tree_map().get(mcu).unwrap();
+        atdf.modules
+            .iter()
+            .filter(|x: &&Module| x.name == module)
+            .flat_map(|x| x.register_group.iter())
+            .flat_map(|x| x.register.iter())
+            .find(|x| x.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_initval() {
+        assert_eq!(register("atmega328p", "FUSE", "LOW").initval, 0x62);
+        assert_eq!(register("atmega328p", "FUSE", "HIGH").initval, 0xd9);
+        assert_eq!(register("atmega328p", "TC16", "TCCR1B").initval, 0);
+        assert_eq!(register("atmega4809", "CLKCTRL", "MCLKCTRLB").initval, 0x11);
+        assert_eq!(register("atmega4809", "TCA", "PER").initval, 0xffff);
+        assert_eq!(register("attiny85", "FUSE", "LOW").initval, 0x62);
+    }
+
+    #[test]
+    fn test_rw() {
+        let ucsr0a = register("atmega328p", "USART", "UCSR0A");
+        let rxc0 = ucsr0a.bitfields.unwrap().iter().find(|x| x.name == "RXC0").unwrap();
+        let txc0 = ucsr0a.bitfields.unwrap().iter().find(|x| x.name == "TXC0").unwrap();
+        assert_eq!((rxc0.rw, txc0.rw), (Some("R"), None));
+        assert_eq!(register("atmega4809", "ADC", "RES").rw, Some("R"));
+        let status = register("atmega4809", "AC", "STATUS");
+        assert_eq!(status.rw, Some("RW"));
+        assert_eq!(status.bitfields.unwrap()[1].rw, Some("R"));
+    }
+
+    #[test]
+    fn test_bitfield_values() {
+        let lockbit = register("atmega328p", "LOCKBIT", "LOCKBIT");
+        assert_eq!(lockbit.bitfields.unwrap()[0].values, Some("ENUM_LB"));
+        let adcsra = register("attiny85", "ADC", "ADCSRA");
+        let adps = adcsra.bitfields.unwrap().iter().find(|x| x.name == "ADPS").unwrap();
+        assert_eq!(adps.values, Some("ANALOG_ADC_PRESCALER"));
+        assert_eq!(adcsra.bitfields.unwrap()[0].values, None);
+    }
+
+    #[test]
+    fn test_decode_register() {
+        let tccr0b = register("atmega328p", "TC8", "TCCR0B");
+        let fields = decode_register("atmega328p", tccr0b, 0x03).unwrap();
+        let cs0 = fields.iter().find(|x| x.name == "CS0").unwrap();
+        assert_eq!((cs0.value, cs0.caption), (3, Some("Running, CLK/64")));
+
+        let admux = register("atmega328p", "ADC", "ADMUX");
+        let fields = decode_register("atmega328p", admux, 0x45).unwrap();
+        let refs = fields.iter().find(|x| x.name == "REFS").unwrap();
+        assert_eq!(refs.caption, Some("AVCC with external capacitor at AREF pin"));
+        // ADLAR has no value group
+        assert_eq!(fields.iter().find(|x| x.name == "ADLAR").unwrap().caption, None);
+
+        // WDP3 is bit 5, apart from WDP2..0
+        let wdtcsr = register("atmega328p", "WDT", "WDTCSR");
+        let fields = decode_register("atmega328p", wdtcsr, 0x21).unwrap();
+        let wdp = fields.iter().find(|x| x.name == "WDP").unwrap();
+        assert_eq!((wdp.value, wdp.caption), (9, Some("Oscillator Cycles 1024K")));
+    }
+
+    #[test]
+    fn test_common_registers() {
+        let name = |mcu: &str, key: &str| {
+            let mut registers = *get_common_registers(mcu).unwrap();
+            registers.iter_mut().find(|(x, _)| *x == key).unwrap().1.register.name
+        };
+        assert_eq!((name("atmega2560", "eind"), name("atmega2560", "rampz")), ("EIND", "RAMPZ"));
+        // AVRxt splits the two byte SP of the CPU module and has no MCUCR
+        assert_eq!((name("atmega4809", "spl"), name("atmega4809", "sph"), name("atmega4809", "mcucr")), ("SPL", "SPH", ""));
+        assert_eq!((name("attiny26", "spl"), name("attiny13", "sph")), ("SP", ""));
+        assert_eq!((name("attiny11", "sreg"), name("attiny11", "spl")), ("SREG", ""));
+        assert_eq!(MCU_COMMON_REGISTER_STRUCT.len(), MCU_LIST.len());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{AvrDeviceFile, CommonRegisters, Register};
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+use xmltree::Element;
+
+/// a device parsed at runtime, built-in devices come from the phf maps of build.rs
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LoadedDevice {
+    pub atdf: &'static AvrDeviceFile,
+    pub registers: &'static HashMap<u64, &'static Register>,
+    pub common_registers: Option<Detached>, // None when the core registers are missing
+}
+
+/// common registers without data pointers, nothing in them is shared mutably
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Detached(pub &'static CommonRegisters);
+unsafe impl Send for Detached {}
+unsafe impl Sync for Detached {}
+
+static LOADED: LazyLock<RwLock<HashMap<&'static str, LoadedDevice>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+static FAILED: RwLock<Vec<(String, String)>> = RwLock::new(vec![]);
+
+/// parses the content of an .atdf file into the structures build.rs generates
+pub fn parse(xml: &str) -> anyhow::Result<&'static AvrDeviceFile> {
+    let element: &'static Element = Box::leak(Box::new(Element::parse(xml.as_bytes())?));
+    if element.name != "avr-tools-device-file" {
+        return Err(anyhow!("not an atdf file:{}", element.name));
+    }
+    let atdf: &'static AvrDeviceFile = Box::leak(Box::new(AvrDeviceFile::try_from(element)?));
+    // the simulator needs both, built-in devices always have them
+    for id in ["prog", "data"] {
+        if !atdf.devices.address_spaces.iter().any(|x| x.id == id) {
+            return Err(anyhow!("missing address space:{}", id));
+        }
+    }
+    Ok(atdf)
+}
+
+/// lowercase name a device is registered and stored under, only `[a-z0-9_-]` is accepted
+pub fn name(atdf: &'static AvrDeviceFile) -> anyhow::Result<&'static str> {
+    let name = atdf.devices.name.to_lowercase();
+    if name.is_empty() || !name.chars().all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '_' || x == '-') {
+        return Err(anyhow!("invalid device name:{}", atdf.devices.name));
+    }
+    if crate::MCU_STRUCT.contains_key(name.as_str()) {
+        return Err(anyhow!("device is built in:{}", name));
+    }
+    Ok(Box::leak(name.into_boxed_str()))
+}
+
+/// registers a parsed device under its name, returns that name
+pub fn register(atdf: &'static AvrDeviceFile) -> anyhow::Result<&'static str> {
+    let name = name(atdf)?;
+    let registers: &'static HashMap<u64, &'static Register> = Box::leak(Box::new(atdf.register_map()));
+    let device = LoadedDevice {
+        atdf,
+        registers,
+        common_registers: common_registers(atdf, registers).map(Detached),
+    };
+    LOADED
+        .write()
+        .map_err(|e| anyhow!("poison error:{}", e))?
+        .insert(name, device);
+    Ok(name)
+}
+
+/// parses `xml` and registers the device under its lowercase name, returns that name
+pub fn load(xml: &str) -> anyhow::Result<&'static str> {
+    register(parse(xml)?)
+}
+
+/// files that failed to load with their error
+pub type LoadErrors = Vec<(String, anyhow::Error)>;
+
+/// loads every .atdf file of `dir`, returns the loaded devices and the files that failed
+pub fn load_dir(dir: &Path) -> anyhow::Result<(Vec<&'static str>, LoadErrors)> {
+    let mut loaded = vec![];
+    let mut failed = vec![];
+    if !dir.exists() {
+        return Ok((loaded, failed));
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                failed.push((dir.display().to_string(), e.into()));
+                continue;
+            }
+        };
+        if path.extension().is_none_or(|x| x != "atdf") {
+            continue;
+        }
+        match std::fs::read_to_string(&path).map_err(anyhow::Error::from).and_then(|x| load(&x)) {
+            Ok(name) => loaded.push(name),
+            Err(e) => failed.push((path.display().to_string(), e)),
+        }
+    }
+    if let Ok(mut errors) = FAILED.write() {
+        errors.extend(failed.iter().map(|(file, e)| (file.clone(), e.to_string())));
+    }
+    Ok((loaded, failed))
+}
+
+pub(crate) fn get(mcu: &str) -> Option<LoadedDevice> {
+    LOADED.read().ok()?.get(mcu).copied()
+}
+
+pub(crate) fn errors() -> Vec<(String, String)> {
+    FAILED.read().map(|x| x.clone()).unwrap_or_default()
+}
+
+pub(crate) fn names() -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = LOADED.read().map(|x| x.keys().copied().collect()).unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// common registers of a device, None when the core registers are missing
+fn common_registers(
+    atdf: &'static AvrDeviceFile,
+    registers: &'static HashMap<u64, &'static Register>,
+) -> Option<&'static CommonRegisters> {
+    let mut data = vec![0u8; atdf.io_size()? as usize + 20];
+    let registers = CommonRegisters::init(atdf, registers, &mut data).ok()?;
+    Some(Box::leak(Box::new(registers.detached())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let xml = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/atdf/ATmega328P.atdf")).unwrap();
+        let renamed = xml.replace("name=\"ATmega328P\"", "name=\"ATmega328X\"");
+        assert_eq!(load(&renamed).unwrap(), "atmega328x");
+        assert!(load(&xml).is_err());
+        assert!(load(&xml.replace("name=\"ATmega328P\"", "name=\"../ATmega328X\"")).is_err());
+        assert!(parse("<avr-tools-device-file/>").is_err());
+        let broken = renamed.replace("<interrupt index=\"1\" ", "<interrupt ");
+        assert_eq!(
+            parse(&broken).unwrap_err().to_string(),
+            "avr-tools-device-file/devices/device[ATmega328X]/interrupts/interrupt[INT0]: missing attribute index"
+        );
+        assert!(parse("<html/>").is_err());
+        let no_prog = renamed.replace("id=\"prog\"", "id=\"flash\"");
+        assert_eq!(parse(&no_prog).unwrap_err().to_string(), "missing address space:prog");
+
+        assert!(crate::get_mcu_list().contains(&"atmega328x"));
+        let atdf = crate::get_device("atmega328x").unwrap();
+        assert_eq!(atdf.devices.name, "ATmega328X");
+        let map = crate::get_register_map(&"atmega328x".to_string()).unwrap();
+        assert_eq!(map.get(&0x5f).unwrap().name, "SREG");
+        let registers = crate::get_common_registers("atmega328x").unwrap();
+        assert!(std::ptr::eq(registers, crate::get_common_registers("atmega328x").unwrap()));
+    }
+}
@@ -1,13 +1,16 @@
+use std::collections::HashMap;
 use std::sync::OnceLock;
 use proc_macro2::{Span};
 use syn::Ident;
 use quote::__private::TokenStream;
 use quote::{quote, ToTokens};
 use xmltree::Element;
-use super::utils::{find_child, find_childs};
+use super::utils::find_child;
+use super::parse_error::{child, parse_childs, parse_list, ParseError};
+use super::device_address_space::{AddressSpace, MemorySegment};
 use super::device_info::{Device, Variant};
 use super::device_package::Pinout;
-use super::module::Module;
+use super::module::{Module, Register};
 
 #[derive(Debug,Default)]
 pub struct AvrDeviceFile {
@@ -16,17 +19,101 @@ pub struct AvrDeviceFile {
     pub modules:&'static [Module],
     pub pinouts:Option<&'static [Pinout]>,
 }
-impl From<&'static Element> for AvrDeviceFile {
-    fn from(element:&'static Element) -> Self {
-        AvrDeviceFile{
-            variants: Box::leak(find_childs(find_child(element,"variants").unwrap(),"variant").into_iter().map(|x| {Variant::from(x)}).collect::<Vec<Variant>>().into_boxed_slice()),
-            devices: find_child(find_child(element,"devices").unwrap(),"device").map(|f| Device::from(f)).unwrap(),
-            modules: Box::leak(find_childs(find_child(element,"modules").unwrap(),"module").into_iter().map(|x| {Module::from(x)}).collect::<Vec<Module>>().into_boxed_slice()),
-            pinouts: match find_child(element,"pinouts").map(|x| find_childs(x,"pinout").into_iter().map(|x| {Pinout::from(x)}).collect::<Vec<Pinout>>().into_boxed_slice()) {
-                None => {None}
-                Some(x) => {Some(Box::leak(x))}
-            },
+impl TryFrom<&'static Element> for AvrDeviceFile {
+    type Error = ParseError;
+    fn try_from(element:&'static Element) -> Result<Self,ParseError> {
+        let devices = child(element,"devices")?;
+        let device = child(devices,"device").map_err(|e| e.within(element))?;
+        let modules = child(element,"modules")?;
+        Ok(AvrDeviceFile{
+            variants: parse_list(element,"variants","variant")?,
+            devices: Device::try_from(device).map_err(|e| e.within(devices).within(element))?,
+            modules: parse_childs(modules,"module").map_err(|e| e.within(element))?,
+            pinouts: find_child(element,"pinouts").map(|x| parse_childs(x,"pinout")).transpose().map_err(|e| e.within(element))?,
+        })
+    }
+}
+impl AvrDeviceFile {
+    /// one byte registers of the instances in data space by their data address, 16 bit registers
+    /// are split into their L and H halves; AVRxt offsets are relative to the instance
+    pub fn register_map(&'static self) -> HashMap<u64,&'static Register>{
+        let mut reg_map = HashMap::<u64,&'static Register>::new();
+        let at = |x:&'static Register, offset:u64, suffix:&str| -> &'static Register {
+            if offset == x.offset && suffix.is_empty() {
+                return x;
+            }
+            Box::leak(Box::new(Register {
+                name: match suffix.is_empty() {
+                    true => x.name,
+                    false => &*(Box::leak(Box::new(x.name.to_owned() + suffix))),
+                },
+                offset,
+                size: 1,
+                ..x.clone()
+            }))
+        };
+        for module in self.devices.peripherals {
+            let Some(registers) = self.modules.iter().find(|x| x.name == module.name) else {
+                continue;
+            };
+            for group in module.instances.iter().filter_map(|x| x.register_group.as_ref()) {
+                if group.address_space != "data" {
+                    continue;
+                }
+                let Some(x1) = registers.register_group.iter().find(|x| x.name == group.name_in_module) else {
+                    continue;
+                };
+                x1.register.iter().for_each(|x2| {
+                    let address = group.offset + x2.offset;
+                    match x2.size{
+                        1=>{
+                            reg_map.insert(address,at(x2,address,""));
+                        }
+                        2=>{
+                            reg_map.insert(address+1, at(x2,address+1,"H"));
+                            reg_map.insert(address, at(x2,address,"L"));
+                        }
+                        _=>{}
+                    }
+                })
+            }
         }
+        reg_map
+    }
+
+    pub fn data_space(&self) -> Option<&'static AddressSpace>{
+        self.devices.address_spaces.iter().find(|x| x.id == "data")
+    }
+
+    /// bytes of the register file mapped to the start of data space, 0 on AVRxt and reduced cores
+    pub fn mapped_registers(&self) -> u64{
+        self.data_space().and_then(|x| x.segment("regs")).map_or(0, |x| x.size)
+    }
+
+    /// internal sram in data space
+    pub fn ram(&self) -> Option<&'static MemorySegment>{
+        self.data_space()?.segment("ram")
+    }
+
+    /// eeprom segment, its own address space or mapped to data space on AVRxt
+    pub fn eeprom(&self) -> Option<&'static MemorySegment>{
+        self.devices.address_spaces.iter().find(|x| x.id == "eeprom")
+            .and_then(|x| x.segment("eeprom"))
+            .or_else(|| self.data_space()?.segment("eeprom"))
+    }
+
+    /// application flash, the first flash segment of the prog space
+    pub fn flash(&self) -> Option<&'static MemorySegment>{
+        self.devices.address_spaces.iter().find(|x| x.id == "prog")?.segment("flash")
+    }
+
+    /// bytes between the register file and the ram, i/o registers and the other mapped memories
+    pub fn io_size(&self) -> Option<u64>{
+        let end = match self.ram() {
+            Some(ram) => ram.start,
+            None => self.data_space()?.memory_segments.iter().map(|x| x.start + x.size).max()?,
+        };
+        Some(end.saturating_sub(self.mapped_registers()))
     }
 }
 impl Default for &'static AvrDeviceFile {
@@ -70,27 +70,45 @@ pub struct CommonRegisters{
     pub mcucr:CommonReg,
 }
 impl CommonRegisters{
+    /// finds the registers by name, only SREG is required; the stack pointer is SP on devices
+    /// with a one byte SP, EIND, RAMPx and MCUCR are missing on most devices
     pub fn init(atdf:&AvrDeviceFile,reg_map:&HashMap<u64,&'static Register>,data:&mut Vec<u8>)->Result<Self,anyhow::Error>{
         let mut s = Self::default();
-        let reg_list = s.get_reg_list();
-        reg_map
-            .iter()
-            .filter(|(_,value)|{ reg_list.iter().find(|x| {value.name.to_lowercase()==*x.to_lowercase()}).is_some()})
-            .map(|(_,register)| { //todo does not work
-                //warn!("{}",register.name);
-                let (_, v) = s.iter_mut().find(|(key,_)| {key.to_lowercase()==register.name.to_lowercase()}).ok_or_else(|| anyhow!("invalid register:{0}", register.name))?;
-                v.register = register;
-                Ok(())
-            }).collect::<Result<(),anyhow::Error>>()?;
-
+        for (key,value) in s.iter_mut(){
+            let register = Self::aliases(key).iter().find_map(|name| {
+                // the lowest address, a register can be listed by more than one module
+                reg_map.iter().filter(|(_,x)| x.name.eq_ignore_ascii_case(name)).min_by_key(|(address,_)| **address)
+            });
+            if let Some((_,register)) = register {
+                value.register = register;
+            }
+        }
+        if s.sreg.register.name.is_empty() {
+            return Err(anyhow!("missing register:SREG"));
+        }
         s.init_regs(atdf,data)?;
 
         Ok(s)
     }
-    fn get_reg_list(&mut self)->Vec<String>{
-        self.iter_mut().map(|(key,_)| {
-            key.to_uppercase()
-        }).collect()
+    /// register names of a field of `iter_mut`, the first one the device has is used
+    fn aliases(key:&str)->&'static [&'static str]{
+        match key {
+            "sreg"=>&["SREG"],
+            "eind"=>&["EIND"],
+            "spl"=>&["SPL","SP"],
+            "sph"=>&["SPH"],
+            "rampx"=>&["RAMPX"],
+            "rampy"=>&["RAMPY"],
+            "rampz"=>&["RAMPZ"],
+            "rampd"=>&["RAMPD"],
+            "mcucr"=>&["MCUCR"],
+            _=>&[],
+        }
+    }
+    /// the registers without the data memory `init_regs` pointed them to
+    pub fn detached(mut self)->Self{
+        self.iter_mut().for_each(|(_,x)| x.data = None);
+        self
     }
     pub fn init_regs(&mut self,atdf:&AvrDeviceFile, data:&mut Vec<u8>)->Result<(),anyhow::Error>{
         for (_,value)in self.iter_mut(){
@@ -98,10 +116,9 @@ impl CommonRegisters{
                 continue;
             }
             let addr = value.register.offset;
-            let register_count = atdf.devices.address_spaces.iter().find(|x| { x.id == "data" }).unwrap()
-                .memory_segments.iter().find(|x1| { x1.name == "REGISTERS" }).unwrap().size;
-            //warn!("a:{},B:{},c:{:?}",addr,register_count,value);
-            value.data = Some(data.get_mut((addr - register_count) as usize).ok_or(anyhow!(format!("invalid reg addr:{}",addr)))?);
+            // `data` starts after the register file where it is mapped to data space
+            let index = addr.checked_sub(atdf.mapped_registers()).ok_or(anyhow!(format!("invalid reg addr:{}",addr)))?;
+            value.data = Some(data.get_mut(index as usize).ok_or(anyhow!(format!("invalid reg addr:{}",addr)))?);
 
         }
         Ok(())
@@ -2,7 +2,7 @@ use std::str::FromStr;
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use xmltree::Element;
-use super::utils::find_childs;
+use super::parse_error::{attribute, number, optional_attribute, parse_childs, parsed_attribute, ParseError, ParseErrorKind};
 #[derive(Debug)]
 pub struct AddressSpace{
     pub memory_segments:&'static [MemorySegment],
@@ -12,19 +12,27 @@ pub struct AddressSpace{
     pub start:u64,
     pub size:u64,
 }
-impl From<&'static Element> for AddressSpace {
-    fn from(x:&'static Element) -> Self {
-        AddressSpace{
-            memory_segments: Box::leak(find_childs(x,"memory-segment").into_iter().map(|x| {MemorySegment::from(x)}).collect::<Vec<MemorySegment>>().into_boxed_slice()),
-            endianess: Endianess::from_str(&*x.attributes["endianness"]).expect(&*x.attributes["endianness"]),
-            name: &x.attributes["name"],
-            id: &x.attributes["id"],
-            start: match x.attributes["start"].starts_with("0x"){true=>{u64::from_str_radix(x.attributes["start"].strip_prefix("0x").unwrap(),16).unwrap()},false=>{x.attributes["start"].parse::<u64>().unwrap()},},
-            size: match x.attributes["size"].starts_with("0x"){true=>{u64::from_str_radix(x.attributes["size"].strip_prefix("0x").unwrap(),16).unwrap()},false=>{x.attributes["size"].parse::<u64>().unwrap()},},
-        }
+impl TryFrom<&'static Element> for AddressSpace {
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError> {
+        let endianness = attribute(x,"endianness")?;
+        Ok(AddressSpace{
+            memory_segments: parse_childs(x,"memory-segment")?,
+            endianess: Endianess::from_str(endianness).map_err(|_| ParseError::new(x, ParseErrorKind::InvalidValue{attribute:"endianness", value:endianness.to_string()}))?,
+            name: attribute(x,"name")?,
+            id: attribute(x,"id")?,
+            start: number(x,"start")?,
+            size: number(x,"size")?,
+        })
     }
 }
 
+impl AddressSpace{
+    /// first segment of the type, e.g. regs, io, ram or eeprom
+    pub fn segment(&'static self, data_type:&str) -> Option<&'static MemorySegment>{
+        self.memory_segments.iter().find(|x| x.data_type == data_type)
+    }
+}
 #[derive(Debug)]
 pub enum Endianess{
     Big,
@@ -51,18 +59,19 @@ pub struct MemorySegment{
     pub exec:Option<bool>,
     pub external:Option<bool>,
 }
-impl From<&'static Element> for MemorySegment {
-    fn from(x:&'static Element) -> Self {
-        MemorySegment{
-            start: match x.attributes["start"].starts_with("0x"){true=>{u64::from_str_radix(x.attributes["start"].strip_prefix("0x").unwrap(),16).unwrap()},false=>{x.attributes["start"].parse::<u64>().unwrap()},},
-            size: match x.attributes["size"].starts_with("0x"){true=>{u64::from_str_radix(x.attributes["size"].strip_prefix("0x").unwrap(),16).unwrap()},false=>{x.attributes["size"].parse::<u64>().unwrap()},},
-            data_type: &x.attributes["type"],
-            access: Access::option_from(x.attributes.get("rw").unwrap_or(&"err".to_string())),
-            page_size: x.attributes.get("pagesize").map(|t| {u64::from_str_radix(t.strip_prefix("0x").unwrap(), 16).unwrap()}),
-            exec: x.attributes.get("exec").map(|t| {match t.as_str() {"1"=>{true},"0"=>{false} _ => {panic!("err1")} }}),
-            external: x.attributes.get("external").map(|t| {match t.as_str() {"true"=>{true},"false"=>{false} _ => {panic!("err")} }}),
-            name:&x.attributes["name"],
-        }
+impl TryFrom<&'static Element> for MemorySegment {
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError> {
+        Ok(MemorySegment{
+            start: number(x,"start")?,
+            size: number(x,"size")?,
+            data_type: attribute(x,"type")?,
+            access: optional_attribute(x,"rw").and_then(Access::option_from),
+            page_size: parsed_attribute(x,"pagesize",super::parse_error::parse_number)?,
+            exec: parsed_attribute(x,"exec",|t| match t {"1"=>Some(true),"0"=>Some(false),_=>None})?,
+            external: parsed_attribute(x,"external",|t| match t {"true"=>Some(true),"false"=>Some(false),_=>None})?,
+            name:attribute(x,"name")?,
+        })
     }
 }
 #[derive(Debug)]
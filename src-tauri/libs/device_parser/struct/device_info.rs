@@ -3,7 +3,7 @@ use super::device_interface::Interface;
 use super::device_interrupt::Interrupt;
 use super::device_peripherals::Module;
 use super::device_property_group::PropertyGroup;
-use super::utils::{find_child, find_childs};
+use super::parse_error::{attribute, optional_attribute, parse_list, parsed_attribute, ParseError, ParseErrorKind};
 use quote::__private::TokenStream;
 use quote::{quote, ToTokens};
 use xmltree::Element;
@@ -19,18 +19,23 @@ pub struct Device{
     pub interfaces:&'static [Interface],
     pub propery_groups:&'static [PropertyGroup]
 }
-impl From<&'static Element> for Device{
-    fn from(x:&'static Element) -> Self{
-        Device{
-            name: &x.attributes["name"],
-            architecture: &x.attributes["architecture"],
-            family: &x.attributes["family"],
-            address_spaces: Box::leak(find_childs(find_child(x,"address-spaces").unwrap(),"address-space").into_iter().map(|x| {AddressSpace::from(x)}).collect::<Vec<AddressSpace>>().into_boxed_slice()),
-            peripherals: Box::leak(find_childs(find_child(x,"peripherals").unwrap(),"module").into_iter().map(|x| {Module::from(x)}).collect::<Vec<Module>>().into_boxed_slice()),
-            interrupts: Box::leak(find_childs(find_child(x,"interrupts").unwrap(),"interrupt").into_iter().map(|x| {Interrupt::from(x)}).collect::<Vec<Interrupt>>().into_boxed_slice()),
-            interfaces: Box::leak(find_childs(find_child(x,"interfaces").unwrap(),"interface").into_iter().map(|x| {Interface::from(x)}).collect::<Vec<Interface>>().into_boxed_slice()),
-            propery_groups: Box::leak(find_childs(find_child(x,"property-groups").unwrap(),"property-group").into_iter().map(|x| {PropertyGroup::from(x)}).collect::<Vec<PropertyGroup>>().into_boxed_slice()),
+impl TryFrom<&'static Element> for Device{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError>{
+        let address_spaces = parse_list(x,"address-spaces","address-space")?;
+        if address_spaces.is_empty() {
+            return Err(ParseError::new(x, ParseErrorKind::MissingElement("address-spaces")));
         }
+        Ok(Device{
+            name: attribute(x,"name")?,
+            architecture: attribute(x,"architecture")?,
+            family: optional_attribute(x,"family").unwrap_or_default(),
+            address_spaces,
+            peripherals: parse_list(x,"peripherals","module")?,
+            interrupts: parse_list(x,"interrupts","interrupt")?,
+            interfaces: parse_list(x,"interfaces","interface")?,
+            propery_groups: parse_list(x,"property-groups","property-group")?,
+        })
     }
 }
 
@@ -39,24 +44,26 @@ pub struct Variant{
     pub order_code: &'static str,
     pub temp_min:i64,
     pub temp_max:i64,
-    pub max_speed:i64,
+    pub max_speed:Option<i64>,
     pub pinout: Option<&'static str>,
     pub package: &'static str,
-    pub vcc_min:f64,
-    pub vcc_max:f64,
+    pub vcc_min:Option<f64>,
+    pub vcc_max:Option<f64>,
 }
-impl From<&'static Element> for Variant{
-    fn from(element:&'static Element) -> Self{
-        Variant{
-            order_code: &element.attributes["ordercode"],
-            temp_min: element.attributes["tempmin"].parse().unwrap(),
-            temp_max: element.attributes["tempmax"].parse().unwrap(),
-            max_speed: element.attributes["speedmax"].parse().unwrap(),
-            pinout: element.attributes.get("pinout").map(|x| x.as_str()),
-            package: &element.attributes["package"],
-            vcc_min: element.attributes["vccmin"].parse().unwrap(),
-            vcc_max: element.attributes["vccmax"].parse().unwrap(),
-        }
+impl TryFrom<&'static Element> for Variant{
+    type Error = ParseError;
+    fn try_from(element:&'static Element) -> Result<Self,ParseError>{
+        // the temperatures are informational, missing ones read as 0
+        Ok(Variant{
+            order_code: attribute(element,"ordercode")?,
+            temp_min: parsed_attribute(element,"tempmin",|x| x.parse().ok())?.unwrap_or_default(),
+            temp_max: parsed_attribute(element,"tempmax",|x| x.parse().ok())?.unwrap_or_default(),
+            max_speed: parsed_attribute(element,"speedmax",|x| x.parse().ok())?,
+            pinout: optional_attribute(element,"pinout"),
+            package: optional_attribute(element,"package").unwrap_or_default(),
+            vcc_min: parsed_attribute(element,"vccmin",|x| x.parse().ok())?,
+            vcc_max: parsed_attribute(element,"vccmax",|x| x.parse().ok())?,
+        })
     }
 }
 impl ToTokens for Device {
@@ -90,10 +97,19 @@ impl ToTokens for Variant {
         let order_code = &self.order_code;
         let temp_min = self.temp_min;
         let temp_max = self.temp_max;
-        let max_speed = self.max_speed;
         let package = &self.package;
-        let vcc_min = self.vcc_min;
-        let vcc_max = self.vcc_max;
+        let max_speed = match self.max_speed {
+            Some(x) => quote! { Some(#x) },
+            None => quote! { None },
+        };
+        let vcc_min = match self.vcc_min {
+            Some(x) => quote! { Some(#x) },
+            None => quote! { None },
+        };
+        let vcc_max = match self.vcc_max {
+            Some(x) => quote! { Some(#x) },
+            None => quote! { None },
+        };
 
         let pinout = match &self.pinout {
             Some(p) => quote! { Some(#p) },
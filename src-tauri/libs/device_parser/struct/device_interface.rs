@@ -1,17 +1,19 @@
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use xmltree::Element;
+use super::parse_error::{attribute, ParseError};
 #[derive(Debug)]
 pub struct Interface {
     pub name: &'static str,
     pub data_type: &'static str, //todo should be enum
 }
-impl From<&'static Element> for Interface {
-    fn from(x: &'static Element) -> Self {
-        Interface{
-            name: &x.attributes["name"],
-            data_type: &x.attributes["type"],
-        }
+impl TryFrom<&'static Element> for Interface {
+    type Error = ParseError;
+    fn try_from(x: &'static Element) -> Result<Self,ParseError> {
+        Ok(Interface{
+            name: attribute(x,"name")?,
+            data_type: attribute(x,"type")?,
+        })
     }
 }
 impl ToTokens for Interface {
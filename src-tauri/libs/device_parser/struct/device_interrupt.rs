@@ -1,20 +1,24 @@
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use xmltree::Element;
+use super::parse_error::{attribute, optional_attribute, parsed_attribute, ParseError, ParseErrorKind};
 
 #[derive(Debug)]
 pub struct Interrupt{
     pub index:i64,
     pub name:&'static str,
     pub caption:Option<&'static str>,
+    pub module_instance:Option<&'static str>, // AVRxt names are only unique per instance
 }
-impl From<&'static Element> for Interrupt{
-    fn from(x:&'static Element) -> Interrupt{
-        Interrupt{
-            index: x.attributes["index"].parse().unwrap(),
-            name: &x.attributes["name"],
-            caption: x.attributes.get("caption").map(|x| x.as_str()),
-        }
+impl TryFrom<&'static Element> for Interrupt{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Interrupt,ParseError>{
+        Ok(Interrupt{
+            index: parsed_attribute(x,"index",|x1| x1.parse().ok())?.ok_or_else(|| ParseError::new(x, ParseErrorKind::MissingAttribute("index")))?,
+            name: attribute(x,"name")?,
+            caption: optional_attribute(x,"caption"),
+            module_instance: optional_attribute(x,"module-instance"),
+        })
     }
 }
 impl ToTokens for Interrupt {
@@ -25,12 +29,17 @@ impl ToTokens for Interrupt {
             Some(c) => quote! { Some(#c) },
             None => quote! { None },
         };
+        let module_instance = match &self.module_instance {
+            Some(c) => quote! { Some(#c) },
+            None => quote! { None },
+        };
 
         tokens.extend(quote! {
             crate::r#struct::device_interrupt::Interrupt {
                 index: #index,
                 name: #name,
                 caption: #caption,
+                module_instance: #module_instance,
             }
         });
     }
@@ -1,7 +1,7 @@
 use quote::__private::TokenStream;
 use quote::{quote, ToTokens};
 use xmltree::Element;
-use super::utils::find_childs;
+use super::parse_error::{attribute, optional_attribute, parse_childs, ParseError};
 
 #[derive(Debug)]
 pub struct Pinout {
@@ -9,13 +9,14 @@ pub struct Pinout {
     pub caption: Option<&'static str>,
     pub pins: &'static [Pin],
 }
-impl From<&'static Element> for Pinout {
-    fn from(x: &'static Element) -> Self {
-        Pinout{
-            name: &x.attributes["name"],
-            caption: x.attributes.get("caption").map(|x| x.as_str()),
-            pins: Box::leak(find_childs(x,"pin").into_iter().map(|x1| Pin::from(x1)).collect::<Vec<Pin>>().into_boxed_slice()),
-        }
+impl TryFrom<&'static Element> for Pinout {
+    type Error = ParseError;
+    fn try_from(x: &'static Element) -> Result<Self,ParseError> {
+        Ok(Pinout{
+            name: attribute(x,"name")?,
+            caption: optional_attribute(x,"caption"),
+            pins: parse_childs(x,"pin")?,
+        })
     }
 }
 
@@ -24,12 +25,13 @@ pub struct Pin {
     pub position:&'static str,
     pub pad:&'static str,
 }
-impl From<&'static Element> for Pin {
-    fn from(x: &'static Element) -> Self {
-        Pin{ 
-            position: &x.attributes["position"],
-            pad:  &x.attributes["pad"]
-        }
+impl TryFrom<&'static Element> for Pin {
+    type Error = ParseError;
+    fn try_from(x: &'static Element) -> Result<Self,ParseError> {
+        Ok(Pin{
+            position: attribute(x,"position")?,
+            pad: attribute(x,"pad")?,
+        })
     }
 }
 impl ToTokens for Pinout {
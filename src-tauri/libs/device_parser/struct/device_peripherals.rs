@@ -1,37 +1,41 @@
 use xmltree::Element;
-use super::utils::{find_child, find_childs};
+use super::utils::find_child;
+use super::parse_error::{attribute, number, optional_attribute, parse_childs, parsed_attribute, ParseError};
 #[derive(Debug)]
 pub struct Module{
     pub name: &'static str,
     pub instances:&'static [Instance]
     
 }
-impl From<&'static Element> for Module {
-    fn from(x:&'static Element) -> Self {
-        Module{ 
-            name: &x.attributes["name"],
-            instances: Box::leak(find_childs(x,"memory-segment").into_iter().map(|x| {Instance::from(x)}).collect::<Vec<Instance>>().into_boxed_slice()),
-        }
+impl TryFrom<&'static Element> for Module {
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError> {
+        Ok(Module{
+            name: attribute(x,"name")?,
+            instances: parse_childs(x,"instance")?,
+        })
     }
 }
 
 #[derive(Debug)]
 pub struct Instance{
     pub name: &'static str,
-    pub caption: &'static str,
-    pub register_group: RegisterGroup,
+    pub caption: Option<&'static str>,
+    pub register_group: Option<RegisterGroup>, // missing on instances without registers
     pub signals:Option<&'static [Signal]>,
     pub parameters:Option<&'static [Param]>
 }
-impl From<&'static Element> for Instance {
-    fn from(x:&'static Element) -> Self {
-        Instance{
-            name: &x.attributes["name"],
-            caption: &x.attributes["caption"],
-            register_group: RegisterGroup::from(find_child(x, "register-group").unwrap()),
-            signals: Some(Box::leak(find_childs(find_child(x,"signals").unwrap(),"signal").into_iter().map(|x| {Signal::from(x)}).collect::<Vec<Signal>>().into_boxed_slice())),
-            parameters: Some(Box::leak(find_childs(find_child(x,"parameters").unwrap(),"parameter").into_iter().map(|x| {Param::from(x)}).collect::<Vec<Param>>().into_boxed_slice())),
-        }
+impl TryFrom<&'static Element> for Instance {
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError> {
+        let within = |e:ParseError| e.within(x);
+        Ok(Instance{
+            name: attribute(x,"name")?,
+            caption: optional_attribute(x,"caption"),
+            register_group: find_child(x, "register-group").map(RegisterGroup::try_from).transpose().map_err(within)?,
+            signals: find_child(x,"signals").map(|x1| parse_childs(x1,"signal")).transpose().map_err(within)?,
+            parameters: find_child(x,"parameters").map(|x1| parse_childs(x1,"param")).transpose().map_err(within)?,
+        })
     }
 }
 #[derive(Debug)]
@@ -40,35 +44,38 @@ pub struct RegisterGroup{
     pub name_in_module: &'static str,
     pub offset: u64,
     pub address_space:&'static str,
-    pub caption: &'static str,
+    pub caption: Option<&'static str>,
 }
-impl From<&'static Element> for RegisterGroup {
-    fn from(x:&'static Element) -> Self {
-        RegisterGroup{
-            name: &x.attributes["name"],
-            name_in_module: &x.attributes["name-in-module"],
-            offset: u64::from_str_radix(x.attributes["offset"].strip_prefix("0x").unwrap(), 16).unwrap(),
-            address_space: &x.attributes["address-space"],
-            caption: &x.attributes["caption"],
-        }
+impl TryFrom<&'static Element> for RegisterGroup {
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError> {
+        let name = attribute(x,"name")?;
+        Ok(RegisterGroup{
+            name,
+            name_in_module: optional_attribute(x,"name-in-module").unwrap_or(name),
+            offset: number(x,"offset")?,
+            address_space: attribute(x,"address-space")?,
+            caption: optional_attribute(x,"caption"),
+        })
     }
 }
 
 #[derive(Debug)]
 pub struct Signal{
     pub group: &'static str,
-    pub function: &'static str, //todo should be enum
+    pub function: Option<&'static str>, //todo should be enum
     pub pad:&'static str,
     pub index:Option<i64>,
 }
-impl From<&'static Element> for Signal {
-    fn from(x:&'static Element) -> Self {
-        Signal{
-            group: &x.attributes["group"],
-            function: &x.attributes["function"],
-            pad: &x.attributes["pad"],
-            index: Some(x.attributes["index"].parse().unwrap()),
-        }
+impl TryFrom<&'static Element> for Signal {
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError> {
+        Ok(Signal{
+            group: attribute(x,"group")?,
+            function: optional_attribute(x,"function"),
+            pad: attribute(x,"pad")?,
+            index: parsed_attribute(x,"index",|x1| x1.parse().ok())?,
+        })
     }
 }
 
@@ -77,12 +84,13 @@ pub struct Param{
     pub name: &'static str,
     pub value: &'static str,
 }
-impl From<&'static Element> for Param {
-    fn from(x:&'static Element) -> Self {
-        Param{ 
-            name: &x.attributes["name"],
-            value: &x.attributes["value"]
-        }
+impl TryFrom<&'static Element> for Param {
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError> {
+        Ok(Param{
+            name: attribute(x,"name")?,
+            value: attribute(x,"value")?,
+        })
     }
 }
 use quote::{quote, ToTokens};
@@ -106,8 +114,14 @@ impl ToTokens for Module {
 impl ToTokens for Instance {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let name = &self.name;
-        let caption = &self.caption;
-        let register_group = &self.register_group;
+        let caption = match &self.caption {
+            Some(c) => quote! { Some(#c) },
+            None => quote! { None },
+        };
+        let register_group = match &self.register_group {
+            Some(r) => quote! { Some(#r) },
+            None => quote! { None },
+        };
 
         let signals = match &self.signals {
             Some(s) => quote! { Some(&[#( #s ),*]) },
@@ -136,7 +150,10 @@ impl ToTokens for RegisterGroup {
         let name_in_module = &self.name_in_module;
         let offset = self.offset;
         let address_space = &self.address_space;
-        let caption = &self.caption;
+        let caption = match &self.caption {
+            Some(c) => quote! { Some(#c) },
+            None => quote! { None },
+        };
 
         tokens.extend(quote! {
             crate::r#struct::device_peripherals::RegisterGroup {
@@ -153,7 +170,10 @@ impl ToTokens for RegisterGroup {
 impl ToTokens for Signal {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let group = &self.group;
-        let function = &self.function;
+        let function = match &self.function {
+            Some(f) => quote!{ Some(#f) },
+            None => quote!{ None },
+        };
         let pad = &self.pad;
         let index = match self.index {
             Some(i) => quote!{ Some(#i) },
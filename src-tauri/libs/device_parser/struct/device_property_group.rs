@@ -2,18 +2,19 @@ use std::str::FromStr;
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
 use xmltree::Element;
-use super::utils::find_childs;
+use super::parse_error::{attribute, parse_childs, ParseError, ParseErrorKind};
 #[derive(Debug)]
 pub struct PropertyGroup{
     pub name: &'static str,
     pub properties: &'static [Property],
 }
-impl From<&'static Element> for PropertyGroup{
-    fn from(element: &'static Element) -> PropertyGroup{
-        PropertyGroup{
-            name: &element.attributes["name"],
-            properties: Box::leak(find_childs(element,"property").into_iter().map(|x| {Property::from(x)}).collect::<Vec<Property>>().into_boxed_slice()),
-        }
+impl TryFrom<&'static Element> for PropertyGroup{
+    type Error = ParseError;
+    fn try_from(element: &'static Element) -> Result<PropertyGroup,ParseError>{
+        Ok(PropertyGroup{
+            name: attribute(element,"name")?,
+            properties: parse_childs(element,"property")?,
+        })
     }
 }
 #[derive(Debug)]
@@ -21,12 +22,14 @@ pub struct Property {
     pub name: &'static str,
     pub value: PropertyValue,
 }
-impl From<&'static Element> for Property{
-    fn from(x: &'static Element) -> Self{
-        Property{
-            name: &x.attributes["name"],
-            value: PropertyValue::from(&x.attributes["value"]),
-        }
+impl TryFrom<&'static Element> for Property{
+    type Error = ParseError;
+    fn try_from(x: &'static Element) -> Result<Self,ParseError>{
+        let value = x.attributes.get("value").ok_or_else(|| ParseError::new(x, ParseErrorKind::MissingAttribute("value")))?;
+        Ok(Property{
+            name: attribute(x,"name")?,
+            value: PropertyValue::from(value),
+        })
     }
 }
 
@@ -41,7 +44,11 @@ impl From<&'static String> for PropertyValue{
         match x.strip_prefix("0x") { 
             Some(v) => {match u64::from_str_radix(v,16) {
                 Ok(v) => PropertyValue::Number(v),
-                Err(_)=> PropertyValue::Vec(Box::leak(x.split(" ").into_iter().map(|x| u64::from_str_radix(x.strip_prefix("0x").unwrap(),16).unwrap()).collect::<Vec<_>>().into_boxed_slice()))
+                // lists like "0x1e 0x95", anything else is kept as text
+                Err(_)=> match x.split(" ").map(|x| u64::from_str_radix(x.strip_prefix("0x")?,16).ok()).collect::<Option<Vec<_>>>() {
+                    Some(v) => PropertyValue::Vec(Box::leak(v.into_boxed_slice())),
+                    None => PropertyValue::String(x.as_str()),
+                }
             }},
             None => match u64::from_str(x){
                 Ok(v) => PropertyValue::Number(v),
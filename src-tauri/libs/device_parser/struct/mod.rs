@@ -10,3 +10,4 @@ pub mod device_property_group;
 pub mod avr_device_file;
 pub mod module;
 pub mod common_registers;
+pub mod parse_error;
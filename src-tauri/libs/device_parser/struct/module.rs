@@ -3,7 +3,7 @@ use quote::{quote, ToTokens};
 use xmltree::Element;
 use serde::Serialize;
 use crate::r#struct::device_property_group::PropertyValue;
-use super::utils::find_childs;
+use super::parse_error::{attribute, number, optional_attribute, parse_childs, ParseError, ParseErrorKind};
 
 #[derive(Debug)]
 pub struct Module{
@@ -12,14 +12,15 @@ pub struct Module{
     pub register_group: &'static[ModuleRegisterGroup],
     pub value_grop: &'static[ValueGroup]
 }
-impl From<&'static Element> for Module{
-    fn from(x:&'static Element) -> Self{
-        Module{
-            caption: x.attributes.get("caption").map(|t| t.as_str()),
-            name: &x.attributes["name"],
-            register_group: Box::leak(find_childs(x,"register-group").into_iter().map(|x1| {ModuleRegisterGroup::from(x1)}).collect::<Vec<ModuleRegisterGroup>>().into_boxed_slice()),
-            value_grop: Box::leak(find_childs(x,"value-group").into_iter().map(|x1| {ValueGroup::from(x1)}).collect::<Vec<ValueGroup>>().into_boxed_slice()),
-        }
+impl TryFrom<&'static Element> for Module{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError>{
+        Ok(Module{
+            caption: optional_attribute(x,"caption"),
+            name: attribute(x,"name")?,
+            register_group: parse_childs(x,"register-group")?,
+            value_grop: parse_childs(x,"value-group")?,
+        })
     }
 }
 #[derive(Debug)]
@@ -28,13 +29,14 @@ pub struct ModuleRegisterGroup{
     pub name: &'static str,
     pub register: &'static[Register]
 }
-impl From<&'static Element> for ModuleRegisterGroup{
-    fn from(x:&'static Element) -> Self{
-        ModuleRegisterGroup{
-            caption: x.attributes.get("caption").map(|x1| x1.as_str()),
-            name: &x.attributes["name"],
-            register: Box::leak(find_childs(x,"register").into_iter().map(|x1| {Register::from(x1)}).collect::<Vec<Register>>().into_boxed_slice()),
-        }
+impl TryFrom<&'static Element> for ModuleRegisterGroup{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError>{
+        Ok(ModuleRegisterGroup{
+            caption: optional_attribute(x,"caption"),
+            name: attribute(x,"name")?,
+            register: parse_childs(x,"register")?,
+        })
     }
 }
 #[derive(Debug,Default,Serialize,Clone)]
@@ -44,19 +46,22 @@ pub struct Register{
     pub offset: u64,
     pub size: u64,
     pub initval:u64,
+    pub rw:Option<&'static str>,
     pub bitfields:Option<&'static[BitField]>,
 }
 
-impl From<&'static Element> for Register{
-    fn from(x:&'static Element) -> Self{
-        Register{
-            caption:x.attributes.get("caption").map(|x1| x1.as_str()),
-            name: &x.attributes["name"],
-            offset: u64::from_str_radix(x.attributes["offset"].strip_prefix("0x").unwrap(), 16).unwrap(),
-            size: x.attributes["size"].parse().unwrap(),
-            initval: u64::from_str_radix(x.attributes["offset"].strip_prefix("0x").unwrap(), 16).unwrap(),
-            bitfields: Some(Box::leak(find_childs(x,"bitfield").into_iter().map(|x1| {BitField::from(x1)}).collect::<Vec<BitField>>().into_boxed_slice())),
-        }
+impl TryFrom<&'static Element> for Register{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError>{
+        Ok(Register{
+            caption:optional_attribute(x,"caption"),
+            name: attribute(x,"name")?,
+            offset: number(x,"offset")?,
+            size: number(x,"size")?,
+            initval: super::parse_error::parsed_attribute(x,"initval",super::parse_error::parse_number)?.unwrap_or(0),
+            rw: optional_attribute(x,"rw"),
+            bitfields: Some(parse_childs(x,"bitfield")?),
+        })
     }
 }
 #[derive(Debug,Serialize,Clone,Default)]
@@ -66,15 +71,18 @@ pub struct BitField{
     pub mask: u64,
     pub name: &'static str,
     pub values:Option<&'static str>,
+    pub rw:Option<&'static str>,
 }
-impl From<&'static Element> for BitField{
-    fn from(x:&'static Element) -> Self{
-        BitField{
-            caption: x.attributes.get("caption").map(|x1| x1.as_str()),
-            mask: u64::from_str_radix(x.attributes["mask"].strip_prefix("0x").unwrap(), 16).unwrap(),
-            name: &x.attributes["name"],
-            values: x.attributes.get("name").map(|x1| x1.as_str()),
-        }
+impl TryFrom<&'static Element> for BitField{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError>{
+        Ok(BitField{
+            caption: optional_attribute(x,"caption"),
+            mask: number(x,"mask")?,
+            name: attribute(x,"name")?,
+            values: optional_attribute(x,"values"),
+            rw: optional_attribute(x,"rw"),
+        })
     }
 }
 #[derive(Debug)]
@@ -82,12 +90,13 @@ pub struct ValueGroup{
     pub name: &'static str,
     pub values: &'static[Value]
 }
-impl From<&'static Element> for ValueGroup{
-    fn from(x:&'static Element) -> Self{
-        ValueGroup{ 
-            name: &x.attributes["name"],
-            values: Box::leak(find_childs(x,"value").into_iter().map(|x1| {Value::from(x1)}).collect::<Vec<Value>>().into_boxed_slice())
-        }
+impl TryFrom<&'static Element> for ValueGroup{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError>{
+        Ok(ValueGroup{
+            name: attribute(x,"name")?,
+            values: parse_childs(x,"value")?,
+        })
     }
 }
 #[derive(Debug)]
@@ -96,15 +105,64 @@ pub struct Value{
     pub name: &'static str,
     pub value: PropertyValue,
 }
-impl From<&'static Element> for Value{
-    fn from(x:&'static Element) -> Self{
-        Value{
-            caption: &x.attributes["caption"],
-            name: &x.attributes["name"],
-            value: PropertyValue::from(&x.attributes["value"]),
-        }
+impl TryFrom<&'static Element> for Value{
+    type Error = ParseError;
+    fn try_from(x:&'static Element) -> Result<Self,ParseError>{
+        let value = x.attributes.get("value").ok_or_else(|| ParseError::new(x, ParseErrorKind::MissingAttribute("value")))?;
+        let name = attribute(x,"name")?;
+        Ok(Value{
+            caption: optional_attribute(x,"caption").unwrap_or(name),
+            name,
+            value: PropertyValue::from(value),
+        })
     }
 }
+/// a bitfield of a register value with the meaning from its value group
+#[derive(Debug,Serialize,Clone,PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedField{
+    pub name: &'static str,
+    pub value: u64,
+    pub caption: Option<&'static str>,
+}
+
+impl Module{
+    /// value group a bitfield refers to with its `values` attribute
+    pub fn value_group(&self, name:&str) -> Option<&'static ValueGroup>{
+        self.value_grop.iter().find(|x| x.name == name)
+    }
+    /// every bitfield of `register` in `value`, named by the value group where there is one
+    pub fn decode(&self, register:&Register, value:u64) -> Vec<DecodedField>{
+        register.bitfields.unwrap_or_default().iter().map(|x| {
+            let field = x.extract(value);
+            DecodedField{
+                name: x.name,
+                value: field,
+                caption: x.values.and_then(|x1| self.value_group(x1)).and_then(|x1| x1.decode(field)).map(|x1| x1.caption),
+            }
+        }).collect()
+    }
+}
+impl BitField{
+    /// bits of the field in `register` packed down to bit 0, masks may have gaps like WDP 0x27
+    pub fn extract(&self, register:u64) -> u64{
+        (0..64).filter(|x| self.mask >> x & 1 == 1).enumerate().fold(0, |value, (bit, x)| {
+            value | ((register >> x & 1) << bit)
+        })
+    }
+    /// `register` with the field set to `value`, the inverse of `extract`
+    pub fn insert(&self, register:u64, value:u64) -> u64{
+        (0..64).filter(|x| self.mask >> x & 1 == 1).enumerate().fold(register & !self.mask, |register, (bit, x)| {
+            register | ((value >> bit & 1) << x)
+        })
+    }
+}
+impl ValueGroup{
+    pub fn decode(&self, value:u64) -> Option<&'static Value>{
+        self.values.iter().find(|x| matches!(x.value, PropertyValue::Number(n) if n == value))
+    }
+}
+
 impl ToTokens for Module {
     fn to_tokens(&self, tokens: &mut TokenStream) {
         let name = &self.name;
@@ -158,6 +216,10 @@ impl ToTokens for Register {
             Some(c) => quote! { Some(#c) },
             None => quote! { None },
         };
+        let rw = match &self.rw {
+            Some(r) => quote! { Some(#r) },
+            None => quote! { None },
+        };
         let bitfields = match &self.bitfields {
             Some(b) => quote! { Some(&[#( #b ),*]) },
             None => quote! { None },
@@ -170,6 +232,7 @@ impl ToTokens for Register {
                 offset: #offset,
                 size: #size,
                 initval: #initval,
+                rw: #rw,
                 bitfields: #bitfields,
             }
         });
@@ -188,6 +251,10 @@ impl ToTokens for BitField {
             Some(v) => quote! { Some(#v) },
             None => quote! { None },
         };
+        let rw = match &self.rw {
+            Some(r) => quote! { Some(#r) },
+            None => quote! { None },
+        };
 
         tokens.extend(quote! {
             crate::r#struct::module::BitField {
@@ -195,6 +262,7 @@ impl ToTokens for BitField {
                 mask: #mask,
                 name: #name,
                 values: #values,
+                rw: #rw,
             }
         });
     }
@@ -0,0 +1,102 @@
+use std::fmt::{Display, Formatter};
+use xmltree::Element;
+use super::utils::{find_child, find_childs};
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ParseErrorKind{
+    MissingAttribute(&'static str),
+    MissingElement(&'static str),
+    InvalidValue{attribute:&'static str, value:String},
+}
+
+/// why an element of a device file could not be parsed and where it is
+#[derive(Debug,Clone,PartialEq)]
+pub struct ParseError{
+    pub file: Option<String>,
+    pub path: Vec<String>, // outermost element first, e.g. module[PORT]
+    pub kind: ParseErrorKind,
+}
+
+impl ParseError{
+    pub fn new(element:&Element, kind:ParseErrorKind) -> Self{
+        ParseError{ file: None, path: vec![segment(element)], kind }
+    }
+    /// prepends the parent element to the path
+    pub fn within(mut self, element:&Element) -> Self{
+        self.path.insert(0, segment(element));
+        self
+    }
+    pub fn in_file(mut self, file:&str) -> Self{
+        self.file = Some(file.to_string());
+        self
+    }
+}
+
+impl Display for ParseError{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+        write!(f, "{}: ", self.path.join("/"))?;
+        match &self.kind {
+            ParseErrorKind::MissingAttribute(x) => write!(f, "missing attribute {}", x),
+            ParseErrorKind::MissingElement(x) => write!(f, "missing element {}", x),
+            ParseErrorKind::InvalidValue{attribute, value} => write!(f, "invalid value for {}: {}", attribute, value),
+        }
+    }
+}
+
+impl std::error::Error for ParseError{}
+
+/// element name with its name attribute, e.g. register[PORTB]
+fn segment(element:&Element) -> String{
+    match element.attributes.get("name") {
+        Some(name) => format!("{}[{}]", element.name, name),
+        None => element.name.clone(),
+    }
+}
+
+pub fn attribute(x:&'static Element, name:&'static str) -> Result<&'static str,ParseError>{
+    x.attributes.get(name).map(|x1| x1.as_str()).ok_or_else(|| ParseError::new(x, ParseErrorKind::MissingAttribute(name)))
+}
+
+pub fn optional_attribute(x:&'static Element, name:&'static str) -> Option<&'static str>{
+    x.attributes.get(name).map(|x1| x1.as_str())
+}
+
+/// attribute parsed with `parse`, an error names the attribute and its value
+pub fn parsed_attribute<T>(x:&'static Element, name:&'static str, parse:impl Fn(&str)->Option<T>) -> Result<Option<T>,ParseError>{
+    optional_attribute(x, name).map(|value| {
+        parse(value).ok_or_else(|| ParseError::new(x, ParseErrorKind::InvalidValue{attribute:name, value:value.to_string()}))
+    }).transpose()
+}
+
+/// hex with a 0x prefix or decimal
+pub fn parse_number(value:&str) -> Option<u64>{
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+pub fn number(x:&'static Element, name:&'static str) -> Result<u64,ParseError>{
+    parsed_attribute(x, name, parse_number)?.ok_or_else(|| ParseError::new(x, ParseErrorKind::MissingAttribute(name)))
+}
+
+pub fn child(x:&'static Element, name:&'static str) -> Result<&'static Element,ParseError>{
+    find_child(x, name).ok_or_else(|| ParseError::new(x, ParseErrorKind::MissingElement(name)))
+}
+
+/// every child `name` converted to `T`, errors get `x` prepended to their path
+pub fn parse_childs<T:TryFrom<&'static Element,Error=ParseError>>(x:&'static Element, name:&str) -> Result<&'static [T],ParseError>{
+    let items = find_childs(x, name).into_iter().map(T::try_from).collect::<Result<Vec<T>,ParseError>>().map_err(|e| e.within(x))?;
+    Ok(Box::leak(items.into_boxed_slice()))
+}
+
+/// children `item` of the optional container `list`, empty without it
+pub fn parse_list<T:TryFrom<&'static Element,Error=ParseError>>(x:&'static Element, list:&str, item:&str) -> Result<&'static [T],ParseError>{
+    match find_child(x, list) {
+        Some(list) => parse_childs(list, item).map_err(|e| e.within(x)),
+        None => Ok(&[]),
+    }
+}
@@ -1,42 +1,115 @@
 use crate::project::{ProjectState, get_project};
 use crate::sim::controller::{Action, Controller};
+use crate::sim::memory::MemorySpace;
+use crate::sim::peripherals::fuse::{FuseField, Fuses};
 use crate::sim::parser::parse_hex;
+use crate::sim::peripherals::analog::{AnalogNet, AnalogSource};
+use crate::sim::peripherals::spi::SpiDeviceKind;
+use crate::sim::peripherals::twi::I2cDeviceKind;
 use crate::wrap_anyhow;
+use anyhow::anyhow;
+use device_parser::capabilities::{Capabilities, CapabilityFilter};
 use opcode_gen::RawInst;
 use tauri::ipc::Invoke;
 
 pub(crate) const HANDLER: fn(Invoke) -> bool = tauri::generate_handler![
     get_instruction_list,
     get_mcu_list,
+    import_device,
+    get_device_errors,
+    get_capabilities,
+    filter_mcu_list,
     set_mcu,
     set_freq,
     get_project_info,
+    get_fuses,
+    set_fuse,
+    reset_fuses,
     menu_new,
     menu_open,
     menu_import,
     menu_close,
     menu_save,
-    sim_action
+    sim_action,
+    sim_set_register,
+    sim_set_flag,
+    sim_set_sp,
+    sim_set_pc,
+    sim_poke,
+    sim_read_memory,
+    sim_io_view,
+    sim_peripheral_support,
+    sim_pinout,
+    sim_reserved_warnings,
+    sim_drive_pin,
+    sim_drive_pin_at,
+    sim_spi_attach,
+    sim_spi_transfer,
+    sim_i2c_attach,
+    sim_set_analog,
+    sim_load_analog_csv
 ];
 
 wrap_anyhow!(get_instruction_list() -> Vec<RawInst> {
     Ok(Vec::from(opcode_gen::OPCODE_LIST))
 });
 
-wrap_anyhow!(get_mcu_list() -> &'static [&'static str] {
+wrap_anyhow!(get_mcu_list() -> Vec<&'static str> {
     Ok(device_parser::get_mcu_list())
 });
 
-wrap_anyhow!(set_mcu(mcu:String) ->(){
+// copies the file into the user device directory, the device is available right away
+wrap_anyhow!(import_device(file:String) -> &'static str {
+    let xml = std::fs::read_to_string(&file)?;
+    let atdf = device_parser::loader::parse(&xml)?;
+    let name = device_parser::loader::name(atdf)?;
+    let dir = crate::user_device_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(format!("{}.atdf", name)), xml)?;
+    device_parser::loader::register(atdf)
+});
+
+// device files that could not be parsed, with the element and attribute at fault
+wrap_anyhow!(get_device_errors() -> Vec<(String, String)> {
+    Ok(device_parser::get_parse_errors())
+});
 
-    get_project()?.get_state()?.mcu = mcu;
-    get_project()?.reload_instruction_list()?;
-    get_project()?.save()?;
+wrap_anyhow!(get_capabilities(mcu:String) -> Capabilities {
+    device_parser::get_capabilities(&mcu).ok_or(anyhow!("invalid mcu:{}", mcu))
+});
+
+wrap_anyhow!(filter_mcu_list(filter:CapabilityFilter) -> Vec<&'static str> {
+    Ok(device_parser::filter_mcu_list(&filter))
+});
+
+// the project clock has to be within the maximum of the new device
+wrap_anyhow!(set_mcu(mcu:String) ->(){
+    let capabilities = device_parser::get_capabilities(&mcu).ok_or(anyhow!("invalid mcu:{}", mcu))?;
+    device_parser::get_common_registers(&mcu).ok_or(anyhow!("mcu not supported:{}", mcu))?;
+    let mut project = get_project()?;
+    let state = project.get_state()?;
+    // 0 until a clock is set, the simulator then runs at its default
+    if state.freq != 0 {
+        capabilities.check_frequency(state.freq)?;
+    }
+    // fuse bytes of the old part mean nothing to the new one, its defaults apply
+    if state.mcu != mcu {
+        state.fuses.clear();
+        state.lockbits.clear();
+    }
+    state.mcu = mcu;
+    project.reload_instruction_list()?;
+    project.save()?;
     Ok(())
 });
 wrap_anyhow!(set_freq(freq:u32)->(){
-    get_project()?.get_state()?.freq = freq;
-    get_project()?.save()?;
+    let mut project = get_project()?;
+    let state = project.get_state()?;
+    if let Some(capabilities) = device_parser::get_capabilities(&state.mcu) {
+        capabilities.check_frequency(freq)?;
+    }
+    state.freq = freq;
+    project.save()?;
     Ok(())
 });
 
@@ -44,6 +117,35 @@ wrap_anyhow!(get_project_info() -> ProjectState {
     get_project()?.get_state().cloned()
 });
 
+/// fuses of the project, decoded with the FUSE and LOCKBIT modules of its mcu
+fn project_fuses(state: &ProjectState) -> crate::error::Result<Fuses> {
+    let atdf = device_parser::get_device(&state.mcu).ok_or(anyhow!("invalid mcu"))?;
+    Ok(Fuses::init(atdf, &state.fuses, &state.lockbits))
+}
+
+wrap_anyhow!(get_fuses() -> Vec<FuseField> {
+    Ok(project_fuses(get_project()?.get_state()?)?.fields())
+});
+
+// takes effect when the simulation is started again
+wrap_anyhow!(set_fuse(name:String, value:String) ->(){
+    let mut project = get_project()?;
+    let state = project.get_state()?;
+    let mut fuses = project_fuses(state)?;
+    fuses.set(&name, &value)?;
+    state.fuses = fuses.bytes;
+    state.lockbits = fuses.lock_bits;
+    project.save()
+});
+
+wrap_anyhow!(reset_fuses() ->(){
+    let mut project = get_project()?;
+    let state = project.get_state()?;
+    state.fuses.clear();
+    state.lockbits.clear();
+    project.save()
+});
+
 wrap_anyhow!(menu_new(file:String)->(){
     get_project()?.create(&*file.to_string())
 });
@@ -84,3 +186,71 @@ wrap_anyhow!(menu_save()->(){
 wrap_anyhow!(async sim_action(action:Action)->(){
    Controller::do_action_and_wait(action).await
 });
+
+wrap_anyhow!(async sim_set_register(index:u8, value:u8)->(){
+   Controller::do_action_and_wait(Action::SetRegister(index, value)).await
+});
+
+wrap_anyhow!(async sim_set_flag(bit:u8, value:bool)->(){
+   Controller::do_action_and_wait(Action::SetFlag(bit, value)).await
+});
+
+wrap_anyhow!(async sim_set_sp(sp:u16)->(){
+   Controller::do_action_and_wait(Action::SetSp(sp)).await
+});
+
+wrap_anyhow!(async sim_set_pc(pc:u32)->(){
+   Controller::do_action_and_wait(Action::SetPc(pc)).await
+});
+
+wrap_anyhow!(async sim_poke(space:MemorySpace, address:u32, value:u8)->(){
+   Controller::do_action_and_wait(Action::Poke(space, address, value)).await
+});
+
+wrap_anyhow!(async sim_read_memory(space:MemorySpace, address:u32, len:u32)->(){
+   Controller::do_action_and_wait(Action::ReadMemory(space, address, len)).await
+});
+
+wrap_anyhow!(async sim_io_view()->(){
+   Controller::do_action_and_wait(Action::IoView).await
+});
+
+wrap_anyhow!(async sim_peripheral_support()->(){
+   Controller::do_action_and_wait(Action::PeripheralSupport).await
+});
+
+wrap_anyhow!(async sim_pinout()->(){
+   Controller::do_action_and_wait(Action::Pinout).await
+});
+
+wrap_anyhow!(async sim_reserved_warnings(enabled:bool)->(){
+   Controller::do_action_and_wait(Action::ReservedWarnings(enabled)).await
+});
+
+wrap_anyhow!(async sim_drive_pin(pad:String, level:Option<bool>)->(){
+   Controller::do_action_and_wait(Action::DrivePin(pad, level)).await
+});
+
+wrap_anyhow!(async sim_drive_pin_at(pad:String, level:Option<bool>, cycle:u64)->(){
+   Controller::do_action_and_wait(Action::DrivePinAt(pad, level, cycle)).await
+});
+
+wrap_anyhow!(async sim_spi_attach(spi:String, device:SpiDeviceKind, select:Option<String>)->(){
+   Controller::do_action_and_wait(Action::SpiAttach(spi, device, select)).await
+});
+
+wrap_anyhow!(async sim_spi_transfer(spi:String, mosi:u8)->(){
+   Controller::do_action_and_wait(Action::SpiTransfer(spi, mosi)).await
+});
+
+wrap_anyhow!(async sim_i2c_attach(twi:String, address:u8, device:I2cDeviceKind)->(){
+   Controller::do_action_and_wait(Action::I2cAttach(twi, address, device)).await
+});
+
+wrap_anyhow!(async sim_set_analog(net:AnalogNet, source:AnalogSource)->(){
+   Controller::do_action_and_wait(Action::SetAnalog(net, source)).await
+});
+
+wrap_anyhow!(async sim_load_analog_csv(net:AnalogNet, path:String)->(){
+   Controller::do_action_and_wait(Action::LoadAnalogCsv(net, path)).await
+});
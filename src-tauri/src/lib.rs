@@ -1,6 +1,7 @@
 use crate::sim::controller::Controller;
 use anyhow::anyhow;
 use error::Result;
+use std::path::PathBuf;
 use std::sync::{OnceLock};
 use std::time::Duration;
 use tauri::{AppHandle, Manager};
@@ -20,6 +21,10 @@ pub fn get_app_handle() -> Result<&'static AppHandle> {
         .get()
         .ok_or_else(|| anyhow!("AppHandle not initialized"))
 }
+/// .atdf files imported by the user, loaded on startup
+pub fn user_device_dir() -> Result<PathBuf> {
+    Ok(get_app_handle()?.path().app_data_dir()?.join("devices"))
+}
 pub fn set_app_title(app_title: &str) ->Result<()> {
     emit!("project_state",app_title);
     let title = format!("{} - avr simulator", app_title);
@@ -43,6 +48,11 @@ pub fn run() {
             APP_HANDLE
                 .set(app.app_handle().to_owned())
                 .unwrap();
+            // a broken device directory only costs the user devices
+            match user_device_dir().and_then(|x| device_parser::loader::load_dir(&x)) {
+                Ok((_, failed)) => failed.iter().for_each(|(file, e)| println!("failed to load {}: {}", file, e)),
+                Err(e) => println!("failed to load the device directory: {}", e),
+            }
             tauri::async_runtime::spawn(async move {
                 loop {
                     Controller::update().unwrap();
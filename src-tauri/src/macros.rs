@@ -21,7 +21,7 @@ macro_rules! wrap_anyhow {
     ($name:ident ( $($arg:ident : $typ:ty),* ) -> $ret:ty $body:block) => {
         #[tauri::command]
         pub fn $name($($arg : $typ),*) -> ::tauri::Result<$ret> {
-            let args = format!("{:?}",$($arg.clone()),*);
+            let args = format!("{:?}",($($arg.clone()),*));
             let result = (|| -> ::anyhow::Result<$ret> {$body})();
             if(result.is_err()){
                 println!("Error:{}({:?})->{:?}",stringify!($name),args,result);
@@ -37,7 +37,7 @@ macro_rules! wrap_anyhow {
     (async $name:ident () -> $ret:ty $body:block) => {
         #[tauri::command]
         pub async fn $name() -> ::tauri::Result<$ret> {
-            let result = (async || -> ::anyhow::Result<$ret> {$body})().await;
+            let result: ::anyhow::Result<$ret> = async {$body}.await;
             if cfg!(debug_assertions){
                 if(result.is_err()){
                     println!("Error:({})->{:?}",stringify!($name), result);
@@ -54,8 +54,8 @@ macro_rules! wrap_anyhow {
     (async $name:ident ( $($arg:ident : $typ:ty),* ) -> $ret:ty $body:block) => {
         #[tauri::command]
         pub async fn $name($($arg : $typ),*) -> ::tauri::Result<$ret> {
-            let args = format!("{:?}",$($arg.clone()),*);
-            let result = (async || -> ::anyhow::Result<$ret> {$body})().await;
+            let args = format!("{:?}",($($arg.clone()),*));
+            let result: ::anyhow::Result<$ret> = async {$body}.await;
             if(result.is_err()){
                 println!("Error:{}({:?})->{:?}",stringify!($name),args,result);
             }
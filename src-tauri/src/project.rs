@@ -215,10 +215,12 @@ impl Project {
         if self.state.mcu.is_empty() {
             return Ok(instructions);
         }
+        let mut prev: Option<Instruction> = None;
         instructions
             .into_iter()
             .map(|mut x| {
-                x.gen_comment(&self.state)?;
+                x.gen_comment(prev.as_ref(), &self.state)?;
+                prev = Some(x.clone());
                 Ok(x)
             })
             .collect::<Result<Vec<Instruction>>>()
@@ -276,6 +278,10 @@ pub struct ProjectState {
     pub name: String,
     pub mcu: String,
     pub freq: u32,
+    #[serde(default)]
+    pub fuses: Vec<u8>, // empty until edited, the device defaults apply
+    #[serde(default)]
+    pub lockbits: Vec<u8>,
 }
 
 impl FromSql for ProjectState {
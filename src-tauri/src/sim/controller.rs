@@ -9,9 +9,15 @@ use std::sync::{
 };
 use std::{thread};
 
+use crate::sim::coverage::CoverageFormat;
+use crate::sim::memory::MemorySpace;
+use crate::sim::peripherals::analog::{AnalogNet, AnalogSource};
+use crate::sim::peripherals::spi::SpiDeviceKind;
+use crate::sim::peripherals::twi::I2cDeviceKind;
+use crate::sim::trace::TraceFormat;
 use crate::sim::worker;
 
-#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum Action {
     Run,
@@ -23,6 +29,30 @@ pub enum Action {
     Skip,              //only on call runs until fn returns
     Watch([u8; 8]),    //we accept this as string
     WatchUpdate(bool), // update watchlist variables when running
+    Trace(u32),        // trace ring size, 0 disables tracing
+    TraceExport(String, TraceFormat), // path
+    ProfileGet,        // profile is sent with sim-profile
+    ProfileReset,
+    LoadElf(String),   // symbols and line info for the profile and coverage
+    CoverageExport(String, CoverageFormat), // path
+    CoverageReset,
+    SetRegister(u8, u8), // register, value; only while paused
+    SetFlag(u8, bool),   // sreg bit
+    SetSp(u16),
+    SetPc(u32),          // byte address
+    Poke(MemorySpace, u32, u8),
+    ReadMemory(MemorySpace, u32, u32), // address, len; sent with sim-memory
+    IoView,            // decoded registers are sent with sim-io-view
+    PeripheralSupport, // modelled module instances are sent with sim-peripheral-support
+    Pinout,            // packages with the pin states are sent with sim-pinout
+    ReservedWarnings(bool), // writes to reserved bits are sent with sim-reserved-write
+    DrivePin(String, Option<bool>), // pad e.g. PB2, None releases the pin
+    DrivePinAt(String, Option<bool>, u64), // applied once the simulation reaches the cycle
+    SpiAttach(String, SpiDeviceKind, Option<String>), // spi instance, device, select pad
+    SpiTransfer(String, u8), // byte from an external master, MISO is sent with sim-spi-transfer
+    I2cAttach(String, u8, I2cDeviceKind), // twi instance, 7 bit address, device
+    SetAnalog(AnalogNet, AnalogSource),
+    LoadAnalogCsv(AnalogNet, String), // path of time,volts samples
 }
 #[derive(Debug)]
 pub enum Response {
@@ -1,4 +1,7 @@
+use device_parser::AvrDeviceFile;
+
 #[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Core {
     AVR,
     AVRe,
@@ -7,3 +10,13 @@ pub enum Core {
     AVRxt,
     AVRrc,
 }
+impl Core {
+    pub fn from_atdf(atdf: &AvrDeviceFile) -> Core {
+        match atdf.devices.architecture {
+            "AVR8X" => Core::AVRxt,
+            "AVR8L" => Core::AVRrc,
+            "AVR8_XMEGA" => Core::AVRxm,
+            _ => Core::AVRe,
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use crate::error::Result;
+use crate::sim::debug_info::DebugInfo;
+use crate::sim::instruction::Instruction;
+use opcode_gen::Opcode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// file name used for per address coverage when no elf is loaded, lines are byte addresses
+const FLASH_FILE: &str = "flash";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CoverageFormat {
+    Lcov,
+    Cobertura,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct BranchCount {
+    taken: u64,
+    not_taken: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct LineRecord {
+    hits: u64,
+    executed: bool,
+    branches: Vec<BranchCount>,
+}
+
+type FileRecords = BTreeMap<String, BTreeMap<u32, LineRecord>>;
+
+/// executed flash words and the outcome of every conditional branch or skip
+#[derive(Debug, Default)]
+pub struct Coverage {
+    hits: BTreeMap<u32, u64>,
+    branches: BTreeMap<u32, BranchCount>,
+}
+
+fn is_conditional(opcode: &Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::BRBC
+            | Opcode::BRBS
+            | Opcode::BRCC
+            | Opcode::BRCS
+            | Opcode::BREQ
+            | Opcode::BRGE
+            | Opcode::BRHC
+            | Opcode::BRHS
+            | Opcode::BRID
+            | Opcode::BRIE
+            | Opcode::BRLO
+            | Opcode::BRLT
+            | Opcode::BRMI
+            | Opcode::BRNE
+            | Opcode::BRPL
+            | Opcode::BRSH
+            | Opcode::BRTC
+            | Opcode::BRTS
+            | Opcode::BRVC
+            | Opcode::BRVS
+            | Opcode::CPSE
+            | Opcode::SBRC
+            | Opcode::SBRS
+            | Opcode::SBIC
+            | Opcode::SBIS
+    )
+}
+
+impl Coverage {
+    pub fn reset(&mut self) {
+        *self = Coverage::default();
+    }
+
+    /// a conditional counts as taken when execution did not fall through to the next instruction
+    pub fn record(&mut self, pc: u32, opcode: &Opcode, len: u8, next_pc: u32) {
+        *self.hits.entry(pc).or_insert(0) += 1;
+        if is_conditional(opcode) {
+            let branch = self.branches.entry(pc).or_default();
+            if next_pc == pc + (len as u32) * 2 {
+                branch.not_taken += 1;
+            } else {
+                branch.taken += 1;
+            }
+        }
+    }
+
+    fn records(&self, flash: &[Instruction], debug: Option<&DebugInfo>) -> Result<FileRecords> {
+        let mut files = FileRecords::new();
+        for (index, inst) in flash.iter().enumerate() {
+            let name = &inst.get_raw_inst()?.name;
+            if inst.address as usize != index || matches!(name, Opcode::CUSTOM_INST(_)) {
+                continue;
+            }
+            let (file, line) = match debug {
+                Some(debug) => match debug.line_at(inst.address) {
+                    Some(line) => (line.file.clone(), line.line),
+                    None => continue,
+                },
+                None => (FLASH_FILE.to_string(), inst.address),
+            };
+            let record = files.entry(file).or_default().entry(line).or_default();
+            if let Some(hits) = self.hits.get(&inst.address) {
+                record.hits = record.hits.max(*hits);
+                record.executed = true;
+            }
+            if is_conditional(name) {
+                record
+                    .branches
+                    .push(self.branches.get(&inst.address).copied().unwrap_or_default());
+            }
+        }
+        Ok(files)
+    }
+
+    pub fn to_lcov(&self, flash: &[Instruction], debug: Option<&DebugInfo>) -> Result<String> {
+        let mut out = String::from("TN:\n");
+        for (file, lines) in self.records(flash, debug)? {
+            let _ = writeln!(out, "SF:{}", file);
+            if let Some(debug) = debug {
+                let functions: Vec<(u32, &String, u64)> = debug
+                    .functions
+                    .iter()
+                    .filter_map(|(address, name)| {
+                        let line = debug.line_at(*address).filter(|x| x.file == file)?;
+                        Some((line.line, name, *self.hits.get(address).unwrap_or(&0)))
+                    })
+                    .collect();
+                for (line, name, _) in &functions {
+                    let _ = writeln!(out, "FN:{},{}", line, name);
+                }
+                for (_, name, hits) in &functions {
+                    let _ = writeln!(out, "FNDA:{},{}", hits, name);
+                }
+                let _ = writeln!(out, "FNF:{}", functions.len());
+                let _ = writeln!(out, "FNH:{}", functions.iter().filter(|x| x.2 > 0).count());
+            }
+            let (mut branches_found, mut branches_hit) = (0, 0);
+            for (line, record) in &lines {
+                for (block, branch) in record.branches.iter().enumerate() {
+                    for (id, count) in [branch.taken, branch.not_taken].iter().enumerate() {
+                        branches_found += 1;
+                        if *count > 0 {
+                            branches_hit += 1;
+                        }
+                        match record.executed {
+                            true => {
+                                let _ = writeln!(out, "BRDA:{},{},{},{}", line, block, id, count);
+                            }
+                            false => {
+                                let _ = writeln!(out, "BRDA:{},{},{},-", line, block, id);
+                            }
+                        }
+                    }
+                }
+            }
+            let _ = writeln!(out, "BRF:{}", branches_found);
+            let _ = writeln!(out, "BRH:{}", branches_hit);
+            for (line, record) in &lines {
+                let _ = writeln!(out, "DA:{},{}", line, record.hits);
+            }
+            let _ = writeln!(out, "LF:{}", lines.len());
+            let _ = writeln!(out, "LH:{}", lines.values().filter(|x| x.executed).count());
+            out.push_str("end_of_record\n");
+        }
+        Ok(out)
+    }
+
+    pub fn to_cobertura(&self, flash: &[Instruction], debug: Option<&DebugInfo>) -> Result<String> {
+        fn rate(hit: usize, found: usize) -> f64 {
+            match found {
+                0 => 1.0,
+                _ => hit as f64 / found as f64,
+            }
+        }
+        fn escape(text: &str) -> String {
+            text.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        }
+        // (lines valid, lines covered, branches valid, branches covered)
+        fn count(lines: &BTreeMap<u32, LineRecord>) -> (usize, usize, usize, usize) {
+            let branches = lines.values().flat_map(|x| x.branches.iter());
+            (
+                lines.len(),
+                lines.values().filter(|x| x.executed).count(),
+                branches.clone().count() * 2,
+                branches
+                    .map(|x| (x.taken > 0) as usize + (x.not_taken > 0) as usize)
+                    .sum(),
+            )
+        }
+
+        let files = self.records(flash, debug)?;
+        let mut classes = String::new();
+        let mut total = (0, 0, 0, 0);
+        for (file, lines) in &files {
+            let c = count(lines);
+            total = (total.0 + c.0, total.1 + c.1, total.2 + c.2, total.3 + c.3);
+            let _ = writeln!(
+                classes,
+                "        <class name=\"{0}\" filename=\"{0}\" line-rate=\"{1:.4}\" branch-rate=\"{2:.4}\" complexity=\"0\">",
+                escape(file),
+                rate(c.1, c.0),
+                rate(c.3, c.2)
+            );
+            classes.push_str("          <methods/>\n          <lines>\n");
+            for (line, record) in lines {
+                let _ = write!(classes, "            <line number=\"{}\" hits=\"{}\"", line, record.hits);
+                if record.branches.is_empty() {
+                    classes.push_str(" branch=\"false\"/>\n");
+                } else {
+                    let found = record.branches.len() * 2;
+                    let hit: usize = record
+                        .branches
+                        .iter()
+                        .map(|x| (x.taken > 0) as usize + (x.not_taken > 0) as usize)
+                        .sum();
+                    let _ = writeln!(
+                        classes,
+                        " branch=\"true\" condition-coverage=\"{}% ({}/{})\"/>",
+                        hit * 100 / found,
+                        hit,
+                        found
+                    );
+                }
+            }
+            classes.push_str("          </lines>\n        </class>\n");
+        }
+
+        let mut out = String::from("<?xml version=\"1.0\" ?>\n");
+        let _ = writeln!(
+            out,
+            "<coverage line-rate=\"{:.4}\" branch-rate=\"{:.4}\" lines-covered=\"{}\" lines-valid=\"{}\" branches-covered=\"{}\" branches-valid=\"{}\" complexity=\"0\" version=\"1.9\" timestamp=\"0\">",
+            rate(total.1, total.0),
+            rate(total.3, total.2),
+            total.1,
+            total.0,
+            total.3,
+            total.2
+        );
+        out.push_str("  <sources/>\n  <packages>\n");
+        let _ = writeln!(
+            out,
+            "    <package name=\"firmware\" line-rate=\"{:.4}\" branch-rate=\"{:.4}\" complexity=\"0\">",
+            rate(total.1, total.0),
+            rate(total.3, total.2)
+        );
+        out.push_str("      <classes>\n");
+        out.push_str(&classes);
+        out.push_str("      </classes>\n    </package>\n  </packages>\n</coverage>\n");
+        Ok(out)
+    }
+
+    pub fn export(
+        &self,
+        path: &str,
+        format: CoverageFormat,
+        flash: &[Instruction],
+        debug: Option<&DebugInfo>,
+    ) -> Result<()> {
+        let data = match format {
+            CoverageFormat::Lcov => self.to_lcov(flash, debug)?,
+            CoverageFormat::Cobertura => self.to_cobertura(flash, debug)?,
+        };
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::debug_info::SourceLine;
+    use opcode_gen::{CustomOpcodes, RawInst};
+
+    // 0x0: breq .+2   0x2: nop   0x4: nop
+    fn program() -> Vec<Instruction> {
+        let mut flash = vec![
+            Instruction::new("".to_string(), CustomOpcodes::EMPTY as usize, vec![], 0);
+            6
+        ];
+        for (address, opcode) in [(0, Opcode::BREQ), (2, Opcode::NOP), (4, Opcode::NOP)] {
+            flash[address] = Instruction::new(
+                "".to_string(),
+                RawInst::get_inst_id_from_opcode(opcode).unwrap(),
+                vec![],
+                address as u32,
+            );
+        }
+        flash
+    }
+
+    #[test]
+    fn test_lcov_per_address() {
+        let mut coverage = Coverage::default();
+        coverage.record(0, &Opcode::BREQ, 1, 2);
+        coverage.record(2, &Opcode::NOP, 1, 4);
+        coverage.record(0, &Opcode::BREQ, 1, 4);
+        let lcov = coverage.to_lcov(&program(), None).unwrap();
+        assert_eq!(
+            lcov,
+            "TN:\nSF:flash\nBRDA:0,0,0,1\nBRDA:0,0,1,1\nBRF:2\nBRH:2\nDA:0,2\nDA:2,1\nDA:4,0\nLF:3\nLH:2\nend_of_record\n"
+        );
+    }
+
+    #[test]
+    fn test_cobertura_source_lines() {
+        let mut debug = DebugInfo::default();
+        debug.functions.insert(0, "main".to_string());
+        for (address, line) in [(0, 3), (4, 4)] {
+            debug.lines.insert(
+                address,
+                SourceLine {
+                    file: "main.c".to_string(),
+                    line,
+                },
+            );
+        }
+        let mut coverage = Coverage::default();
+        coverage.record(0, &Opcode::BREQ, 1, 4);
+        coverage.record(4, &Opcode::NOP, 1, 6);
+
+        let lcov = coverage.to_lcov(&program(), Some(&debug)).unwrap();
+        assert!(lcov.contains("SF:main.c\nFN:3,main\nFNDA:1,main\nFNF:1\nFNH:1\n"));
+        assert!(lcov.contains("DA:3,1\nDA:4,1\nLF:2\nLH:2\n"));
+
+        let xml = coverage.to_cobertura(&program(), Some(&debug)).unwrap();
+        assert!(xml.contains("lines-covered=\"2\" lines-valid=\"2\" branches-covered=\"1\" branches-valid=\"2\""));
+        assert!(xml.contains("<line number=\"3\" hits=\"1\" branch=\"true\" condition-coverage=\"50% (1/2)\"/>"));
+    }
+}
@@ -0,0 +1,106 @@
+use crate::error::Result;
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+/// function symbols and dwarf line table of an elf, addresses are flash byte addresses
+#[derive(Debug, Default)]
+pub struct DebugInfo {
+    pub functions: BTreeMap<u32, String>,
+    pub lines: BTreeMap<u32, SourceLine>,
+}
+
+impl DebugInfo {
+    pub fn load(path: &str) -> Result<DebugInfo> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<DebugInfo> {
+        let file = object::File::parse(data)?;
+        let mut info = DebugInfo::default();
+        for symbol in file.symbols() {
+            if symbol.kind() == SymbolKind::Text && symbol.is_definition() {
+                info.functions
+                    .insert(symbol.address() as u32, symbol.name()?.to_string());
+            }
+        }
+
+        let endian = match file.is_little_endian() {
+            true => gimli::RunTimeEndian::Little,
+            false => gimli::RunTimeEndian::Big,
+        };
+        let sections = gimli::DwarfSections::load(|id| -> Result<Cow<[u8]>> {
+            Ok(match file.section_by_name(id.name()) {
+                Some(section) => section.uncompressed_data()?,
+                None => Cow::Borrowed(&[]),
+            })
+        })?;
+        let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() {
+                    continue;
+                }
+                let (Some(file), Some(line)) = (row.file(header), row.line()) else {
+                    continue;
+                };
+                let mut path = String::new();
+                if let Some(dir) = file.directory(header) {
+                    path += &dwarf.attr_string(&unit, dir)?.to_string_lossy();
+                    path.push('/');
+                }
+                path += &dwarf.attr_string(&unit, file.path_name())?.to_string_lossy();
+                info.lines.entry(row.address() as u32).or_insert(SourceLine {
+                    file: path,
+                    line: line.get() as u32,
+                });
+            }
+        }
+        Ok(info)
+    }
+
+    /// source line the instruction at `address` belongs to
+    pub fn line_at(&self, address: u32) -> Option<&SourceLine> {
+        self.lines.range(..=address).next_back().map(|(_, line)| line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_lookup() {
+        let mut info = DebugInfo::default();
+        info.lines.insert(
+            0x10,
+            SourceLine {
+                file: "main.c".to_string(),
+                line: 4,
+            },
+        );
+        info.lines.insert(
+            0x16,
+            SourceLine {
+                file: "main.c".to_string(),
+                line: 5,
+            },
+        );
+        assert_eq!(info.line_at(0x8), None);
+        assert_eq!(info.line_at(0x14).unwrap().line, 4);
+        assert_eq!(info.line_at(0x20).unwrap().line, 5);
+        assert!(DebugInfo::parse(b"not an elf").is_err());
+    }
+}
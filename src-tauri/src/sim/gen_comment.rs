@@ -7,7 +7,7 @@ use crate::sim::constraint::Constraint;
 use crate::sim::display::Display;
 use crate::sim::instruction::Instruction;
 use crate::sim::operand::OperandInfo;
-use device_parser::get_register_map;
+use device_parser::{decode_register, get_register_map};
 use opcode_gen::Opcode;
 
 pub fn gen_comment(i: &mut Instruction) -> Result<()> {
@@ -24,32 +24,106 @@ pub fn gen_comment(i: &mut Instruction) -> Result<()> {
         _ => Ok(()),
     }
 }
-pub fn gen_operand_details(i: &mut Instruction, state: &ProjectState) -> Result<()> {
+/// value an OUT or STS writes when the directly preceding instruction loads it with LDI
+fn written_value(i: &Instruction, prev: Option<&Instruction>) -> Result<Option<u64>> {
+    let Some(prev) = prev else {
+        return Ok(None);
+    };
+    if prev.get_raw_inst()?.name != Opcode::LDI
+        || !matches!(i.get_raw_inst()?.name, Opcode::OUT | Opcode::STS)
+    {
+        return Ok(None);
+    }
+    match (&i.operands, &prev.operands) {
+        (Some(ops), Some(prev_ops)) if ops.len() == 2 && prev_ops.len() == 2 && ops[1].value == prev_ops[0].value => {
+            Ok(Some(prev_ops[1].value as u64))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn gen_operand_details(i: &mut Instruction, prev: Option<&Instruction>, state: &ProjectState) -> Result<()> {
+    let written = written_value(i, prev)?;
+    let is_sts = RawInst::get_inst_from_id(i.opcode_id)?.name == Opcode::STS;
     match i.operands {
         Some(ref mut operands) => {
             for x in operands.into_iter() {
-                match x.constraint {
-                    Constraint::p | Constraint::P => {
-                        let tree = get_register_map(&state.mcu).ok_or(anyhow!("invalid mcu"))?;
-                        let reg_opt = tree.get(&(x.value as u64 + 0x20));
-                        if reg_opt.is_none() {
-                            continue;
-                        }
-                        let reg = reg_opt.unwrap();
-                        let info = OperandInfo {
-                            register_name: reg.name.parse()?,
-                            register_mask: serde_json::to_string(&reg.bitfields)?,
-                            description: reg.caption.clone().unwrap_or(reg.name).parse()?,
-                        };
-                        x.operand_info = Some(info);
-
-                        continue;
-                    }
+                let address = match x.constraint {
+                    Constraint::p | Constraint::P => x.value as u64 + 0x20,
+                    Constraint::i | Constraint::j if is_sts => x.value as u64,
                     _ => continue,
-                }
+                };
+                let tree = get_register_map(&state.mcu).ok_or(anyhow!("invalid mcu"))?;
+                let Some(reg) = tree.get(&address) else {
+                    continue;
+                };
+                let value_description = written
+                    .and_then(|value| decode_register(&state.mcu, reg, value))
+                    .map(|fields| {
+                        fields
+                            .iter()
+                            .map(|x1| match x1.caption {
+                                Some(caption) => format!("{}: {}", x1.name, caption),
+                                None => format!("{}={}", x1.name, x1.value),
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    })
+                    .unwrap_or_default();
+                x.operand_info = Some(OperandInfo {
+                    register_name: reg.name.parse()?,
+                    register_mask: serde_json::to_string(&reg.bitfields)?,
+                    description: reg.caption.unwrap_or(reg.name).parse()?,
+                    value_description,
+                });
             }
             Ok(())
         }
         None => Ok(()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::operand::Operand;
+
+    fn inst(opcode: Opcode, operands: Vec<(Constraint, i64)>) -> Instruction {
+        let operands = operands
+            .into_iter()
+            .map(|(constraint, value)| Operand {
+                constraint,
+                value,
+                ..Operand::default()
+            })
+            .collect();
+        Instruction::new("".to_string(), RawInst::get_inst_id_from_opcode(opcode).unwrap(), operands, 0)
+    }
+
+    #[test]
+    fn test_written_value_description() -> Result<()> {
+        let state = ProjectState {
+            mcu: "atmega328p".to_string(),
+            ..ProjectState::default()
+        };
+        // TCCR0B is io 0x25
+        let ldi = inst(Opcode::LDI, vec![(Constraint::d, 16), (Constraint::M, 0x03)]);
+        let mut out = inst(Opcode::OUT, vec![(Constraint::P, 0x25), (Constraint::r, 16)]);
+        gen_operand_details(&mut out, Some(&ldi), &state)?;
+        let info = out.operands.as_ref().unwrap()[0].operand_info.clone().unwrap();
+        assert_eq!(info.register_name, "TCCR0B");
+        assert!(info.value_description.contains("CS0: Running, CLK/64"));
+
+        // ADMUX 0x7c is out of io space
+        let mut sts = inst(Opcode::STS, vec![(Constraint::i, 0x7c), (Constraint::r, 16)]);
+        gen_operand_details(&mut sts, Some(&ldi), &state)?;
+        let info = sts.operands.as_ref().unwrap()[0].operand_info.clone().unwrap();
+        assert!(info.value_description.contains("REFS: AREF, Internal Vref turned off"));
+
+        // the value is unknown when another register was loaded
+        let mut out = inst(Opcode::OUT, vec![(Constraint::P, 0x25), (Constraint::r, 17)]);
+        gen_operand_details(&mut out, Some(&ldi), &state)?;
+        assert!(out.operands.unwrap()[0].operand_info.clone().unwrap().value_description.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use crate::sim::memory::{Memory, MemorySpace};
+use device_parser::RegisterMap;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryRange {
+    pub space: MemorySpace,
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub changed: Vec<bool>, // changed since the simulation was last resumed
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IoBitField {
+    pub name: &'static str,
+    pub caption: Option<&'static str>,
+    pub mask: u64,
+    pub value: u64, // shifted down to bit 0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IoRegister {
+    pub name: &'static str,
+    pub caption: Option<&'static str>,
+    pub address: u32,
+    pub size: u64,
+    pub value: u64,
+    pub bitfields: Vec<IoBitField>,
+}
+
+/// every register of the device register map with its current value split into bitfields
+pub fn io_view(reg_map: RegisterMap, memory: &Memory) -> Vec<IoRegister> {
+    let mut registers: Vec<IoRegister> = reg_map
+        .entries()
+        .filter_map(|(address, reg)| {
+            let value = memory
+                .read(MemorySpace::Data, address as u32, reg.size as u32)
+                .ok()?
+                .iter()
+                .rev()
+                .fold(0u64, |acc, x| (acc << 8) | *x as u64);
+            Some(IoRegister {
+                name: reg.name,
+                caption: reg.caption,
+                address: address as u32,
+                size: reg.size,
+                value,
+                bitfields: reg
+                    .bitfields
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|field| IoBitField {
+                        name: field.name,
+                        caption: field.caption,
+                        mask: field.mask,
+                        value: (value & field.mask)
+                            .checked_shr(field.mask.trailing_zeros())
+                            .unwrap_or(0),
+                    })
+                    .collect(),
+            })
+        })
+        .collect();
+    registers.sort_by_key(|x| x.address);
+    registers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::{get_register_map, get_tree_map};
+
+    #[test]
+    fn test_io_view() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut memory = Memory::default();
+        memory.data.init(atdf).unwrap();
+        memory.poke(MemorySpace::Data, 0x5f, 0x82).unwrap(); // SREG: I and Z
+        memory.poke(MemorySpace::Data, 0x85, 0x12).unwrap(); // TCNT1H
+
+        let view = io_view(get_register_map(&"atmega328p".to_string()).unwrap(), &memory);
+        assert!(view.windows(2).all(|x| x[0].address < x[1].address));
+        let sreg = view.iter().find(|x| x.name == "SREG").unwrap();
+        assert_eq!(sreg.value, 0x82);
+        let flags: Vec<_> = sreg.bitfields.iter().filter(|x| x.value == 1).map(|x| x.name).collect();
+        assert_eq!(flags.len(), 2);
+        assert!(flags.contains(&"I") && flags.contains(&"Z"));
+        // 16 bit registers are split into their low and high byte by the register map
+        assert_eq!(view.iter().find(|x| x.name == "TCNT1H").unwrap().value, 0x12);
+    }
+}
@@ -165,9 +165,29 @@ impl Instruction {
         }
     }
 
-    pub(crate) fn gen_comment(&mut self, state: &ProjectState) -> Result<()> {
+    /// assembly text of the instruction, e.g. `ldi r16, 0xff`
+    pub(crate) fn mnemonic(&self) -> Result<String> {
+        let name = match &self.get_raw_inst()?.name {
+            Opcode::CUSTOM_INST(_) => ".word".to_string(),
+            name => name.to_string().to_lowercase(),
+        };
+        let operands = self
+            .operands
+            .iter()
+            .flatten()
+            .map(|x| x.to_string())
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>();
+        if operands.is_empty() {
+            Ok(name)
+        } else {
+            Ok(format!("{} {}", name, operands.join(", ")))
+        }
+    }
+
+    pub(crate) fn gen_comment(&mut self, prev: Option<&Instruction>, state: &ProjectState) -> Result<()> {
         super::gen_comment::gen_comment(self)?;
-        super::gen_comment::gen_operand_details(self, state)?;
+        super::gen_comment::gen_operand_details(self, prev, state)?;
         Ok(())
     }
 }
@@ -1,7 +1,11 @@
 use crate::error::Result;
 use crate::sim::instruction::Instruction;
+use crate::sim::peripherals::InstanceMap;
 use device_parser::AvrDeviceFile;
-use opcode_gen::CustomOpcodes;
+use opcode_gen::{CustomOpcodes, RawInst};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Default, Debug)]
 pub struct Memory {
@@ -9,6 +13,7 @@ pub struct Memory {
     pub data: DataMemory,
     pub eeprom: Vec<u8>,
     pub program_couter: u32,
+    flash_before: BTreeMap<u32, u16>, // words written since the last snapshot, as they were
 }
 
 impl Memory {
@@ -25,89 +30,282 @@ impl Memory {
             .address_spaces
             .iter()
             .find(|x| x.id == "prog")
-            .unwrap();
-        let eeprom_space = atdf
-            .devices
-            .address_spaces
-            .iter()
-            .find(|x| x.id == "eeprom")
-            .unwrap();
+            .ok_or(anyhow!("no prog address space"))?;
+        let eeprom_size = atdf.eeprom().map_or(0, |x| x.size);
+        // indexed by byte address, the second byte of a word holds a filler
         self.flash.resize(
-            (address_space.size / 2) as usize,
+            address_space.size as usize,
             Instruction::decode_from_opcode(CustomOpcodes::EMPTY as u16)?,
         );
-        self.eeprom.resize(eeprom_space.size as usize, 0xffu8);
+        self.eeprom.resize(eeprom_size as usize, 0xffu8);
         self.data.init(&atdf)?;
         Ok(())
     }
+
+    /// instruction starting at the byte `address`, fillers and empty slots are skipped
+    pub fn instruction_at(&self, address: u32) -> Option<&Instruction> {
+        self.flash.get(address as usize).filter(|x| {
+            x.address == address
+                && x.opcode_id != CustomOpcodes::EMPTY as usize
+                && x.opcode_id != CustomOpcodes::REMINDER as usize
+        })
+    }
+    fn is_two_word(inst: &Instruction) -> bool {
+        RawInst::get_inst_from_id(inst.opcode_id).is_ok_and(|x| x.len == 2)
+    }
+
+    /// flash word at the even byte `address`, erased flash reads 0xffff
+    pub fn flash_word(&self, address: u32) -> u16 {
+        if let Some(inst) = self.instruction_at(address) {
+            return match Self::is_two_word(inst) {
+                true => (inst.raw_opcode >> 16) as u16,
+                false => inst.raw_opcode as u16,
+            };
+        }
+        match address.checked_sub(2).and_then(|x| self.instruction_at(x)) {
+            Some(inst) if Self::is_two_word(inst) => inst.raw_opcode as u16,
+            _ => 0xffff,
+        }
+    }
+
+    /// replaces the flash word at the even byte `address` and decodes the affected instruction again
+    pub fn set_flash_word(&mut self, address: u32, word: u16) -> Result<()> {
+        let index = address as usize;
+        if !address.is_multiple_of(2) || index >= self.flash.len() {
+            return Err(anyhow!("invalid flash address:{:#x}", address));
+        }
+        let old = self.flash_word(address);
+        self.flash_before.entry(address).or_insert(old);
+        if let Some(prev) = address.checked_sub(2).filter(|x| {
+            self.instruction_at(*x).is_some_and(Self::is_two_word)
+        }) {
+            let inst = &mut self.flash[prev as usize];
+            inst.raw_opcode = (inst.raw_opcode & 0xffff0000) | word as u32;
+            return inst.mach_registers();
+        }
+        let was_two_word = self.instruction_at(address).is_some_and(Self::is_two_word);
+        let next = self.flash_word(address + 2);
+
+        let mut inst = Instruction::decode_from_opcode(word)?;
+        inst.address = address;
+        let is_two_word = Self::is_two_word(&inst);
+        if is_two_word {
+            inst.raw_opcode = ((word as u32) << 16) | next as u32;
+        }
+        inst.mach_registers()?;
+        self.flash[index] = inst;
+
+        if was_two_word && !is_two_word {
+            self.set_flash_word(address + 2, next)?;
+        } else if is_two_word && self.instruction_at(address + 2).is_some() {
+            self.flash[index + 2] =
+                Instruction::new("".to_string(), CustomOpcodes::EMPTY as usize, vec![], 0);
+        }
+        Ok(())
+    }
+
+    /// `len` bytes starting at `address`
+    pub fn read(&self, space: MemorySpace, address: u32, len: u32) -> Result<Vec<u8>> {
+        let size = match space {
+            MemorySpace::Data => self.data.len(),
+            MemorySpace::Eeprom => self.eeprom.len(),
+            MemorySpace::Flash => self.flash.len(),
+        };
+        let (start, end) = (address as usize, address as usize + len as usize);
+        if end > size {
+            return Err(anyhow!("invalid range:{:#x}..{:#x}", start, end));
+        }
+        Ok(match space {
+            MemorySpace::Data => (start..end).map(|x| self.data[x]).collect(),
+            MemorySpace::Eeprom => self.eeprom[start..end].to_vec(),
+            MemorySpace::Flash => (start..end)
+                .map(|x| (self.flash_word(x as u32 & !1) >> ((x & 1) * 8)) as u8)
+                .collect(),
+        })
+    }
+
+    /// flash is not copied, the words written after this keep their old value instead
+    pub fn snapshot(&mut self) -> Result<MemorySnapshot> {
+        self.flash_before.clear();
+        Ok(MemorySnapshot {
+            data: self.read(MemorySpace::Data, 0, self.data.len() as u32)?,
+            eeprom: self.eeprom.clone(),
+        })
+    }
+
+    /// true for every byte of `current` (read at `address`) that differs from `snapshot`
+    pub fn changed(&self, snapshot: &MemorySnapshot, space: MemorySpace, address: u32, current: &[u8]) -> Vec<bool> {
+        let old = match space {
+            MemorySpace::Data => &snapshot.data,
+            MemorySpace::Eeprom => &snapshot.eeprom,
+            MemorySpace::Flash => {
+                return current
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| {
+                        let byte = address + i as u32;
+                        self.flash_before
+                            .get(&(byte & !1))
+                            .is_some_and(|w| (w >> ((byte & 1) * 8)) as u8 != *x)
+                    })
+                    .collect();
+            }
+        };
+        current
+            .iter()
+            .enumerate()
+            .map(|(i, x)| old.get(address as usize + i) != Some(x))
+            .collect()
+    }
+
+    /// writes one byte, flash bytes are little endian within their word
+    pub fn poke(&mut self, space: MemorySpace, address: u32, value: u8) -> Result<()> {
+        match space {
+            MemorySpace::Data => {
+                *self
+                    .data
+                    .get_mut(address as usize)
+                    .ok_or(anyhow!("invalid data address:{:#x}", address))? = value;
+            }
+            MemorySpace::Eeprom => {
+                *self
+                    .eeprom
+                    .get_mut(address as usize)
+                    .ok_or(anyhow!("invalid eeprom address:{:#x}", address))? = value;
+            }
+            MemorySpace::Flash => {
+                let word_address = address & !1;
+                let shift = (address & 1) * 8;
+                let word = (self.flash_word(word_address) & !(0xff << shift)) | ((value as u16) << shift);
+                self.set_flash_word(word_address, word)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MemorySpace {
+    Data,
+    Eeprom,
+    Flash,
+}
+
+/// data and eeprom at a point in time, used to mark what changed since then
+#[derive(Debug, Default, Clone)]
+pub struct MemorySnapshot {
+    data: Vec<u8>,
+    eeprom: Vec<u8>,
 }
 
+/// eeprom or flash mapped into data space, as on AVRxt and reduced cores
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MappedMemory {
+    pub start: u32,
+    pub size: u32,
+    pub space: MemorySpace,
+}
+
+/// data space: the register file where it is mapped, the i/o registers and other memories
+/// up to the ram, then the ram
 #[derive(Default, Debug)]
 pub struct DataMemory {
-    pub registers: Vec<u8>,
+    pub registers: Vec<u8>, // r0 to r31, only the first `io.reg_size` bytes are in data space
     pub io: IOMemory<u8>,
     pub ram: Vec<u8>,
+    pub mapped: Vec<MappedMemory>,
 }
 impl std::ops::Index<usize> for DataMemory {
     type Output = u8;
 
     fn index(&self, index: usize) -> &Self::Output {
-        if index < self.registers.len() {
+        if index < self.io.reg_size {
             &self.registers[index]
-        } else if index - self.registers.len() < self.io.len() {
-            &self.io[index - self.registers.len()]
+        } else if index - self.io.reg_size < self.io.len() {
+            &self.io[index - self.io.reg_size]
         } else {
-            &self.ram[index - self.registers.len() - self.io.len()]
+            &self.ram[index - self.io.reg_size - self.io.len()]
         }
     }
 }
 impl std::ops::IndexMut<usize> for DataMemory {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        if index < self.registers.len() {
+        let reg_size = self.io.reg_size;
+        if index < reg_size {
             &mut self.registers[index]
-        } else if index - self.registers.len() < self.io.len() {
-            &mut self.io[index - self.registers.len()]
+        } else if index - reg_size < self.io.len() {
+            &mut self.io[index - reg_size]
         } else {
-            &mut self.ram[index - self.registers.len() - self.io.len()]
+            &mut self.ram[index - reg_size - self.io.len()]
         }
     }
 }
 
 impl DataMemory {
+    /// lays out data space from the memory segments of the ATDF
     pub fn init(&mut self, atdf: &'static AvrDeviceFile) -> Result<()> {
-        let address_space = atdf
-            .devices
-            .address_spaces
-            .iter()
-            .find(|x| x.id == "data")
-            .unwrap();
-        let reg_size = address_space
-            .memory_segments
-            .iter()
-            .find(|x| x.name == "REGISTERS")
-            .unwrap()
-            .size;
-        let io_size = address_space
-            .memory_segments
-            .iter()
-            .find(|x| x.name == "MAPPED_IO")
-            .unwrap()
-            .size;
-        let ram_size = address_space
-            .memory_segments
-            .iter()
-            .find(|x| x.name == "IRAM")
-            .unwrap()
-            .size;
-        self.registers.resize(reg_size as usize, 0);
+        let address_space = atdf.data_space().ok_or(anyhow!("no data address space"))?;
+        let reg_size = atdf.mapped_registers();
+        let io_size = atdf.io_size().ok_or(anyhow!("no data memory segments"))?;
+        let ram_size = atdf.ram().map_or(0, |x| x.size);
+        self.registers.resize(32, 0);
         self.io.resize(io_size as usize, 0);
         self.io.reg_size = reg_size as usize;
         self.ram.resize(ram_size as usize, 0);
+        self.mapped = address_space
+            .memory_segments
+            .iter()
+            .filter_map(|x| {
+                let space = match x.data_type {
+                    "eeprom" => MemorySpace::Eeprom,
+                    "flash" => MemorySpace::Flash,
+                    _ if x.name.contains("PROGMEM") => MemorySpace::Flash,
+                    _ => return None,
+                };
+                Some(MappedMemory {
+                    start: x.start as u32,
+                    size: x.size as u32,
+                    space,
+                })
+            })
+            .collect();
+        self.reset(atdf);
 
         Ok(())
     }
+    /// the memory and offset behind a data address of mapped eeprom or flash
+    pub fn mapped(&self, address: u32) -> Option<(MemorySpace, u32)> {
+        self.mapped
+            .iter()
+            .find(|x| (x.start..x.start + x.size).contains(&address))
+            .map(|x| (x.space, address - x.start))
+    }
+    /// i/o registers take their reset value from the ATDF, registers without one start at 0
+    pub fn reset(&mut self, atdf: &'static AvrDeviceFile) {
+        self.io.inner.iter_mut().for_each(|x| *x = 0);
+        let maps = atdf
+            .devices
+            .peripherals
+            .iter()
+            .flat_map(|x| InstanceMap::find(atdf, x.name))
+            .filter(|x| x.is_data());
+        for map in maps {
+            for (address, register) in map.registers() {
+                // registers of another mode share the address, one without an initval must not clear it
+                for byte in 0..register.size {
+                    let value = (register.initval >> (8 * byte)) as u8;
+                    if let Some(x) = self.get_mut((address as u64 + byte) as usize)
+                        && value != 0
+                    {
+                        *x = value;
+                    }
+                }
+            }
+        }
+    }
     pub fn len(&self) -> usize {
-        self.registers.len() + self.io.len() + self.ram.len()
+        self.io.reg_size + self.io.len() + self.ram.len()
     }
     pub fn get_mut(&mut self, index: usize) -> Option<&mut u8> {
         if index < self.len() {
@@ -125,6 +323,24 @@ impl DataMemory {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// single data space access made by an instruction, address is in data space
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemAccess {
+    pub kind: AccessKind,
+    pub address: u32,
+    pub value: u8,
+    pub width: u8, // bytes moved by the instruction, e.g. 2 for a pushed 16 bit pc
+    pub pc: u32,   // byte address of the accessing instruction
+}
+
 #[derive(Default, Debug)]
 pub struct IOMemory<T> {
     pub inner: Vec<T>,
@@ -159,3 +375,103 @@ impl<T> std::ops::IndexMut<usize> for IOMemory<T> {
         self.inner.index_mut(index)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opcode_gen::Opcode;
+
+    fn memory() -> Memory {
+        Memory {
+            flash: vec![
+                Instruction::new("".to_string(), CustomOpcodes::EMPTY as usize, vec![], 0);
+                8
+            ],
+            eeprom: vec![0xff; 4],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_flash_words() {
+        let mut m = memory();
+        assert_eq!(m.flash_word(0), 0xffff);
+        m.set_flash_word(0, 0xef0f).unwrap(); // ldi r16, 0xff
+        assert_eq!(m.instruction_at(0).unwrap().get_raw_inst().unwrap().name, Opcode::LDI);
+        assert_eq!(m.flash_word(0), 0xef0f);
+
+        m.set_flash_word(4, 0x0010).unwrap();
+        m.set_flash_word(2, 0x940c).unwrap(); // jmp 0x20, takes the word at 4 as its address
+        assert_eq!(m.instruction_at(2).unwrap().raw_opcode, 0x940c0010);
+        assert!(m.instruction_at(4).is_none());
+        m.poke(MemorySpace::Flash, 5, 0x02).unwrap();
+        assert_eq!(m.flash_word(4), 0x0210);
+        assert_eq!(m.instruction_at(2).unwrap().raw_opcode, 0x940c0210);
+
+        assert!(m.set_flash_word(3, 0).is_err());
+        assert!(m.set_flash_word(8, 0).is_err());
+
+        // the whole 32KB, the boot section included
+        let atdf = device_parser::get_tree_map().get("atmega328p").unwrap();
+        m.init(atdf, vec![], vec![]).unwrap();
+        m.set_flash_word(0x7e00, 0xef0f).unwrap();
+        assert_eq!(m.read(MemorySpace::Flash, 0x7e00, 2).unwrap(), vec![0x0f, 0xef]);
+        assert!(m.set_flash_word(0x8000, 0).is_err());
+    }
+
+    #[test]
+    fn test_read_and_snapshot() {
+        let mut m = memory();
+        m.set_flash_word(0, 0xef0f).unwrap();
+        assert_eq!(m.read(MemorySpace::Flash, 0, 3).unwrap(), vec![0x0f, 0xef, 0xff]);
+        assert!(m.read(MemorySpace::Eeprom, 2, 3).is_err());
+
+        let snapshot = m.snapshot().unwrap();
+        m.poke(MemorySpace::Eeprom, 1, 0x10).unwrap();
+        m.poke(MemorySpace::Flash, 1, 0xe0).unwrap();
+        let eeprom = m.read(MemorySpace::Eeprom, 0, 4).unwrap();
+        assert_eq!(
+            m.changed(&snapshot, MemorySpace::Eeprom, 0, &eeprom),
+            vec![false, true, false, false]
+        );
+        let flash = m.read(MemorySpace::Flash, 0, 2).unwrap();
+        assert_eq!(m.changed(&snapshot, MemorySpace::Flash, 0, &flash), vec![false, true]);
+    }
+
+    #[test]
+    fn test_poke_bounds() {
+        let mut m = memory();
+        m.poke(MemorySpace::Eeprom, 3, 1).unwrap();
+        assert_eq!(m.eeprom[3], 1);
+        assert!(m.poke(MemorySpace::Eeprom, 4, 1).is_err());
+        assert!(m.poke(MemorySpace::Data, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_register_reset_values() {
+        let atdf = device_parser::get_tree_map().get("atmega4809").unwrap();
+        let mut data = DataMemory::default();
+        data.ram.resize(0x1000, 0);
+        data.reset(atdf);
+        // CLKCTRL MCLKCTRLB 0x61, TCA0 PER 0xa26 is 16 bit
+        assert_eq!(data[0x61], 0x11);
+        assert_eq!((data[0xa26], data[0xa27]), (0xff, 0xff));
+        assert_eq!(data[0xa20], 0x00);
+    }
+
+    #[test]
+    fn test_segment_layout() {
+        let atdf = device_parser::get_tree_map().get("atmega4809").unwrap();
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        // no mapped registers, io up to INTERNAL_SRAM at 0x2800
+        assert_eq!((data.io.reg_size, data.len()), (0, 0x4000));
+        assert_eq!(data.mapped(0x1402), Some((MemorySpace::Eeprom, 2)));
+        assert_eq!(data.mapped(0x4010), Some((MemorySpace::Flash, 0x10)));
+        assert_eq!(data.mapped(0x2800), None);
+
+        let atdf = device_parser::get_tree_map().get("atmega328p").unwrap();
+        data.init(atdf).unwrap();
+        assert_eq!((data.io.reg_size, data.len(), data.mapped.len()), (32, 0x900, 0));
+    }
+}
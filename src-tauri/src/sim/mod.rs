@@ -1,12 +1,18 @@
 pub mod constraint;
 pub mod controller;
 mod core;
+pub mod coverage;
+mod debug_info;
 mod display;
 mod gen_comment;
+mod inspector;
 pub mod instruction;
-mod memory;
+pub mod memory;
 pub mod operand;
 pub mod parser;
+pub mod peripherals;
+mod profile;
 mod sim;
 mod timing;
+pub mod trace;
 mod worker;
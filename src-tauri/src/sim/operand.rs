@@ -584,4 +584,5 @@ pub struct OperandInfo {
     pub register_name: String,
     pub register_mask: String,
     pub description: String,
+    pub value_description: String, // bitfields of the written value, when it is known
 }
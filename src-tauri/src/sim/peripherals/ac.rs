@@ -0,0 +1,283 @@
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::adc::BANDGAP;
+use crate::sim::peripherals::analog::AnalogNet;
+use crate::sim::peripherals::{Bit, Context, InstanceMap, Peripheral, read, write};
+use device_parser::AvrDeviceFile;
+use std::any::Any;
+
+const ACD: u8 = 0x80;
+const ACBG: u8 = 0x40;
+const ACO: u8 = 0x20;
+const ACI: u8 = 0x10;
+const ACIE: u8 = 0x08;
+const ACIC: u8 = 0x04;
+const ACIS: u8 = 0x03;
+
+/// Timer1 input capture unit, latches TCNT1 into ICR1 on comparator edges
+#[derive(Debug)]
+struct Capture {
+    tcnt: u32, // low byte, the high byte follows
+    icr: u32,
+    edge: Bit, // ICES1, set captures on rising edges
+    enable: Bit,
+    flag: Bit,
+    vector: Option<u32>,
+    flagged: bool,
+}
+
+impl Capture {
+    fn init(atdf: &'static AvrDeviceFile) -> Option<Capture> {
+        let map = InstanceMap::find(atdf, "TC16")
+            .into_iter()
+            .find(|x| x.instance.name == "TC1")?;
+        Some(Capture {
+            tcnt: map.address("TCNT1")?,
+            icr: map.address("ICR1")?,
+            edge: Bit::find(&map, "ICES1")?,
+            enable: Bit::find(&map, "ICIE1").or_else(|| Bit::find(&map, "TICIE1"))?,
+            flag: Bit::find(&map, "ICF1")?,
+            vector: InstanceMap::named_vector(atdf, &["TIMER1_CAPT", "TIM1_CAPT"]),
+            flagged: false,
+        })
+    }
+
+    fn trigger(&mut self, data: &mut DataMemory) {
+        write(data, self.icr, read(data, self.tcnt));
+        write(data, self.icr + 1, read(data, self.tcnt + 1));
+        self.flagged = true;
+    }
+}
+
+/// ADC registers selecting the negative input while ACME is set
+#[derive(Debug)]
+struct Mux {
+    admux: u32,
+    adcsra: u32,
+    mux5: Option<Bit>, // selects ADC8..15
+}
+
+impl Mux {
+    fn init(atdf: &'static AvrDeviceFile) -> Option<Mux> {
+        let map = InstanceMap::find(atdf, "ADC").into_iter().next()?;
+        Some(Mux {
+            admux: map.address("ADMUX")?,
+            adcsra: map.address("ADCSRA").or_else(|| map.address("ADCSR"))?,
+            mux5: Bit::find(&map, "MUX5"),
+        })
+    }
+
+    /// the comparator only gets the mux while the ADC is disabled
+    fn channel(&self, data: &DataMemory) -> Option<u8> {
+        if read(data, self.adcsra) & 0x80 != 0 {
+            return None;
+        }
+        let high = self.mux5.is_some_and(|x| x.get(data)) as u8;
+        Some((read(data, self.admux) & 0x07) | (high << 3))
+    }
+}
+
+/// classic analog comparator controlled by ACSR
+#[derive(Debug)]
+pub struct Ac {
+    name: &'static str,
+    acsr: u32,
+    acme: Option<Bit>, // ADCSRB or SFIOR
+    mux: Option<Mux>,
+    vector: Option<u32>,
+    capture: Option<Capture>,
+    output: bool,
+    aci: bool,
+}
+
+impl Ac {
+    pub fn new(atdf: &'static AvrDeviceFile, map: &InstanceMap) -> Option<Ac> {
+        // ACSR layouts with several comparators (AC0O, AC1O, ...) are not modelled
+        map.bitfield("ACIS")?;
+        let acme = Bit::find(map, "ACME").or_else(|| {
+            let adc = InstanceMap::find(atdf, "ADC").into_iter().next()?;
+            Bit::find(&adc, "ACME")
+        });
+        Some(Ac {
+            name: map.instance.name,
+            acsr: map.address("ACSR")?,
+            acme,
+            mux: Mux::init(atdf),
+            vector: InstanceMap::named_vector(atdf, &["ANALOG_COMP", "ANA_COMP"]),
+            capture: Capture::init(atdf),
+            output: false,
+            aci: false,
+        })
+    }
+
+    /// AIN1, or the ADC mux channel with ACME set and the ADC disabled
+    fn negative(&self, data: &DataMemory) -> AnalogNet {
+        let channel = self.mux.as_ref().and_then(|x| x.channel(data));
+        match (self.acme.is_some_and(|x| x.get(data)), channel) {
+            (true, Some(channel)) => AnalogNet::Adc(channel),
+            _ => AnalogNet::Ain(1),
+        }
+    }
+}
+
+impl Peripheral for Ac {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn addresses(&self) -> Vec<u32> {
+        let capture = self.capture.as_ref().map(|x| x.flag.address);
+        std::iter::once(self.acsr).chain(capture).collect()
+    }
+
+    fn write(&mut self, _ctx: &mut Context, address: u32, value: u8) {
+        if address == self.acsr && value & ACI != 0 {
+            self.aci = false;
+        }
+        if let Some(capture) = &mut self.capture
+            && address == capture.flag.address
+            && value & capture.flag.mask != 0
+        {
+            capture.flagged = false;
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context, _cycles: u64) {
+        let (data, analog, time) = (&mut *ctx.data, ctx.analog, ctx.time);
+        let acsr = read(data, self.acsr);
+        if acsr & ACD == 0 {
+            let positive = match acsr & ACBG != 0 {
+                true => BANDGAP,
+                false => analog.voltage(AnalogNet::Ain(0), time),
+            };
+            let negative = analog.voltage(self.negative(data), time);
+            let output = positive > negative;
+            if output != self.output {
+                self.aci |= match acsr & ACIS {
+                    0 => true,
+                    2 => !output,
+                    3 => output,
+                    _ => false,
+                };
+                if acsr & ACIC != 0
+                    && let Some(capture) = &mut self.capture
+                    && capture.edge.get(data) == output
+                {
+                    capture.trigger(data);
+                }
+                self.output = output;
+            }
+        }
+
+        let aco = match self.output {
+            true => ACO,
+            false => 0,
+        };
+        let aci = match self.aci {
+            true => ACI,
+            false => 0,
+        };
+        write(data, self.acsr, (acsr & !(ACO | ACI)) | aco | aci);
+        if let Some(capture) = &self.capture {
+            capture.flag.set(data, capture.flagged);
+        }
+    }
+
+    fn pending_interrupts(&self, data: &DataMemory) -> Vec<u32> {
+        let acsr = read(data, self.acsr);
+        let comparator = (self.aci && acsr & ACIE != 0)
+            .then_some(self.vector)
+            .flatten();
+        let capture = self
+            .capture
+            .as_ref()
+            .filter(|x| x.flagged && x.enable.get(data))
+            .and_then(|x| x.vector);
+        comparator.into_iter().chain(capture).collect()
+    }
+
+    fn acknowledge(&mut self, data: &mut DataMemory, vector: u32) {
+        if self.vector == Some(vector) {
+            self.aci = false;
+            write(data, self.acsr, read(data, self.acsr) & !ACI);
+        }
+        if let Some(capture) = &mut self.capture
+            && capture.vector == Some(vector)
+        {
+            capture.flagged = false;
+            capture.flag.set(data, false);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::peripherals::analog::AnalogSource;
+    use crate::sim::peripherals::bench::{Bench, store};
+
+    // ACSR 0x50, ADCSRB 0x7b, ADCSRA 0x7a, ADMUX 0x7c on the atmega328p
+    #[test]
+    fn test_comparator() {
+        let mut bench = Bench::new("atmega328p");
+        let mut ac = Ac::new(bench.atdf, &bench.map("AC")).unwrap();
+        bench
+            .analog
+            .set(AnalogNet::Ain(1), AnalogSource::Constant(2.0));
+
+        bench.data[0x50] = ACIE | 0x03; // rising edge
+        bench
+            .analog
+            .set(AnalogNet::Ain(0), AnalogSource::Constant(3.0));
+        bench.run(&mut ac, &[], 1);
+        assert_eq!(bench.data[0x50] & (ACO | ACI), ACO | ACI);
+        assert_eq!(ac.pending_interrupts(&bench.data), vec![23]);
+        ac.acknowledge(&mut bench.data, 23);
+
+        // falling edges are ignored
+        bench
+            .analog
+            .set(AnalogNet::Ain(0), AnalogSource::Constant(1.0));
+        bench.run(&mut ac, &[], 1);
+        assert_eq!(bench.data[0x50] & (ACO | ACI), 0);
+
+        // bandgap against ADC3 through the mux
+        bench.data[0x50] = ACBG;
+        bench.data[0x7b] = 0x40;
+        bench.data[0x7c] = 0x03;
+        bench
+            .analog
+            .set(AnalogNet::Adc(3), AnalogSource::Constant(0.5));
+        bench.run(&mut ac, &[], 1);
+        assert_eq!(bench.data[0x50] & ACO, ACO);
+        bench.data[0x7a] = 0x80; // ADEN gives the mux back to the ADC, AIN1 is used
+        bench.run(&mut ac, &[], 1);
+        assert_eq!(bench.data[0x50] & ACO, 0);
+    }
+
+    #[test]
+    fn test_input_capture() {
+        let mut bench = Bench::new("atmega328p");
+        let mut ac = Ac::new(bench.atdf, &bench.map("AC")).unwrap();
+
+        // TCNT1 0x84, ICR1 0x86, TIFR1 0x36, TIMSK1 0x6f, TCCR1B 0x81
+        bench.data[0x50] = ACIC;
+        bench.data[0x6f] = 0x20;
+        bench.data[0x81] = 0x40;
+        bench.data[0x84] = 0x34;
+        bench.data[0x85] = 0x12;
+        bench
+            .analog
+            .set(AnalogNet::Ain(0), AnalogSource::Constant(1.0));
+        bench.run(&mut ac, &[], 1);
+        assert_eq!((bench.data[0x86], bench.data[0x87]), (0x34, 0x12));
+        assert_eq!(bench.data[0x36] & 0x20, 0x20);
+        assert_eq!(ac.pending_interrupts(&bench.data), vec![10]);
+
+        bench.run(&mut ac, &[store(0x36, 0x20)], 1);
+        assert!(ac.pending_interrupts(&bench.data).is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::sim::peripherals::InstanceMap;
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::module::{BitField, Register};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// cpu write that set bits no bitfield of the register describes
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReservedWrite {
+    pub register: &'static str,
+    pub address: u32,
+    pub value: u8,
+    pub reserved: u8,
+}
+
+/// what the cpu may do with the bits of one register byte
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Rule {
+    register: &'static str,
+    writable: u8,
+    clear: u8,    // flags cleared by writing a one
+    reserved: u8, // not described, read as zero
+}
+
+impl Rule {
+    fn readonly(&self) -> u8 {
+        !(self.writable | self.clear | self.reserved)
+    }
+
+    /// `byte` of the register, 16 bit registers have a rule per byte
+    fn new(register: &'static Register, bitfields: &[BitField], byte: u64) -> Rule {
+        let mut rule = Rule {
+            register: register.name,
+            reserved: 0xff,
+            ..Rule::default()
+        };
+        for field in bitfields {
+            let mask = (field.mask >> (8 * byte)) as u8;
+            rule.reserved &= !mask;
+            if field.rw == Some("R") || register.rw == Some("R") || is_status(field) {
+                continue;
+            }
+            match is_flag(register, field) {
+                true => rule.clear |= mask,
+                false => rule.writable |= mask,
+            }
+        }
+        rule
+    }
+
+    /// a register that is described more than once, e.g. in the SINGLE and SPLIT mode of TCA
+    fn merge(self, other: Rule) -> Rule {
+        Rule {
+            register: self.register,
+            writable: self.writable | other.writable,
+            clear: (self.clear | other.clear) & !(self.writable | other.writable),
+            reserved: self.reserved & other.reserved,
+        }
+    }
+}
+
+/// interrupt flags are cleared by writing a one, AVRxt keeps them in INTFLAGS
+fn is_flag(register: &Register, field: &BitField) -> bool {
+    if register.name.ends_with("INTFLAGS") {
+        return true;
+    }
+    let caption = field.caption.unwrap_or_default().to_lowercase();
+    register.name != "SREG"
+        && [
+            "interrupt flag",
+            "overflow flag",
+            "compare flag",
+            "capture flag",
+            "transmit complete",
+        ]
+        .iter()
+        .any(|x| caption.contains(x))
+}
+
+/// status bits only the hardware changes, SPIF is cleared by reading SPSR and then SPDR
+fn is_status(field: &BitField) -> bool {
+    let caption = field.caption.unwrap_or_default().to_lowercase();
+    field.name == "SPIF" || caption.contains("collision flag")
+}
+
+// older parts whose datasheets say SBI and CBI write back every bit of the register, clearing
+// the flags that are set; later parts only change the addressed bit
+const WHOLE_BYTE_BIT_WRITES: &[&str] = &[
+    "atmega8", "atmega8a", "atmega16", "atmega16a", "atmega32", "atmega32a", "atmega64", "atmega64a",
+    "atmega128", "atmega128a", "atmega162", "atmega8515", "atmega8535", "attiny11", "attiny12", "attiny15",
+    "attiny26",
+];
+
+/// read only, reserved and write-one-to-clear bits of the i/o registers from the ATDF
+#[derive(Debug, Default)]
+pub struct AccessRules {
+    rules: HashMap<u32, Rule>,
+    pub whole_byte_bit_writes: bool, // SBI and CBI are a read-modify-write of the whole byte
+    pub warnings: bool, // report writes to reserved bits
+    diagnostics: Vec<ReservedWrite>,
+}
+
+impl AccessRules {
+    pub fn init(atdf: &'static AvrDeviceFile) -> AccessRules {
+        let mut rules: HashMap<u32, Rule> = HashMap::new();
+        let maps = atdf
+            .devices
+            .peripherals
+            .iter()
+            .flat_map(|x| InstanceMap::find(atdf, x.name))
+            .filter(|x| x.is_data());
+        for map in maps {
+            for (address, register) in map.registers() {
+                let Some(bitfields) = register.bitfields.filter(|x| !x.is_empty()) else {
+                    continue;
+                };
+                for byte in 0..register.size {
+                    let rule = Rule::new(register, bitfields, byte);
+                    rules
+                        .entry(address + byte as u32)
+                        .and_modify(|x| *x = x.merge(rule))
+                        .or_insert(rule);
+                }
+            }
+        }
+        AccessRules {
+            rules,
+            whole_byte_bit_writes: WHOLE_BYTE_BIT_WRITES.contains(&atdf.devices.name.to_lowercase().as_str()),
+            warnings: false,
+            diagnostics: vec![],
+        }
+    }
+
+    /// value the register holds after the cpu writes `value` over `old`
+    pub fn apply(&mut self, address: u32, old: u8, value: u8) -> u8 {
+        let Some(rule) = self.rules.get(&address) else {
+            return value;
+        };
+        if self.warnings && value & rule.reserved != 0 {
+            self.diagnostics.push(ReservedWrite {
+                register: rule.register,
+                address,
+                value,
+                reserved: value & rule.reserved,
+            });
+        }
+        (value & rule.writable) | (old & rule.readonly()) | (old & rule.clear & !value)
+    }
+
+    /// byte a single bit write drives, flags other than `mask` are written as zero and stay set
+    /// unless the device writes back the whole byte
+    pub fn bit_write(&self, address: u32, old: u8, mask: u8, set: bool) -> u8 {
+        let clear = match self.whole_byte_bit_writes {
+            true => 0,
+            false => self.rules.get(&address).map_or(0, |x| x.clear),
+        };
+        let others = old & !mask & !clear;
+        match set {
+            true => others | mask,
+            false => others,
+        }
+    }
+
+    /// writes to reserved bits since the last call
+    pub fn take_diagnostics(&mut self) -> Vec<ReservedWrite> {
+        std::mem::take(&mut self.diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    #[test]
+    fn test_classic_rules() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut rules = AccessRules::init(atdf);
+        rules.warnings = true;
+        // TIFR1 0x36: ICF1, OCF1B, OCF1A and TOV1 are cleared by a one, bits 7, 6, 4 and 3 are reserved
+        assert_eq!(rules.apply(0x36, 0x27, 0x01), 0x26);
+        assert_eq!(rules.apply(0x36, 0x27, 0x00), 0x27);
+        assert!(rules.take_diagnostics().is_empty());
+        assert_eq!(rules.apply(0x36, 0x00, 0x80), 0x00);
+        assert_eq!(rules.take_diagnostics()[0].register, "TIFR1");
+
+        // SPSR 0x4d: SPIF and WCOL are status bits, SPI2X is writable
+        assert_eq!(rules.apply(0x4d, 0xc0, 0x01), 0xc1);
+        // UCSR0A 0xc0: RXC0, UDRE0, FE0, DOR0 and UPE0 are read only, TXC0 is a flag
+        assert_eq!(rules.apply(0xc0, 0xe0, 0x43), 0xa3);
+        // registers without bitfields are plain memory
+        assert_eq!(rules.apply(0x4e, 0x00, 0xff), 0xff);
+
+        // SBI on TIFR1 leaves OCF1B and TOV1 pending
+        assert!(!rules.whole_byte_bit_writes);
+        let value = rules.bit_write(0x36, 0x05, 0x02, true);
+        assert_eq!(rules.apply(0x36, 0x05, value), 0x05);
+        // the ATmega8 writes the pending ACI of ACSR 0x28 back as a one
+        let rules = AccessRules::init(get_tree_map().get("atmega8").unwrap());
+        assert!(rules.whole_byte_bit_writes);
+        assert_eq!(rules.bit_write(0x28, 0x10, 0x02, true), 0x12);
+    }
+
+    #[test]
+    fn test_avrxt_rules() {
+        let atdf = get_tree_map().get("atmega4809").unwrap();
+        let mut rules = AccessRules::init(atdf);
+        // SBI on VPORTA INTFLAGS 0x03 only clears the addressed flag
+        let value = rules.bit_write(0x03, 0x05, 0x04, true);
+        assert_eq!(value, 0x04);
+        assert_eq!(rules.apply(0x03, 0x05, value), 0x01);
+        // TCA0 INTFLAGS 0xa0b, described by the SINGLE and the SPLIT mode
+        let rule = rules.rules[&0xa0b];
+        assert_eq!(rule.writable, 0);
+        assert_ne!(rule.clear, 0);
+        // TCA0 CNT 0xa20 is a 16 bit register without bitfields
+        assert!(!rules.rules.contains_key(&0xa20));
+    }
+}
@@ -0,0 +1,343 @@
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::analog::{AnalogInputs, AnalogNet};
+use crate::sim::peripherals::{Context, InstanceMap, Peripheral, read, write};
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::device_property_group::PropertyValue;
+use device_parser::r#struct::module::Module;
+use std::any::Any;
+use std::collections::HashMap;
+
+const ADEN: u8 = 0x80;
+const ADSC: u8 = 0x40;
+const ADATE: u8 = 0x20; // ADFR on devices without trigger sources
+const ADIF: u8 = 0x10;
+const ADIE: u8 = 0x08;
+const ADLAR: u8 = 0x20;
+
+pub(crate) const BANDGAP: f64 = 1.1;
+const TEMPERATURE: f64 = 0.314; // sensor output at 25°C
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Input {
+    Net(AnalogNet),
+    Differential(u8, u8, f64), // positive, negative channel and gain
+    Fixed(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reference {
+    Net(AnalogNet),
+    Internal(f64),
+}
+
+/// mux values by their value-group name, e.g. ADC3, ADC_VBG or ADC1_ADC0_10X
+fn parse_input(name: &str) -> Option<Input> {
+    let channel = |x: &str| x.strip_prefix("ADC")?.parse::<u8>().ok();
+    match name {
+        "ADC_GND" => Some(Input::Fixed(0.0)),
+        "ADC_VBG" => Some(Input::Fixed(BANDGAP)),
+        "TEMPSENS" => Some(Input::Fixed(TEMPERATURE)),
+        _ => match name.split('_').collect::<Vec<_>>()[..] {
+            [single] => Some(Input::Net(AnalogNet::Adc(channel(single)?))),
+            [positive, negative, gain] => Some(Input::Differential(
+                channel(positive)?,
+                channel(negative)?,
+                gain.trim_end_matches(['x', 'X']).parse().ok()?,
+            )),
+            _ => None,
+        },
+    }
+}
+
+/// REFS values by their value-group name, e.g. INTERNAL_2_56V_VOLTAGE_REFERENCE
+fn parse_reference(name: &str) -> Option<Reference> {
+    if name.starts_with("AREF") || name.starts_with("EXTERNAL") {
+        return Some(Reference::Net(AnalogNet::Aref));
+    }
+    if name.contains("VCC") || name.contains("AVDD") {
+        return Some(Reference::Net(AnalogNet::Vcc));
+    }
+    let parts: Vec<&str> = name.split('_').collect();
+    parts.windows(2).find_map(|x| {
+        let fraction = x[1].strip_suffix('V')?;
+        let volts: f64 = format!("{}.{}", x[0], fraction).parse().ok()?;
+        Some(Reference::Internal(volts))
+    })
+}
+
+fn values(module: &Module, contains: impl Fn(&str) -> bool) -> Vec<(u8, &'static str)> {
+    module
+        .value_grop
+        .iter()
+        .find(|x| x.values.iter().any(|x| contains(x.name)))
+        .map(|group| {
+            group
+                .values
+                .iter()
+                .filter_map(|x| match x.value {
+                    PropertyValue::Number(value) => Some((value as u8, x.name)),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// classic successive approximation ADC
+#[derive(Debug)]
+pub struct Adc {
+    pub name: &'static str,
+    admux: u32,
+    adcsra: u32,
+    adcsrb: Option<u32>,
+    result: u32, // ADCL, ADCH follows
+    mux5: bool,  // ADCSRB bit 3 extends the mux, e.g. on the atmega2560
+    vector: Option<u32>,
+    inputs: HashMap<u8, Input>,
+    references: [Reference; 4],
+    adif: bool,
+    converting: Option<u64>, // cycles left
+    first: bool,             // the first conversion after enabling takes 25 ADC clocks
+}
+
+impl Adc {
+    pub fn new(atdf: &'static AvrDeviceFile, map: &InstanceMap) -> Option<Adc> {
+        let mut inputs: HashMap<u8, Input> = values(map.module, |x| x == "ADC_GND")
+            .into_iter()
+            .filter_map(|(value, name)| Some((value, parse_input(name)?)))
+            .collect();
+        if inputs.is_empty() {
+            inputs = (0..8).map(|x| (x, Input::Net(AnalogNet::Adc(x)))).collect();
+            inputs.insert(0x0e, Input::Fixed(BANDGAP));
+            inputs.insert(0x0f, Input::Fixed(0.0));
+        }
+        let mut references = [
+            Reference::Net(AnalogNet::Aref),
+            Reference::Net(AnalogNet::Vcc),
+            Reference::Net(AnalogNet::Vcc),
+            Reference::Internal(BANDGAP),
+        ];
+        let names = values(map.module, |x| {
+            x.starts_with("AVCC") || x.starts_with("AREF")
+        });
+        for (value, name) in names {
+            if let (Some(slot), Some(reference)) =
+                (references.get_mut(value as usize), parse_reference(name))
+            {
+                *slot = reference;
+            }
+        }
+        Some(Adc {
+            name: map.instance.name,
+            admux: map.address("ADMUX")?,
+            adcsra: map.address("ADCSRA").or_else(|| map.address("ADCSR"))?,
+            adcsrb: map.address("ADCSRB"),
+            result: map.address("ADC").or_else(|| map.address("ADCW"))?,
+            mux5: map
+                .register("ADCSRB")
+                .and_then(|x| x.bitfields)
+                .is_some_and(|x| x.iter().any(|x| x.name == "MUX5")),
+            vector: map.own_vector(atdf),
+            inputs,
+            references,
+            adif: false,
+            converting: None,
+            first: true,
+        })
+    }
+
+    /// cpu cycles per ADC clock
+    fn prescaler(&self, data: &DataMemory) -> u64 {
+        [2, 2, 4, 8, 16, 32, 64, 128][(read(data, self.adcsra) & 0x07) as usize]
+    }
+
+    fn start(&mut self, data: &DataMemory) {
+        let clocks = match std::mem::replace(&mut self.first, false) {
+            true => 25,
+            false => 13,
+        };
+        self.converting = Some(clocks * self.prescaler(data));
+    }
+
+    fn free_running(&self, data: &DataMemory) -> bool {
+        read(data, self.adcsra) & ADATE != 0
+            && self.adcsrb.is_none_or(|x| read(data, x) & 0x07 == 0)
+    }
+
+    fn convert(&self, data: &mut DataMemory, analog: &AnalogInputs, time: f64) {
+        let admux = read(data, self.admux);
+        let mut mux = admux & 0x1f;
+        if self.mux5 && self.adcsrb.is_some_and(|x| read(data, x) & 0x08 != 0) {
+            mux |= 0x20;
+        }
+        let vref = match self.references[(admux >> 6) as usize] {
+            Reference::Net(net) => analog.voltage(net, time),
+            Reference::Internal(volts) => volts,
+        };
+        let voltage = |channel| analog.voltage(AnalogNet::Adc(channel), time);
+        let single = |vin: f64| (vin * 1024.0 / vref).floor().clamp(0.0, 1023.0) as u16;
+        let value: u16 = match self.inputs.get(&mux) {
+            Some(Input::Net(net)) => single(analog.voltage(*net, time)),
+            Some(Input::Fixed(volts)) => single(*volts),
+            Some(Input::Differential(positive, negative, gain)) => {
+                let value = (voltage(*positive) - voltage(*negative)) * gain * 512.0 / vref;
+                // 10 bit two's complement
+                (value.floor().clamp(-512.0, 511.0) as i16 as u16) & 0x3ff
+            }
+            None => 0,
+        };
+        let value = match admux & ADLAR != 0 {
+            true => value << 6,
+            false => value,
+        };
+        write(data, self.result, value as u8);
+        write(data, self.result + 1, (value >> 8) as u8);
+    }
+}
+
+impl Peripheral for Adc {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn addresses(&self) -> Vec<u32> {
+        vec![self.adcsra]
+    }
+
+    fn write(&mut self, ctx: &mut Context, address: u32, value: u8) {
+        if address != self.adcsra {
+            return;
+        }
+        if value & ADIF != 0 {
+            self.adif = false;
+        }
+        if value & ADEN == 0 {
+            self.converting = None;
+            self.first = true;
+        } else if value & ADSC != 0 && self.converting.is_none() {
+            self.start(ctx.data);
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context, cycles: u64) {
+        let data = &mut *ctx.data;
+        if let Some(left) = self.converting {
+            match left.checked_sub(cycles).filter(|x| *x > 0) {
+                Some(left) => self.converting = Some(left),
+                None => {
+                    self.convert(data, ctx.analog, ctx.time);
+                    self.adif = true;
+                    self.converting = None;
+                    if self.free_running(data) {
+                        self.start(data);
+                    }
+                }
+            }
+        }
+
+        let adcsra = read(data, self.adcsra) & !(ADSC | ADIF);
+        let adsc = match self.converting {
+            Some(_) => ADSC,
+            None => 0,
+        };
+        let adif = match self.adif {
+            true => ADIF,
+            false => 0,
+        };
+        write(data, self.adcsra, adcsra | adsc | adif);
+    }
+
+    fn pending_interrupts(&self, data: &DataMemory) -> Vec<u32> {
+        match self.adif && read(data, self.adcsra) & ADIE != 0 {
+            true => self.vector.into_iter().collect(),
+            false => vec![],
+        }
+    }
+
+    fn acknowledge(&mut self, data: &mut DataMemory, vector: u32) {
+        if self.vector == Some(vector) {
+            self.adif = false;
+            write(data, self.adcsra, read(data, self.adcsra) & !ADIF);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::peripherals::analog::AnalogSource;
+    use crate::sim::peripherals::bench::{Bench, store};
+
+    // ADCL 0x78, ADCH 0x79, ADCSRA 0x7a, ADCSRB 0x7b, ADMUX 0x7c on the atmega328p
+    fn start(adc: &mut Adc, bench: &mut Bench, adcsra: u8) {
+        bench.data[0x7a] = adcsra;
+        bench.run(adc, &[store(0x7a, adcsra)], 0);
+    }
+
+    #[test]
+    fn test_single_conversion() {
+        let mut bench = Bench::new("atmega328p");
+        let mut adc = Adc::new(bench.atdf, &bench.map("ADC")).unwrap();
+        assert_eq!(adc.references[3], Reference::Internal(1.1));
+        assert_eq!(adc.inputs.get(&0x08), Some(&Input::Fixed(TEMPERATURE)));
+        bench
+            .analog
+            .set(AnalogNet::Adc(2), AnalogSource::Constant(2.5));
+
+        bench.data[0x7c] = 0x42; // AVCC, ADC2
+        start(&mut adc, &mut bench, ADEN | ADSC | ADIE | 0x02); // clk/4
+        bench.run(&mut adc, &[], 25 * 4 - 1);
+        assert_eq!(bench.data[0x7a] & (ADSC | ADIF), ADSC);
+        bench.run(&mut adc, &[], 1);
+        assert_eq!(bench.data[0x7a] & (ADSC | ADIF), ADIF);
+        assert_eq!(
+            u16::from_le_bytes([bench.data[0x78], bench.data[0x79]]),
+            512
+        );
+        assert_eq!(adc.pending_interrupts(&bench.data), vec![21]);
+
+        // the second conversion takes 13 clocks, ADIF is cleared by writing a one
+        bench.data[0x7c] = 0x62; // left adjusted
+        start(&mut adc, &mut bench, ADEN | ADSC | ADIF | 0x02);
+        assert!(!adc.adif);
+        bench.run(&mut adc, &[], 13 * 4);
+        assert_eq!((bench.data[0x79], bench.data[0x78]), (0x80, 0x00));
+    }
+
+    #[test]
+    fn test_free_running_and_differential() {
+        let mut bench = Bench::new("atmega2560");
+        let mut adc = Adc::new(bench.atdf, &bench.map("ADC")).unwrap();
+        assert!(adc.mux5);
+        assert_eq!(adc.references[3], Reference::Internal(2.56));
+        bench
+            .analog
+            .set(AnalogNet::Adc(1), AnalogSource::Constant(0.1));
+        bench
+            .analog
+            .set(AnalogNet::Adc(9), AnalogSource::Constant(4.0));
+
+        // ADC1 - ADC0 with 10x gain against AVCC
+        bench.data[0x7c] = 0x49;
+        start(&mut adc, &mut bench, ADEN | ADSC | ADATE);
+        bench.run(&mut adc, &[], 25 * 2);
+        assert_eq!(
+            u16::from_le_bytes([bench.data[0x78], bench.data[0x79]]),
+            102
+        );
+        assert_eq!(bench.data[0x7a] & ADSC, ADSC);
+
+        // ADC9 through MUX5
+        bench.data[0x7b] = 0x08;
+        bench.data[0x7c] = 0x41;
+        bench.run(&mut adc, &[], 13 * 2);
+        assert_eq!(
+            u16::from_le_bytes([bench.data[0x78], bench.data[0x79]]),
+            819
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::Result;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// analog voltage nodes that can be driven from outside
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalogNet {
+    Adc(u8), // ADCn input channel
+    Ain(u8), // AINn comparator input
+    Aref,
+    Vcc, // also AVCC
+}
+
+/// voltage over time, times are in seconds of simulated time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalogSource {
+    Constant(f64),
+    Sine {
+        offset: f64,
+        amplitude: f64,
+        frequency: f64,
+    },
+    Square {
+        low: f64,
+        high: f64,
+        frequency: f64,
+        duty: f64, // 0..1, part of the period spent high
+    },
+    Triangle {
+        low: f64,
+        high: f64,
+        frequency: f64,
+    },
+    Samples(Vec<(f64, f64)>), // (time, volts) sorted by time, each held until the next one
+}
+
+impl AnalogSource {
+    pub fn voltage(&self, time: f64) -> f64 {
+        match self {
+            AnalogSource::Constant(x) => *x,
+            AnalogSource::Sine {
+                offset,
+                amplitude,
+                frequency,
+            } => offset + amplitude * (2.0 * PI * frequency * time).sin(),
+            AnalogSource::Square {
+                low,
+                high,
+                frequency,
+                duty,
+            } => match (time * frequency).fract() < *duty {
+                true => *high,
+                false => *low,
+            },
+            AnalogSource::Triangle {
+                low,
+                high,
+                frequency,
+            } => {
+                let phase = (time * frequency).fract();
+                let ramp = 1.0 - (2.0 * phase - 1.0).abs();
+                low + (high - low) * ramp
+            }
+            AnalogSource::Samples(samples) => {
+                let index = samples.partition_point(|x| x.0 <= time);
+                match index {
+                    0 => samples.first().map(|x| x.1).unwrap_or(0.0),
+                    _ => samples[index - 1].1,
+                }
+            }
+        }
+    }
+
+    /// `time,volts` per line, lines that do not parse (e.g. a header) are skipped
+    pub fn from_csv(text: &str) -> Result<AnalogSource> {
+        let mut samples: Vec<(f64, f64)> = text
+            .lines()
+            .filter_map(|line| {
+                let (time, volts) = line.split_once([',', ';'])?;
+                Some((time.trim().parse().ok()?, volts.trim().parse().ok()?))
+            })
+            .collect();
+        if samples.is_empty() {
+            return Err(anyhow!("no samples in csv"));
+        }
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(AnalogSource::Samples(samples))
+    }
+}
+
+/// sources of every driven net, undriven inputs read 0V and AREF follows VCC
+#[derive(Debug, Default)]
+pub struct AnalogInputs {
+    sources: HashMap<AnalogNet, AnalogSource>,
+}
+
+impl AnalogInputs {
+    pub const DEFAULT_VCC: f64 = 5.0;
+
+    pub fn set(&mut self, net: AnalogNet, source: AnalogSource) {
+        self.sources.insert(net, source);
+    }
+
+    pub fn voltage(&self, net: AnalogNet, time: f64) -> f64 {
+        match self.sources.get(&net) {
+            Some(source) => source.voltage(time),
+            None => match net {
+                AnalogNet::Adc(_) | AnalogNet::Ain(_) => 0.0,
+                AnalogNet::Aref => self.voltage(AnalogNet::Vcc, time),
+                AnalogNet::Vcc => Self::DEFAULT_VCC,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources() {
+        let square = AnalogSource::Square {
+            low: 0.0,
+            high: 5.0,
+            frequency: 10.0,
+            duty: 0.25,
+        };
+        assert_eq!(square.voltage(0.01), 5.0);
+        assert_eq!(square.voltage(0.03), 0.0);
+        let triangle = AnalogSource::Triangle {
+            low: 1.0,
+            high: 3.0,
+            frequency: 1.0,
+        };
+        assert!((triangle.voltage(0.5) - 3.0).abs() < 1e-9);
+        assert!((triangle.voltage(0.25) - 2.0).abs() < 1e-9);
+
+        let csv = AnalogSource::from_csv("time,volts\n0.5,2.0\n0,1.0\n1.0;3.5\n").unwrap();
+        assert_eq!(csv.voltage(0.0), 1.0);
+        assert_eq!(csv.voltage(0.7), 2.0);
+        assert_eq!(csv.voltage(9.0), 3.5);
+        assert!(AnalogSource::from_csv("time,volts").is_err());
+
+        let mut inputs = AnalogInputs::default();
+        assert_eq!(inputs.voltage(AnalogNet::Aref, 0.0), 5.0);
+        inputs.set(AnalogNet::Vcc, AnalogSource::Constant(3.3));
+        assert_eq!(inputs.voltage(AnalogNet::Aref, 0.0), 3.3);
+    }
+}
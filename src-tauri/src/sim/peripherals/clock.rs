@@ -0,0 +1,307 @@
+use crate::sim::memory::{AccessKind, DataMemory, MemAccess};
+use crate::sim::peripherals::{InstanceMap, read, write};
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::device_property_group::PropertyValue;
+
+const CLKPCE: u8 = 0x80;
+const CCP_IOREG: u8 = 0xd8;
+const PEN: u8 = 0x01;
+const MCLKCTRLB_RESET: u8 = 0x11; // 6X prescaler enabled
+const PDIV: [u32; 16] = [2, 4, 8, 16, 32, 64, 1, 1, 6, 10, 12, 24, 48, 1, 1, 1];
+const ULP32K: u32 = 32_768;
+const PROTECTED_CYCLES: u64 = 4;
+
+/// frequency in a fuse value name, e.g. INTRCOSC_8MHZ_6CK_14CK_0MS, 128KHZ or 6MHZ4
+fn parse_frequency(name: &str) -> Option<u32> {
+    name.split('_').find_map(|part| {
+        let (unit, scale) = match part.find("MHZ") {
+            Some(x) => (x, 1_000_000.0),
+            None => (part.find("KHZ")?, 1_000.0),
+        };
+        let fraction = match &part[unit + 3..] {
+            "" => "0",
+            x => x,
+        };
+        let value: f64 = format!("{}.{}", &part[..unit], fraction).parse().ok()?;
+        Some((value * scale) as u32)
+    })
+}
+
+/// name of the value a fuse bitfield is set to
+fn fuse_value(map: &InstanceMap, fuses: &[u8], bitfield: &str) -> Option<&'static str> {
+    let (offset, mask) = map.bitfield(bitfield)?;
+    let value = (fuses.get(offset as usize)? & mask) >> mask.trailing_zeros();
+    let group = map
+        .module
+        .register_group
+        .iter()
+        .flat_map(|x| x.register.iter())
+        .flat_map(|x| x.bitfields.unwrap_or_default())
+        .find(|x| x.name == bitfield)?
+        .values?;
+    map.module
+        .value_grop
+        .iter()
+        .find(|x| x.name == group)?
+        .values
+        .iter()
+        .find(|x| matches!(x.value, PropertyValue::Number(n) if n as u8 == value))
+        .map(|x| x.name)
+}
+
+#[derive(Debug)]
+enum Prescaler {
+    /// classic CLKPR, written within 4 cycles after setting CLKPCE alone
+    Clkpr { address: u32, clkps: u8 },
+    /// AVRxt CLKCTRL, protected by the CCP signature
+    Clkctrl {
+        ccp: u32,
+        mclkctrla: u32,
+        mclkctrlb: u32,
+        clksel: u8,
+        prescaler: u8,
+    },
+    None,
+}
+
+/// system clock: the selected oscillator divided by the prescaler
+#[derive(Debug)]
+pub struct Clock {
+    oscillator: u32, // Hz of the start-up source, OSC20M on AVRxt
+    external: u32,
+    prescaler: Prescaler,
+    window: u64, // cycles left to change the prescaler
+    fuses: Option<Vec<u8>>, // what `init` was given, a reset starts from them again
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock {
+            oscillator: super::Peripherals::DEFAULT_FREQUENCY,
+            external: super::Peripherals::DEFAULT_FREQUENCY,
+            prescaler: Prescaler::None,
+            window: 0,
+            fuses: None,
+        }
+    }
+}
+
+impl Clock {
+    /// without fuses `frequency` is the system clock after reset,
+    /// with them it is the external clock or crystal and CKSEL/CKDIV8 pick the start-up clock
+    pub fn init(
+        atdf: &'static AvrDeviceFile,
+        fuses: Option<&[u8]>,
+        frequency: u32,
+        data: &mut DataMemory,
+    ) -> Clock {
+        let fuse = InstanceMap::find(atdf, "FUSE").into_iter().next();
+        let cpu = InstanceMap::find(atdf, "CPU").into_iter().next();
+        let clkctrl = InstanceMap::find(atdf, "CLKCTRL").into_iter().next();
+
+        let mut clock = Clock {
+            oscillator: frequency,
+            external: frequency,
+            prescaler: Prescaler::None,
+            window: 0,
+            fuses: fuses.map(|x| x.to_vec()),
+        };
+        if let Some(clkctrl) = clkctrl
+            && let Some(ccp) = cpu.as_ref().and_then(|x| x.address("CCP"))
+            && let (Some(mclkctrla), Some(mclkctrlb)) =
+                (clkctrl.address("MCLKCTRLA"), clkctrl.address("MCLKCTRLB"))
+        {
+            let divider = PDIV[(MCLKCTRLB_RESET >> 1) as usize];
+            clock.oscillator = match (fuses, &fuse) {
+                (Some(fuses), Some(fuse)) => fuse_value(fuse, fuses, "FREQSEL")
+                    .and_then(parse_frequency)
+                    .unwrap_or(20_000_000),
+                _ => frequency.saturating_mul(divider),
+            };
+            clock.prescaler = Prescaler::Clkctrl {
+                ccp,
+                mclkctrla,
+                mclkctrlb,
+                clksel: 0,
+                prescaler: MCLKCTRLB_RESET,
+            };
+        } else if let Some(address) = cpu.as_ref().and_then(|x| x.address("CLKPR")) {
+            let mut clkps = 0;
+            if let (Some(fuses), Some(fuse)) = (fuses, &fuse) {
+                let cksel = fuse_value(fuse, fuses, "SUT_CKSEL")
+                    .or_else(|| fuse_value(fuse, fuses, "CKSEL"));
+                if let Some(internal) = cksel
+                    .filter(|x| x.starts_with("INTRCOSC"))
+                    .and_then(parse_frequency)
+                {
+                    clock.oscillator = internal;
+                }
+                // fuses are programmed when 0
+                if let Some((offset, mask)) = fuse.bitfield("CKDIV8")
+                    && fuses.get(offset as usize).is_some_and(|x| x & mask == 0)
+                {
+                    clkps = 3;
+                }
+            }
+            clock.prescaler = Prescaler::Clkpr { address, clkps };
+        }
+        clock.write(data);
+        clock
+    }
+
+    /// a reset reloads the prescaler from the fuses, CLKPS from CKDIV8
+    pub fn reset(&mut self, atdf: &'static AvrDeviceFile, data: &mut DataMemory) {
+        let fuses = self.fuses.take();
+        *self = Clock::init(atdf, fuses.as_deref(), self.external, data);
+    }
+
+    /// false when the device has neither CLKPR nor CLKCTRL
+    pub fn is_modelled(&self) -> bool {
+        !matches!(self.prescaler, Prescaler::None)
+    }
+
+    /// effective cpu clock in Hz
+    pub fn frequency(&self) -> u32 {
+        match &self.prescaler {
+            Prescaler::Clkpr { clkps, .. } => self.oscillator >> clkps,
+            Prescaler::Clkctrl {
+                clksel, prescaler, ..
+            } => {
+                let source = match clksel {
+                    0 => self.oscillator,
+                    1 | 2 => ULP32K,
+                    _ => self.external,
+                };
+                match prescaler & PEN != 0 {
+                    true => source / PDIV[((prescaler >> 1) & 0x0f) as usize],
+                    false => source,
+                }
+            }
+            Prescaler::None => self.oscillator,
+        }
+    }
+
+    fn write(&self, data: &mut DataMemory) {
+        let open = self.window > 0;
+        match self.prescaler {
+            Prescaler::Clkpr { address, clkps } => {
+                let clkpce = if open { CLKPCE } else { 0 };
+                write(data, address, clkpce | clkps);
+            }
+            Prescaler::Clkctrl {
+                mclkctrla,
+                mclkctrlb,
+                clksel,
+                prescaler,
+                ..
+            } => {
+                let clkout = read(data, mclkctrla) & 0x80;
+                write(data, mclkctrla, clkout | clksel);
+                write(data, mclkctrlb, prescaler);
+            }
+            Prescaler::None => {}
+        }
+    }
+
+    pub fn update(&mut self, data: &mut DataMemory, accesses: &[MemAccess], cycles: u64) {
+        for access in accesses.iter().filter(|x| x.kind == AccessKind::Write) {
+            let open = self.window > 0;
+            match &mut self.prescaler {
+                Prescaler::Clkpr { address, clkps } if access.address == *address => {
+                    if access.value == CLKPCE {
+                        self.window = PROTECTED_CYCLES;
+                    } else if open && access.value & CLKPCE == 0 {
+                        // reserved settings are ignored
+                        if access.value & 0x0f <= 8 {
+                            *clkps = access.value & 0x0f;
+                        }
+                        self.window = 0;
+                    }
+                }
+                Prescaler::Clkctrl {
+                    ccp,
+                    mclkctrla,
+                    mclkctrlb,
+                    clksel,
+                    prescaler,
+                } => {
+                    if access.address == *ccp && access.value == CCP_IOREG {
+                        self.window = PROTECTED_CYCLES;
+                    } else if open && access.address == *mclkctrla {
+                        *clksel = access.value & 0x03;
+                    } else if open && access.address == *mclkctrlb {
+                        *prescaler = access.value & 0x1f;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.window = self.window.saturating_sub(cycles);
+        self.write(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    fn store(address: u32, value: u8) -> MemAccess {
+        MemAccess {
+            kind: AccessKind::Write,
+            address,
+            value,
+            width: 1,
+            pc: 0,
+        }
+    }
+
+    #[test]
+    fn test_clkpr_sequence() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        // internal 8 MHz with CKDIV8 programmed
+        let mut clock = Clock::init(atdf, Some(&[0x62, 0xd9, 0xff]), 16_000_000, &mut data);
+        assert_eq!(clock.frequency(), 1_000_000);
+        assert_eq!(data[0x61], 0x03);
+
+        // too late
+        clock.update(&mut data, &[store(0x61, CLKPCE)], 2);
+        clock.update(&mut data, &[], 3);
+        clock.update(&mut data, &[store(0x61, 0x00)], 2);
+        assert_eq!(clock.frequency(), 1_000_000);
+
+        clock.update(&mut data, &[store(0x61, CLKPCE)], 2);
+        assert_eq!(data[0x61], CLKPCE | 0x03);
+        clock.update(&mut data, &[store(0x61, 0x01)], 2);
+        assert_eq!(clock.frequency(), 4_000_000);
+        assert_eq!(data[0x61], 0x01);
+        clock.reset(atdf, &mut data);
+        assert_eq!((clock.frequency(), data[0x61]), (1_000_000, 0x03));
+
+        // external crystal
+        let clock = Clock::init(atdf, Some(&[0xff, 0xd9, 0xff]), 16_000_000, &mut data);
+        assert_eq!(clock.frequency(), 16_000_000);
+        assert_eq!(parse_frequency("INTRCOSC_6MHZ4"), Some(6_400_000));
+    }
+
+    #[test]
+    fn test_clkctrl() {
+        let atdf = get_tree_map().get("atmega4809").unwrap();
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        let mut clock = Clock::init(atdf, Some(&[0, 0, 0x02]), 0, &mut data);
+        assert_eq!(clock.frequency(), 20_000_000 / 6);
+
+        // MCLKCTRLB 0x61 is ignored without the CCP signature
+        clock.update(&mut data, &[store(0x61, 0x00)], 1);
+        assert_eq!(data[0x61], MCLKCTRLB_RESET);
+        clock.update(&mut data, &[store(0x34, CCP_IOREG), store(0x61, 0x00)], 1);
+        assert_eq!(clock.frequency(), 20_000_000);
+        assert_eq!(data[0x61], 0x00);
+
+        let clock = Clock::init(atdf, None, 1_000_000, &mut data);
+        assert_eq!(clock.frequency(), 1_000_000);
+    }
+}
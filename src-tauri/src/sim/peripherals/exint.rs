@@ -0,0 +1,278 @@
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::gpio::Pin;
+use crate::sim::peripherals::{Bit, Context, InstanceMap, Peripheral, read};
+use device_parser::AvrDeviceFile;
+use std::any::Any;
+
+#[derive(Debug)]
+struct External {
+    pin: Pin,
+    sense: Vec<Bit>, // ISCn0, ISCn1; a single bit only selects between falling and rising
+    enable: Bit,
+    flag: Bit,
+    vector: Option<u32>,
+    level: bool,
+    flagged: bool,
+}
+
+impl External {
+    fn sense(&self, data: &DataMemory) -> u8 {
+        match self.sense[..] {
+            [edge] => 2 + edge.get(data) as u8,
+            [bit0, bit1] => bit0.get(data) as u8 | ((bit1.get(data) as u8) << 1),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PinChange {
+    mask: u32, // PCMSKn
+    pins: Vec<(u8, Pin)>, // bit in PCMSKn
+    enable: Bit,
+    flag: Bit,
+    vector: Option<u32>,
+    levels: Vec<bool>,
+    flagged: bool,
+}
+
+/// INTn with EICRA/EICRB (or MCUCR) sensing and the PCINT groups
+#[derive(Debug)]
+pub struct ExInt {
+    name: &'static str,
+    external: Vec<External>,
+    pin_change: Vec<PinChange>,
+}
+
+impl ExInt {
+    pub fn new(atdf: &'static AvrDeviceFile, map: &InstanceMap, ctx: &Context) -> ExInt {
+        let level = |pin| ctx.gpio.level(ctx.data, pin).unwrap_or(false);
+        let mut signals = map.indexed_signals("INT");
+        signals.sort_by_key(|x| x.0);
+        let external = signals
+            .into_iter()
+            .filter_map(|(n, pin)| {
+                let sense = match map.bitfield(&format!("ISC{}", n)) {
+                    Some((address, mask)) => (0..8)
+                        .map(|x| 1u8 << x)
+                        .filter(|x| mask & x != 0)
+                        .map(|mask| Bit { address, mask })
+                        .collect(),
+                    None => vec![
+                        Bit::indexed(map, &format!("ISC{}", n), 0)?,
+                        Bit::indexed(map, &format!("ISC{}", n), 1)?,
+                    ],
+                };
+                Some(External {
+                    pin,
+                    sense,
+                    enable: Bit::indexed(map, "INT", n)?,
+                    flag: Bit::indexed(map, "INTF", n)?,
+                    vector: InstanceMap::named_vector(atdf, &[&format!("INT{}", n)]),
+                    level: level(pin),
+                    flagged: false,
+                })
+            })
+            .collect();
+
+        let signals = map.indexed_signals("PCINT");
+        let pin_change = (0..8)
+            .filter_map(|k| {
+                let mask = map
+                    .address(&format!("PCMSK{}", k))
+                    .or_else(|| map.address("PCMSK").filter(|_| k == 0))?;
+                let pins: Vec<(u8, Pin)> = signals
+                    .iter()
+                    .filter(|(index, _)| index / 8 == k)
+                    .map(|(index, pin)| ((index % 8) as u8, *pin))
+                    .collect();
+                Some(PinChange {
+                    mask,
+                    levels: pins.iter().map(|(_, pin)| level(*pin)).collect(),
+                    pins,
+                    enable: Bit::indexed(map, "PCIE", k)?,
+                    flag: Bit::indexed(map, "PCIF", k)?,
+                    vector: InstanceMap::named_vector(atdf, &[&format!("PCINT{}", k)]),
+                    flagged: false,
+                })
+            })
+            .collect();
+        ExInt {
+            name: map.instance.name,
+            external,
+            pin_change,
+        }
+    }
+}
+
+impl Peripheral for ExInt {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn addresses(&self) -> Vec<u32> {
+        let external = self.external.iter().map(|x| x.flag.address);
+        external
+            .chain(self.pin_change.iter().map(|x| x.flag.address))
+            .collect()
+    }
+
+    /// flags are cleared by writing a one
+    fn write(&mut self, _ctx: &mut Context, address: u32, value: u8) {
+        for x in &mut self.external {
+            if address == x.flag.address && value & x.flag.mask != 0 {
+                x.flagged = false;
+            }
+        }
+        for x in &mut self.pin_change {
+            if address == x.flag.address && value & x.flag.mask != 0 {
+                x.flagged = false;
+            }
+        }
+    }
+
+    /// compares the pin levels against the ones after the previous instruction
+    fn tick(&mut self, ctx: &mut Context, _cycles: u64) {
+        let (data, gpio) = (&mut *ctx.data, ctx.gpio);
+        for x in &mut self.external {
+            let level = gpio.level(data, x.pin).unwrap_or(false);
+            let edge = match x.sense(data) {
+                1 => level != x.level,
+                2 => x.level && !level,
+                3 => !x.level && level,
+                _ => false, // low level is not latched
+            };
+            x.flagged |= edge;
+            x.level = level;
+            x.flag.set(data, x.flagged);
+        }
+        for x in &mut self.pin_change {
+            let mask = read(data, x.mask);
+            for ((bit, pin), previous) in x.pins.iter().zip(x.levels.iter_mut()) {
+                let level = gpio.level(data, *pin).unwrap_or(false);
+                x.flagged |= level != *previous && mask & (1 << bit) != 0;
+                *previous = level;
+            }
+            x.flag.set(data, x.flagged);
+        }
+    }
+
+    fn pending_interrupts(&self, data: &DataMemory) -> Vec<u32> {
+        let external = self
+            .external
+            .iter()
+            .filter(|x| x.enable.get(data) && (x.flagged || (x.sense(data) == 0 && !x.level)));
+        let pin_change = self
+            .pin_change
+            .iter()
+            .filter(|x| x.enable.get(data) && x.flagged);
+        external
+            .filter_map(|x| x.vector)
+            .chain(pin_change.filter_map(|x| x.vector))
+            .collect()
+    }
+
+    fn acknowledge(&mut self, data: &mut DataMemory, vector: u32) {
+        for x in self
+            .external
+            .iter_mut()
+            .filter(|x| x.vector == Some(vector))
+        {
+            x.flagged = false;
+            x.flag.set(data, false);
+        }
+        for x in self.pin_change.iter_mut().filter(|x| x.vector == Some(vector)) {
+            x.flagged = false;
+            x.flag.set(data, false);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::peripherals::bench::{Bench, store};
+    use device_parser::get_tree_map;
+
+    fn setup(mcu: &str) -> (Bench, ExInt) {
+        let mut bench = Bench::new(mcu);
+        let map = bench.map("EXINT");
+        let exint = ExInt::new(bench.atdf, &map, &bench.ctx());
+        (bench, exint)
+    }
+
+    fn drive(bench: &mut Bench, exint: &mut ExInt, pad: &str, level: bool) {
+        bench.drive(pad, Some(level));
+        bench.run(exint, &[], 1);
+    }
+
+    #[test]
+    fn test_int0_edges() {
+        let (mut bench, mut exint) = setup("atmega328p");
+        // EICRA 0x69, EIMSK 0x3d, EIFR 0x3c; INT0 is PD2
+        bench.data[0x69] = 0x03; // rising
+        bench.data[0x3d] = 0x01;
+        drive(&mut bench, &mut exint, "PD2", false);
+        assert!(exint.pending_interrupts(&bench.data).is_empty());
+        drive(&mut bench, &mut exint, "PD2", true);
+        assert_eq!(bench.data[0x3c], 0x01);
+        assert_eq!(exint.pending_interrupts(&bench.data), vec![1]);
+        exint.acknowledge(&mut bench.data, 1);
+        assert_eq!(bench.data[0x3c], 0x00);
+
+        bench.data[0x69] = 0x02; // falling
+        drive(&mut bench, &mut exint, "PD2", false);
+        assert_eq!(exint.pending_interrupts(&bench.data), vec![1]);
+        bench.run(&mut exint, &[store(0x3c, 0x01)], 1);
+        assert!(exint.pending_interrupts(&bench.data).is_empty());
+
+        // low level stays pending while the pin is low
+        bench.data[0x69] = 0x00;
+        bench.run(&mut exint, &[], 1);
+        assert_eq!(exint.pending_interrupts(&bench.data), vec![1]);
+    }
+
+    #[test]
+    fn test_pin_change_groups() {
+        let (mut bench, mut exint) = setup("atmega328p");
+        // PCICR 0x68, PCIFR 0x3b, PCMSK1 0x6c; PC3 is PCINT11
+        bench.data[0x68] = 0x02;
+        bench.data[0x6c] = 0x08;
+        drive(&mut bench, &mut exint, "PC2", true);
+        assert!(exint.pending_interrupts(&bench.data).is_empty());
+        drive(&mut bench, &mut exint, "PC3", true);
+        assert_eq!(bench.data[0x3b], 0x02);
+        assert_eq!(exint.pending_interrupts(&bench.data), vec![4]);
+    }
+
+    #[test]
+    fn test_scheduled_edge() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        let mut peripherals = crate::sim::peripherals::Peripherals::init(atdf, &mut data).unwrap();
+        data[0x69] = 0x0c;
+        data[0x3d] = 0x02; // INT1 on PD3, rising
+        peripherals.gpio.schedule("PD3".parse().unwrap(), Some(true), 100).unwrap();
+        peripherals.update(&mut data, &[], 60);
+        assert_eq!(peripherals.pending_interrupt(&data), None);
+        peripherals.update(&mut data, &[], 60);
+        assert_eq!(peripherals.pending_interrupt(&data), Some(2));
+    }
+
+    #[test]
+    fn test_split_sense_bits() {
+        // GIMSK INT0 0x40 and ISC01/ISC00 in MCUCR
+        let (_, exint) = setup("attiny85");
+        assert_eq!(exint.external.len(), 1);
+        assert_eq!(exint.external[0].enable.mask, 0x40);
+        assert_eq!(exint.external[0].sense.len(), 2);
+        assert_eq!(exint.pin_change[0].enable.mask, 0x20);
+        let (_, exint) = setup("atmega8");
+        assert_eq!(exint.external[1].enable.mask, 0x80);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::error::Result;
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::{Bit, InstanceMap};
+use anyhow::anyhow;
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::device_property_group::PropertyValue;
+use device_parser::r#struct::module::{BitField, Module, Register};
+use serde::Serialize;
+
+/// one bitfield of the fuse or lock bytes with the meaning of its current value
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FuseField {
+    pub register: &'static str, // e.g. HIGH or LOCKBIT
+    pub name: &'static str,
+    pub caption: Option<&'static str>,
+    pub value: u64,
+    pub meaning: Option<&'static str>, // caption of the value in the value group
+    pub options: Vec<&'static str>,    // value names accepted by `Fuses::set`
+}
+
+/// fuse and lock bytes of a device, bits are programmed when 0
+#[derive(Debug, Clone, Default)]
+pub struct Fuses {
+    fuse: Option<InstanceMap>,
+    lock: Option<InstanceMap>,
+    pub bytes: Vec<u8>,
+    pub lock_bits: Vec<u8>,
+    blbset: Option<(Bit, Bit)>, // BLBSET, or RFLB on tiny devices, and SPMEN of SPMCSR
+}
+
+/// registers by their offset in the fuse or lock bytes, AVRxt maps them into data space
+fn registers(map: &InstanceMap) -> impl Iterator<Item = (u32, &'static Register)> + '_ {
+    let base = map.instance.register_group.as_ref().map_or(0, |x| x.offset as u32);
+    map.registers().map(move |(address, register)| (address - base, register))
+}
+
+/// every byte of the instance with the reset value from the ATDF, unprogrammed without one
+fn defaults(map: Option<&InstanceMap>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (offset, register) in map.into_iter().flat_map(registers) {
+        if bytes.len() <= offset as usize {
+            bytes.resize(offset as usize + 1, 0xff);
+        }
+        bytes[offset as usize] = match register.initval {
+            0 => 0xff,
+            x => x as u8,
+        };
+    }
+    bytes
+}
+
+impl Fuses {
+    /// empty `bytes` or `lock_bits` take the defaults of the device
+    pub fn init(atdf: &'static AvrDeviceFile, bytes: &[u8], lock_bits: &[u8]) -> Fuses {
+        let fuse = InstanceMap::find(atdf, "FUSE").into_iter().next();
+        let lock = InstanceMap::find(atdf, "LOCKBIT").into_iter().next();
+        let blbset = ["CPU", "BOOT_LOAD"]
+            .iter()
+            .flat_map(|x| InstanceMap::find(atdf, x))
+            .find_map(|x| {
+                let blbset = Bit::find(&x, "BLBSET").or_else(|| Bit::find(&x, "RFLB"))?;
+                Some((blbset, Bit::find(&x, "SPMEN")?))
+            });
+        let mut fuses = Fuses {
+            bytes: defaults(fuse.as_ref()),
+            lock_bits: defaults(lock.as_ref()),
+            fuse,
+            lock,
+            blbset,
+        };
+        for (stored, target) in [(bytes, &mut fuses.bytes), (lock_bits, &mut fuses.lock_bits)] {
+            stored.iter().zip(target.iter_mut()).for_each(|(x, y)| *y = *x);
+        }
+        fuses
+    }
+
+    /// (lock byte, byte offset, register, bitfield) of every bitfield, fuses first
+    fn bitfields(&self) -> impl Iterator<Item = (bool, u32, &'static Register, &'static BitField)> + '_ {
+        [(false, &self.fuse), (true, &self.lock)]
+            .into_iter()
+            .flat_map(|(lock, map)| map.iter().flat_map(move |x| registers(x).map(move |x| (lock, x))))
+            .flat_map(|(lock, (offset, register))| {
+                register
+                    .bitfields
+                    .unwrap_or_default()
+                    .iter()
+                    .map(move |x| (lock, offset, register, x))
+            })
+    }
+
+    fn byte(&self, lock: bool, offset: u32) -> u8 {
+        let bytes = if lock { &self.lock_bits } else { &self.bytes };
+        bytes.get(offset as usize).copied().unwrap_or(0xff)
+    }
+
+    fn field(&self, name: &str) -> Option<(bool, u32, &'static Register, &'static BitField)> {
+        self.bitfields().find(|(_, _, _, x)| x.name == name)
+    }
+
+    fn module(&self, lock: bool) -> Option<&'static Module> {
+        if lock { &self.lock } else { &self.fuse }.as_ref().map(|x| x.module)
+    }
+
+    pub fn fields(&self) -> Vec<FuseField> {
+        self.bitfields()
+            .map(|(lock, offset, register, field)| {
+                let value = field.extract(self.byte(lock, offset) as u64);
+                let group = field.values.and_then(|x| self.module(lock)?.value_group(x));
+                FuseField {
+                    register: register.name,
+                    name: field.name,
+                    caption: field.caption,
+                    value,
+                    meaning: group.and_then(|x| x.decode(value)).map(|x| x.caption),
+                    options: group.map(|x| x.values.iter().map(|x| x.name).collect()).unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    /// value of the bitfield `name`
+    pub fn value(&self, name: &str) -> Option<u64> {
+        let (lock, offset, _, field) = self.field(name)?;
+        Some(field.extract(self.byte(lock, offset) as u64))
+    }
+
+    /// name of the value the bitfield `name` is set to, e.g. 2V7 for BODLEVEL
+    pub fn value_name(&self, name: &str) -> Option<&'static str> {
+        let (lock, _, _, field) = self.field(name)?;
+        let group = self.module(lock)?.value_group(field.values?)?;
+        group.decode(self.value(name)?).map(|x| x.name)
+    }
+
+    /// single bit fuses like WDTON are active when programmed
+    pub fn is_programmed(&self, name: &str) -> bool {
+        self.value(name) == Some(0)
+    }
+
+    /// sets the bitfield `name` to a value name of its group, e.g. 2V7, or to a number
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let (lock, offset, _, field) = self
+            .field(name)
+            .ok_or(anyhow!("invalid fuse:{}", name))?;
+        let named = field
+            .values
+            .and_then(|x| self.module(lock)?.value_group(x))
+            .and_then(|x| x.values.iter().find(|x| x.name.eq_ignore_ascii_case(value)))
+            .and_then(|x| match x.value {
+                PropertyValue::Number(n) => Some(n),
+                _ => None,
+            });
+        let number = match named {
+            Some(x) => x,
+            None => match value.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => value.parse(),
+            }
+            .map_err(|_| anyhow!("invalid value for {}:{}", name, value))?,
+        };
+        if number >> field.mask.count_ones() != 0 {
+            return Err(anyhow!("value out of range for {}:{}", name, value));
+        }
+        let bytes = if lock { &mut self.lock_bits } else { &mut self.bytes };
+        let byte = bytes
+            .get_mut(offset as usize)
+            .ok_or(anyhow!("invalid fuse byte:{}", offset))?;
+        *byte = field.insert(*byte as u64, number) as u8;
+        Ok(())
+    }
+
+    /// byte address the cpu starts at, the boot section with BOOTRST programmed
+    pub fn reset_vector(&self) -> u32 {
+        if !self.is_programmed("BOOTRST") {
+            return 0;
+        }
+        // e.g. 256W_3F00, a word address
+        self.value_name("BOOTSZ")
+            .and_then(|x| x.split('_').nth(1))
+            .and_then(|x| u32::from_str_radix(x, 16).ok())
+            .map_or(0, |x| x * 2)
+    }
+
+    /// brown-out threshold in volts, None while BOD is disabled
+    pub fn bod_level(&self) -> Option<f64> {
+        // e.g. 2V7 or 4V3
+        let name = self.value_name("BODLEVEL")?;
+        let part = name.split('_').find(|x| x.contains('V'))?;
+        part.replace('V', ".").parse().ok()
+    }
+
+    /// LPM right after setting BLBSET and SPMEN reads the fuse and lock bytes, both bits are cleared
+    pub fn lpm(&self, data: &mut DataMemory, z: u32) -> Option<u8> {
+        let (blbset, spmen) = self.blbset?;
+        if !blbset.get(data) || !spmen.get(data) {
+            return None;
+        }
+        blbset.set(data, false);
+        spmen.set(data, false);
+        Some(self.read_with_blbset(z))
+    }
+
+    /// byte LPM reads with BLBSET: low fuse, lock bits, extended fuse and high fuse at Z 0 to 3
+    fn read_with_blbset(&self, z: u32) -> u8 {
+        match z {
+            0 => self.byte(false, 0),
+            1 => self.byte(true, 0),
+            2 => self.byte(false, 2),
+            3 => self.byte(false, 1),
+            _ => 0xff,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    #[test]
+    fn test_fuses() -> Result<()> {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut fuses = Fuses::init(atdf, &[], &[]);
+        assert_eq!((fuses.bytes.clone(), fuses.lock_bits.clone()), (vec![0x62, 0xd9, 0xff], vec![0xff]));
+        assert!(fuses.is_programmed("CKDIV8"));
+        assert!(!fuses.is_programmed("WDTON"));
+        assert_eq!(fuses.value_name("SUT_CKSEL"), Some("INTRCOSC_8MHZ_6CK_14CK_65MS"));
+        assert_eq!((fuses.reset_vector(), fuses.bod_level()), (0, None));
+
+        fuses.set("BODLEVEL", "2v7")?;
+        fuses.set("BOOTRST", "0")?;
+        fuses.set("BOOTSZ", "0x03")?;
+        fuses.set("LB", "PROG_DISABLED")?;
+        assert_eq!(fuses.bytes, vec![0x62, 0xde, 0xfd]);
+        assert_eq!(fuses.bod_level(), Some(2.7));
+        assert_eq!(fuses.reset_vector(), 0x7e00);
+        assert_eq!(fuses.read_with_blbset(1), 0xfe);
+        assert!(fuses.set("BOOTSZ", "4").is_err());
+        assert!(fuses.set("CKSEL", "0").is_err());
+
+        let field = fuses.fields().into_iter().find(|x| x.name == "BODLEVEL").unwrap();
+        assert_eq!((field.register, field.meaning), ("EXTENDED", Some("Brown-out detection at VCC=2.7 V")));
+        assert_eq!(field.options.len(), 4);
+
+        let stored = Fuses::init(atdf, &fuses.bytes, &[]);
+        assert_eq!(stored.bod_level(), Some(2.7));
+        Ok(())
+    }
+}
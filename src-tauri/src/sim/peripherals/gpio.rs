@@ -0,0 +1,281 @@
+use crate::error::Result;
+use crate::sim::memory::{AccessKind, DataMemory, MemAccess};
+use crate::sim::peripherals::{InstanceMap, read, write};
+use anyhow::anyhow;
+use device_parser::AvrDeviceFile;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// a port pin as named by its pad, e.g. PB2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pin {
+    pub port: char,
+    pub bit: u8,
+}
+
+impl FromStr for Pin {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Pin> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next(), chars.as_str().parse::<u8>()) {
+            (Some('P'), Some(port), Ok(bit)) if port.is_ascii_uppercase() && bit < 8 => {
+                Ok(Pin { port, bit })
+            }
+            _ => Err(anyhow!("invalid pin:{}", s)),
+        }
+    }
+}
+
+// AVRxt DIRSET, DIRCLR, DIRTGL, OUTSET, OUTCLR, OUTTGL
+#[derive(Debug, Clone, Copy)]
+struct Strobes {
+    dir: [u32; 3],
+    out: [u32; 3],
+}
+
+#[derive(Debug, Clone)]
+struct Port {
+    name: char,
+    dir: u32, // DDRx or DIR
+    out: u32, // PORTx or OUT
+    input: u32, // PINx or IN
+    strobes: Option<Strobes>,
+    vport: Option<[u32; 3]>, // AVRxt VPORT DIR, OUT, IN
+    pin_ctrl: Option<u32>, // AVRxt PIN0CTRL, the pull up is enabled per pin there
+    driven: [Option<bool>; 8],
+}
+
+impl Port {
+    fn classic(map: &InstanceMap, name: char) -> Option<Port> {
+        Some(Port {
+            name,
+            dir: map.address(&format!("DDR{}", name))?,
+            out: map.address(&format!("PORT{}", name))?,
+            input: map.address(&format!("PIN{}", name))?,
+            strobes: None,
+            vport: None,
+            pin_ctrl: None,
+            driven: [None; 8],
+        })
+    }
+
+    fn avrxt(map: &InstanceMap, name: char, vports: &[InstanceMap]) -> Option<Port> {
+        let address = |x: &str| map.address(x);
+        Some(Port {
+            name,
+            dir: address("DIR")?,
+            out: address("OUT")?,
+            input: address("IN")?,
+            strobes: Some(Strobes {
+                dir: [address("DIRSET")?, address("DIRCLR")?, address("DIRTGL")?],
+                out: [address("OUTSET")?, address("OUTCLR")?, address("OUTTGL")?],
+            }),
+            vport: vports
+                .iter()
+                .find(|x| x.instance.name.strip_prefix("VPORT") == Some(&name.to_string()))
+                .and_then(|x| Some([x.address("DIR")?, x.address("OUT")?, x.address("IN")?])),
+            pin_ctrl: address("PIN0CTRL"),
+            driven: [None; 8],
+        })
+    }
+
+    fn apply(&self, data: &mut DataMemory, access: &MemAccess) {
+        let value = access.value;
+        let (dir, out) = (read(data, self.dir), read(data, self.out));
+        if access.address == self.input {
+            // writing a one to an input bit toggles the output latch
+            write(data, self.out, out ^ value);
+        }
+        if let Some(strobes) = self.strobes {
+            for (registers, address, current) in [(strobes.dir, self.dir, dir), (strobes.out, self.out, out)] {
+                match registers.iter().position(|x| *x == access.address) {
+                    Some(0) => write(data, address, current | value),
+                    Some(1) => write(data, address, current & !value),
+                    Some(2) => write(data, address, current ^ value),
+                    _ => {}
+                }
+            }
+        }
+        if let Some([vdir, vout, vin]) = self.vport {
+            match access.address {
+                x if x == vdir => write(data, self.dir, value),
+                x if x == vout => write(data, self.out, value),
+                x if x == vin => write(data, self.out, out ^ value),
+                _ => {}
+            }
+        }
+    }
+
+    fn pull_up(&self, data: &DataMemory, bit: u8) -> bool {
+        match self.pin_ctrl {
+            Some(address) => read(data, address + bit as u32) & 0x08 != 0,
+            None => read(data, self.out) & (1 << bit) != 0,
+        }
+    }
+
+    fn level(&self, data: &DataMemory, bit: u8) -> bool {
+        let mask = 1 << bit;
+        match read(data, self.dir) & mask != 0 {
+            true => read(data, self.out) & mask != 0,
+            false => self.driven[bit as usize].unwrap_or_else(|| self.pull_up(data, bit)),
+        }
+    }
+
+    fn refresh(&self, data: &mut DataMemory) {
+        let input = (0..8).fold(0u8, |acc, bit| acc | ((self.level(data, bit) as u8) << bit));
+        write(data, self.input, input);
+        if let Some(strobes) = self.strobes {
+            // the strobe registers read back as DIR and OUT
+            let (dir, out) = (read(data, self.dir), read(data, self.out));
+            strobes.dir.iter().for_each(|x| write(data, *x, dir));
+            strobes.out.iter().for_each(|x| write(data, *x, out));
+        }
+        if let Some([vdir, vout, vin]) = self.vport {
+            write(data, vdir, read(data, self.dir));
+            write(data, vout, read(data, self.out));
+            write(data, vin, input);
+        }
+    }
+}
+
+/// port registers and the level of every pin, inputs can be driven from outside
+#[derive(Debug, Default)]
+pub struct Gpio {
+    ports: Vec<Port>,
+    scheduled: Vec<(u64, Pin, Option<bool>)>, // sorted by cycle
+}
+
+impl Gpio {
+    pub fn init(atdf: &'static AvrDeviceFile) -> Gpio {
+        let vports = InstanceMap::find(atdf, "VPORT");
+        let ports = InstanceMap::find(atdf, "PORT")
+            .iter()
+            .filter_map(|map| {
+                let name = map.instance.name.strip_prefix("PORT")?.chars().next()?;
+                Port::classic(map, name).or_else(|| Port::avrxt(map, name, &vports))
+            })
+            .collect();
+        Gpio {
+            ports,
+            scheduled: vec![],
+        }
+    }
+
+    pub fn has_port(&self, name: char) -> bool {
+        self.ports.iter().any(|x| x.name == name)
+    }
+
+    fn port(&self, name: char) -> Result<&Port> {
+        self.ports
+            .iter()
+            .find(|x| x.name == name)
+            .ok_or(anyhow!("invalid port:{}", name))
+    }
+
+    pub fn update(&self, data: &mut DataMemory, accesses: &[MemAccess]) {
+        for access in accesses.iter().filter(|x| x.kind == AccessKind::Write) {
+            self.ports.iter().for_each(|x| x.apply(data, access));
+        }
+        self.ports.iter().for_each(|x| x.refresh(data));
+    }
+
+    /// drives an input pin from outside, `None` releases it
+    pub fn drive(&mut self, pin: Pin, level: Option<bool>) -> Result<()> {
+        self.port(pin.port)?;
+        let port = self.ports.iter_mut().find(|x| x.name == pin.port).unwrap();
+        port.driven[pin.bit as usize] = level;
+        Ok(())
+    }
+
+    /// drives the pin once the cycle counter reaches `cycle`
+    pub fn schedule(&mut self, pin: Pin, level: Option<bool>, cycle: u64) -> Result<()> {
+        self.port(pin.port)?;
+        let index = self.scheduled.partition_point(|x| x.0 <= cycle);
+        self.scheduled.insert(index, (cycle, pin, level));
+        Ok(())
+    }
+
+    /// applies the scheduled levels that are due at `now`, pins were checked when scheduling
+    pub fn run_schedule(&mut self, now: u64) {
+        let due = self.scheduled.partition_point(|x| x.0 <= now);
+        for (_, pin, level) in self.scheduled.drain(..due).collect::<Vec<_>>() {
+            let _ = self.drive(pin, level);
+        }
+    }
+
+    /// level on the pin, outputs drive their latch and inputs read the external level or the pull up
+    pub fn level(&self, data: &DataMemory, pin: Pin) -> Result<bool> {
+        Ok(self.port(pin.port)?.level(data, pin.bit))
+    }
+
+    pub fn is_output(&self, data: &DataMemory, pin: Pin) -> Result<bool> {
+        Ok(read(data, self.port(pin.port)?.dir) & (1 << pin.bit) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    #[test]
+    fn test_classic_port() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        let mut gpio = Gpio::init(atdf);
+        let pb2: Pin = "PB2".parse().unwrap();
+        // PINB 0x23, DDRB 0x24, PORTB 0x25
+        gpio.drive(pb2, Some(true)).unwrap();
+        gpio.update(&mut data, &[]);
+        assert_eq!(data[0x23], 0x04);
+
+        data[0x24] = 0x04;
+        gpio.update(&mut data, &[]);
+        assert!(!gpio.level(&data, pb2).unwrap());
+        let toggle = MemAccess {
+            kind: AccessKind::Write,
+            address: 0x23,
+            value: 0x04,
+            width: 1,
+            pc: 0,
+        };
+        gpio.update(&mut data, &[toggle]);
+        assert_eq!(data[0x25], 0x04);
+        assert!(gpio.level(&data, pb2).unwrap());
+
+        assert!("PZ9".parse::<Pin>().is_err());
+        assert!(gpio.drive("PA0".parse().unwrap(), None).is_err());
+    }
+
+    #[test]
+    fn test_avrxt_port() {
+        let atdf = get_tree_map().get("atmega4809").unwrap();
+        let mut data = DataMemory::default();
+        data.io.resize(0x1000, 0);
+        let gpio = Gpio::init(atdf);
+        // PORTA 0x400, VPORTA 0x0
+        data[0x405] = 0x03;
+        gpio.update(&mut data, &[MemAccess {
+            kind: AccessKind::Write,
+            address: 0x405,
+            value: 0x03,
+            width: 1,
+            pc: 0,
+        }]);
+        assert_eq!(data[0x404], 0x03);
+
+        data[0x0] = 0x01;
+        gpio.update(&mut data, &[MemAccess {
+            kind: AccessKind::Write,
+            address: 0x0,
+            value: 0x01,
+            width: 1,
+            pc: 0,
+        }]);
+        assert_eq!(data[0x400], 0x01);
+        assert_eq!(data[0x408] & 0x01, 0x01);
+        assert_eq!(data[0x2], data[0x408]);
+    }
+}
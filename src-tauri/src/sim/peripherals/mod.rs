@@ -0,0 +1,657 @@
+pub mod ac;
+pub mod access;
+pub mod adc;
+pub mod analog;
+pub mod clock;
+pub mod exint;
+pub mod fuse;
+pub mod gpio;
+pub mod pinout;
+pub mod power;
+pub mod signature;
+pub mod spi;
+pub mod twi;
+pub mod watchdog;
+
+use crate::error::Result;
+use crate::sim::memory::{DataMemory, MemAccess};
+use ac::Ac;
+use access::AccessRules;
+use adc::Adc;
+use analog::AnalogInputs;
+use clock::Clock;
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::device_peripherals::Instance;
+use device_parser::r#struct::module::{Module, Register};
+use analog::AnalogNet;
+use exint::ExInt;
+use fuse::Fuses;
+use gpio::{Gpio, Pin};
+use power::Power;
+use serde::Serialize;
+use signature::Signature;
+use spi::Spi;
+use std::any::Any;
+use std::collections::HashMap;
+use twi::Twi;
+use watchdog::Watchdog;
+
+/// data space addresses of the registers of one module instance
+#[derive(Debug, Clone)]
+pub struct InstanceMap {
+    pub instance: &'static Instance,
+    pub module: &'static Module,
+    registers: HashMap<&'static str, u32>,
+}
+
+impl InstanceMap {
+    /// every instance of the module `module` that has registers
+    pub fn find(atdf: &'static AvrDeviceFile, module: &str) -> Vec<InstanceMap> {
+        let Some(device_module) = atdf.devices.peripherals.iter().find(|x| x.name == module) else {
+            return vec![];
+        };
+        let Some(module) = atdf.modules.iter().find(|x| x.name == module) else {
+            return vec![];
+        };
+        device_module
+            .instances
+            .iter()
+            .filter_map(|instance| {
+                let group = instance.register_group.as_ref()?;
+                // classic devices use absolute register offsets with an instance offset of 0
+                let registers = module
+                    .register_group
+                    .iter()
+                    .find(|x| x.name == group.name_in_module)?
+                    .register
+                    .iter()
+                    .map(|x| (x.name, (group.offset + x.offset) as u32))
+                    .collect();
+                Some(InstanceMap {
+                    instance,
+                    module,
+                    registers,
+                })
+            })
+            .collect()
+    }
+
+    /// false for instances in the fuse, lockbit or signature space of classic devices
+    pub fn is_data(&self) -> bool {
+        self.instance
+            .register_group
+            .as_ref()
+            .is_some_and(|x| x.address_space == "data")
+    }
+
+    /// data addresses of every register of the instance
+    pub fn addresses(&self) -> impl Iterator<Item = u32> + '_ {
+        self.registers.values().copied()
+    }
+
+    /// registers of the instance with their data addresses
+    pub fn registers(&self) -> impl Iterator<Item = (u32, &'static Register)> + '_ {
+        self.registers
+            .iter()
+            .filter_map(|(name, address)| Some((*address, self.register(name)?)))
+    }
+
+    pub fn address(&self, register: &str) -> Option<u32> {
+        self.registers.get(register).copied()
+    }
+
+    /// register description from the module, offsets are relative to the instance
+    pub fn register(&self, name: &str) -> Option<&'static Register> {
+        let group = self.instance.register_group.as_ref()?;
+        self.module
+            .register_group
+            .iter()
+            .find(|x| x.name == group.name_in_module)?
+            .register
+            .iter()
+            .find(|x| x.name == name)
+    }
+
+    /// data address and mask of a bitfield in any register of the instance
+    pub fn bitfield(&self, name: &str) -> Option<(u32, u8)> {
+        let group = self.instance.register_group.as_ref()?;
+        self.module
+            .register_group
+            .iter()
+            .find(|x| x.name == group.name_in_module)?
+            .register
+            .iter()
+            .find_map(|register| {
+                let field = register.bitfields?.iter().find(|x| x.name == name)?;
+                Some(((group.offset + register.offset) as u32, field.mask as u8))
+            })
+    }
+
+    /// signals of `group` by their index, e.g. INT or PCINT
+    pub fn indexed_signals(&self, group: &str) -> Vec<(u32, Pin)> {
+        self.instance
+            .signals
+            .unwrap_or_default()
+            .iter()
+            .filter(|x| x.group == group)
+            .filter_map(|x| Some((x.index? as u32, x.pad.parse().ok()?)))
+            .collect()
+    }
+
+    /// for classic names that carry an instance suffix, e.g. SPCR0
+    pub fn address_prefixed(&self, prefix: &str) -> Option<u32> {
+        self.address(prefix).or_else(|| {
+            self.registers
+                .iter()
+                .find(|(name, _)| {
+                    name.strip_prefix(prefix)
+                        .is_some_and(|x| x.chars().all(|c| c.is_ascii_digit()))
+                })
+                .map(|(_, address)| *address)
+        })
+    }
+
+    /// pad of the default signal in `group`, alternate pin locations are skipped
+    pub fn signal(&self, group: &str) -> Option<Pin> {
+        self.instance
+            .signals?
+            .iter()
+            .filter(|x| x.group == group)
+            .find(|x| !x.function.is_some_and(|x| x.contains("ALT")))
+            .and_then(|x| x.pad.parse().ok())
+    }
+
+    /// vector index of the interrupt `name`, e.g. STC for SPI_STC or INT for SPI0 INT
+    pub fn vector(&self, atdf: &'static AvrDeviceFile, name: &str) -> Option<u32> {
+        let prefixed = format!("{}_{}", self.instance.name, name);
+        atdf.devices
+            .interrupts
+            .iter()
+            .find(|x| match x.module_instance {
+                Some(instance) => instance == self.instance.name && x.name == name,
+                None => x.name == prefixed,
+            })
+            .map(|x| x.index as u32)
+    }
+
+    /// vector of the interrupt named after the instance, e.g. TWI
+    pub fn own_vector(&self, atdf: &'static AvrDeviceFile) -> Option<u32> {
+        Self::named_vector(atdf, &[self.instance.name])
+    }
+
+    /// vector of the first interrupt called one of `names`, e.g. ANALOG_COMP or ANA_COMP
+    pub fn named_vector(atdf: &'static AvrDeviceFile, names: &[&str]) -> Option<u32> {
+        atdf.devices
+            .interrupts
+            .iter()
+            .find(|x| names.contains(&x.name))
+            .map(|x| x.index as u32)
+    }
+}
+
+pub(crate) fn read(data: &DataMemory, address: u32) -> u8 {
+    match (address as usize) < data.len() {
+        true => data[address as usize],
+        false => 0,
+    }
+}
+
+pub(crate) fn write(data: &mut DataMemory, address: u32, value: u8) {
+    if let Some(x) = data.get_mut(address as usize) {
+        *x = value;
+    }
+}
+
+/// one bit of a register
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Bit {
+    pub address: u32,
+    pub mask: u8,
+}
+
+impl Bit {
+    pub fn get(&self, data: &DataMemory) -> bool {
+        read(data, self.address) & self.mask != 0
+    }
+
+    pub fn set(&self, data: &mut DataMemory, value: bool) {
+        let current = read(data, self.address) & !self.mask;
+        write(data, self.address, current | if value { self.mask } else { 0 });
+    }
+
+    pub fn find(map: &InstanceMap, name: &str) -> Option<Bit> {
+        let (address, mask) = map.bitfield(name)?;
+        Some(Bit { address, mask })
+    }
+
+    /// bit `n` of a bitfield named after its bits (INT0) or covering all of them (INT)
+    pub fn indexed(map: &InstanceMap, base: &str, n: u32) -> Option<Bit> {
+        if let Some((address, mask)) = map.bitfield(&format!("{}{}", base, n)) {
+            return Some(Bit { address, mask });
+        }
+        let (address, mask) = map.bitfield(base)?;
+        let bit = 1u8.checked_shl(mask.trailing_zeros() + n)?;
+        (mask & bit != 0).then_some(Bit { address, mask: bit })
+    }
+}
+
+/// what a model sees of the rest of the chip
+pub struct Context<'a> {
+    pub data: &'a mut DataMemory,
+    pub gpio: &'a Gpio,
+    pub analog: &'a AnalogInputs,
+    pub time: f64, // seconds of simulated time
+}
+
+/// reset sources besides power-on, each sets its flag in MCUSR
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResetCause {
+    BrownOut,
+    Watchdog,
+}
+
+impl ResetCause {
+    pub fn flag(&self) -> &'static str {
+        match self {
+            ResetCause::BrownOut => "BORF",
+            ResetCause::Watchdog => "WDRF",
+        }
+    }
+}
+
+/// model of one module instance of the ATDF, its registers live in data memory
+pub trait Peripheral: std::fmt::Debug + Send {
+    /// instance name, e.g. SPI0
+    fn name(&self) -> &'static str;
+    /// data addresses whose accesses are dispatched to the model
+    fn addresses(&self) -> Vec<u32>;
+    /// the value the cpu reads from `address`
+    fn read(&mut self, ctx: &mut Context, address: u32) -> u8 {
+        read(ctx.data, address)
+    }
+    /// the cpu wrote `value` to `address`, data memory already holds it
+    fn write(&mut self, _ctx: &mut Context, _address: u32, _value: u8) {}
+    /// advances the model by `cycles`, after the accesses of an instruction
+    fn tick(&mut self, ctx: &mut Context, cycles: u64);
+    fn pending_interrupts(&self, data: &DataMemory) -> Vec<u32>;
+    /// the cpu jumped to `vector`, flags cleared by hardware on entry are cleared here
+    fn acknowledge(&mut self, _data: &mut DataMemory, _vector: u32) {}
+    /// a reset the model caused since the last call
+    fn take_reset(&mut self) -> Option<ResetCause> {
+        None
+    }
+    /// the device was reset, data memory holds the reset values again
+    fn reset(&mut self, _ctx: &mut Context) {}
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// model for an instance of a module of `Device::peripherals`, None leaves it plain memory
+fn create(
+    atdf: &'static AvrDeviceFile,
+    map: &InstanceMap,
+    ctx: &Context,
+) -> Option<Box<dyn Peripheral>> {
+    Some(match map.module.name {
+        "EXINT" => Box::new(ExInt::new(atdf, map, ctx)),
+        "SPI" => Box::new(Spi::new(atdf, map)?),
+        "TWI" => Box::new(Twi::new(atdf, map)?),
+        "ADC" => Box::new(Adc::new(atdf, map)?),
+        "AC" => Box::new(Ac::new(atdf, map)?),
+        "WDT" => Box::new(Watchdog::new(atdf, map)?),
+        _ => return None,
+    })
+}
+
+/// the model of the instance `name` if it is a `T`
+pub fn find<'a, T: Peripheral + 'static>(
+    models: &'a mut [Box<dyn Peripheral>],
+    name: &str,
+) -> Option<&'a mut T> {
+    models
+        .iter_mut()
+        .filter(|x| x.name() == name)
+        .find_map(|x| x.as_any_mut().downcast_mut::<T>())
+}
+
+/// whether a module instance of the selected mcu has a model
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleSupport {
+    pub module: &'static str,
+    pub instance: &'static str,
+    pub simulated: bool,
+}
+
+/// simulated on chip peripherals, updated after every instruction
+#[derive(Debug, Default)]
+pub struct Peripherals {
+    pub gpio: Gpio,
+    pub models: Vec<Box<dyn Peripheral>>,
+    routes: HashMap<u32, Vec<usize>>, // data address to the models listening to it
+    pub analog: AnalogInputs,
+    pub clock: Clock,
+    pub power: Power,
+    pub access: AccessRules,
+    pub fuses: Fuses,
+    pub signature: Signature,
+    brownout: bool, // VCC is below the BODLEVEL threshold
+    reset: Option<ResetCause>,
+    cycles: u64,
+    time: f64, // seconds, advanced at the effective clock
+}
+
+impl Peripherals {
+    pub const DEFAULT_FREQUENCY: u32 = 1_000_000;
+
+    pub fn init(atdf: &'static AvrDeviceFile, data: &mut DataMemory) -> Result<Peripherals> {
+        let gpio = Gpio::init(atdf);
+        gpio.update(data, &[]);
+        let analog = AnalogInputs::default();
+        let clock = Clock::init(atdf, None, Self::DEFAULT_FREQUENCY, data);
+        let ctx = Context {
+            data,
+            gpio: &gpio,
+            analog: &analog,
+            time: 0.0,
+        };
+        let models: Vec<Box<dyn Peripheral>> = atdf
+            .devices
+            .peripherals
+            .iter()
+            .flat_map(|x| InstanceMap::find(atdf, x.name))
+            .filter_map(|map| create(atdf, &map, &ctx))
+            .collect();
+        let mut routes: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, model) in models.iter().enumerate() {
+            for address in model.addresses() {
+                routes.entry(address).or_default().push(index);
+            }
+        }
+        let mut peripherals = Peripherals {
+            gpio,
+            models,
+            routes,
+            analog,
+            clock,
+            power: Power::init(atdf),
+            access: AccessRules::init(atdf),
+            fuses: Fuses::default(),
+            signature: Signature::init(atdf),
+            brownout: false,
+            reset: None,
+            cycles: 0,
+            time: 0.0,
+        };
+        peripherals.set_fuses(Fuses::init(atdf, &[], &[]));
+        peripherals.signature.load(data);
+        Ok(peripherals)
+    }
+
+    /// WDTON keeps the watchdog on, BODLEVEL is checked on every update;
+    /// the clock and the reset vector are taken from the fuses by the simulator
+    pub fn set_fuses(&mut self, fuses: Fuses) {
+        if let Some(wdt) = self.models.iter_mut().find_map(|x| x.as_any_mut().downcast_mut::<Watchdog>()) {
+            wdt.always_on = fuses.is_programmed("WDTON");
+        }
+        self.fuses = fuses;
+    }
+
+    /// WDR
+    pub fn watchdog_restart(&mut self) {
+        let time = self.time;
+        if let Some(wdt) = self.models.iter_mut().find_map(|x| x.as_any_mut().downcast_mut::<Watchdog>()) {
+            wdt.restart(time);
+        }
+    }
+
+    /// LPM after BLBSET or SIGRD reads the fuses or the signature row instead of flash
+    pub fn lpm(&self, data: &mut DataMemory, z: u32) -> Option<u8> {
+        self.fuses.lpm(data, z).or_else(|| self.signature.lpm(data, z))
+    }
+
+    /// a reset since the last call, the simulator restarts the cpu
+    pub fn take_reset(&mut self) -> Option<ResetCause> {
+        self.reset.take()
+    }
+
+    /// lets the models follow a reset of the data memory
+    pub fn reset(&mut self, atdf: &'static AvrDeviceFile, data: &mut DataMemory) {
+        self.clock.reset(atdf, data);
+        self.signature.load(data);
+        let mut ctx = Context {
+            data,
+            gpio: &self.gpio,
+            analog: &self.analog,
+            time: self.time,
+        };
+        for model in &mut self.models {
+            model.reset(&mut ctx);
+        }
+        self.gpio.update(ctx.data, &[]);
+    }
+
+    /// every module instance of the device and whether it is modelled or plain memory
+    pub fn support(&self, atdf: &'static AvrDeviceFile) -> Vec<ModuleSupport> {
+        atdf.devices
+            .peripherals
+            .iter()
+            .flat_map(|module| module.instances.iter().map(move |x| (module.name, x.name)))
+            .map(|(module, instance)| ModuleSupport {
+                module,
+                instance,
+                simulated: match module {
+                    "PORT" | "VPORT" => instance
+                        .strip_prefix(module)
+                        .and_then(|x| x.chars().next())
+                        .is_some_and(|x| self.gpio.has_port(x)),
+                    "CPU" | "CLKCTRL" => self.clock.is_modelled() || self.power.is_modelled(),
+                    "FUSE" | "LOCKBIT" | "SIGROW" => true,
+                    _ => self.models.iter().any(|x| x.name() == instance),
+                },
+            })
+            .collect()
+    }
+
+    /// reacts to the accesses of the last instruction and advances by `cycles`
+    pub fn update(&mut self, data: &mut DataMemory, accesses: &[MemAccess], cycles: u64) {
+        self.cycles += cycles;
+        self.time += cycles as f64 / self.clock.frequency().max(1) as f64;
+        self.clock.update(data, accesses, cycles);
+        self.power.update(data, accesses);
+        self.gpio.run_schedule(self.cycles);
+        self.gpio.update(data, accesses);
+
+        let mut ctx = Context {
+            data,
+            gpio: &self.gpio,
+            analog: &self.analog,
+            time: self.time,
+        };
+        let power = &self.power;
+        for model in self.models.iter_mut().filter(|x| !power.is_gated(x.name())) {
+            model.tick(&mut ctx, cycles);
+            if let Some(reset) = model.take_reset() {
+                self.reset = Some(reset);
+            }
+        }
+
+        if let Some(level) = self.fuses.bod_level() {
+            let low = self.analog.voltage(AnalogNet::Vcc, self.time) < level;
+            if low && !self.brownout {
+                self.reset = Some(ResetCause::BrownOut);
+            }
+            self.brownout = low;
+        }
+    }
+
+    /// data bus read, the models listening to `address` see it when it happens
+    pub fn read(&mut self, data: &mut DataMemory, address: u32) -> u8 {
+        let mut ctx = Context {
+            data,
+            gpio: &self.gpio,
+            analog: &self.analog,
+            time: self.time,
+        };
+        let mut value = read(ctx.data, address);
+        for index in self.routes.get(&address).into_iter().flatten() {
+            let model = &mut self.models[*index];
+            if !self.power.is_gated(model.name()) {
+                value = model.read(&mut ctx, address);
+            }
+        }
+        value
+    }
+
+    /// data bus write, memory holds the stored value before the models see the written one
+    pub fn write(&mut self, data: &mut DataMemory, address: u32, value: u8) {
+        let stored = self.access.apply(address, read(data, address), value);
+        write(data, address, stored);
+        self.notify(data, address, value);
+    }
+
+    /// debugger write, stored as is without the access rules but still seen by the models
+    pub fn poke(&mut self, data: &mut DataMemory, address: u32, value: u8) {
+        write(data, address, value);
+        self.notify(data, address, value);
+    }
+
+    fn notify(&mut self, data: &mut DataMemory, address: u32, value: u8) {
+        let mut ctx = Context {
+            data,
+            gpio: &self.gpio,
+            analog: &self.analog,
+            time: self.time,
+        };
+        for index in self.routes.get(&address).into_iter().flatten() {
+            let model = &mut self.models[*index];
+            if !self.power.is_gated(model.name()) {
+                model.write(&mut ctx, address, value);
+            }
+        }
+    }
+
+    /// the pending vector with the highest priority, i.e. the lowest index
+    pub fn pending_interrupt(&self, data: &DataMemory) -> Option<u32> {
+        self.models
+            .iter()
+            .flat_map(|x| x.pending_interrupts(data))
+            .min()
+    }
+
+    pub fn acknowledge(&mut self, data: &mut DataMemory, vector: u32) {
+        for model in &mut self.models {
+            model.acknowledge(data, vector);
+        }
+    }
+}
+
+/// one model on the data memory of a device, without the rest of the chip
+#[cfg(test)]
+pub(crate) mod bench {
+    use super::*;
+    use crate::sim::memory::AccessKind;
+    use device_parser::get_tree_map;
+
+    pub struct Bench {
+        pub atdf: &'static AvrDeviceFile,
+        pub data: DataMemory,
+        pub gpio: Gpio,
+        pub analog: AnalogInputs,
+        pub time: f64,
+    }
+
+    impl Bench {
+        pub fn new(mcu: &str) -> Bench {
+            let atdf = get_tree_map().get(mcu).unwrap();
+            let mut data = DataMemory::default();
+            data.init(atdf).unwrap();
+            Bench {
+                atdf,
+                data,
+                gpio: Gpio::init(atdf),
+                analog: AnalogInputs::default(),
+                time: 0.0,
+            }
+        }
+
+        pub fn map(&self, module: &str) -> InstanceMap {
+            InstanceMap::find(self.atdf, module).remove(0)
+        }
+
+        pub fn ctx(&mut self) -> Context<'_> {
+            Context {
+                data: &mut self.data,
+                gpio: &self.gpio,
+                analog: &self.analog,
+                time: self.time,
+            }
+        }
+
+        /// drives the pin and lets the port registers follow
+        pub fn drive(&mut self, pad: &str, level: Option<bool>) {
+            self.gpio.drive(pad.parse().unwrap(), level).unwrap();
+            self.gpio.update(&mut self.data, &[]);
+        }
+
+        /// dispatches the accesses of one instruction to `model` and advances it
+        pub fn run(&mut self, model: &mut dyn Peripheral, accesses: &[MemAccess], cycles: u64) {
+            let addresses = model.addresses();
+            let mut ctx = self.ctx();
+            for access in accesses.iter().filter(|x| addresses.contains(&x.address)) {
+                match access.kind {
+                    AccessKind::Read => {
+                        model.read(&mut ctx, access.address);
+                    }
+                    AccessKind::Write => model.write(&mut ctx, access.address, access.value),
+                }
+            }
+            model.tick(&mut ctx, cycles);
+        }
+    }
+
+    pub fn load(address: u32, value: u8) -> MemAccess {
+        MemAccess {
+            kind: AccessKind::Read,
+            address,
+            value,
+            width: 1,
+            pc: 0,
+        }
+    }
+
+    pub fn store(address: u32, value: u8) -> MemAccess {
+        MemAccess {
+            kind: AccessKind::Write,
+            address,
+            value,
+            width: 1,
+            pc: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    #[test]
+    fn test_support() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        let peripherals = Peripherals::init(atdf, &mut data).unwrap();
+        let support = peripherals.support(atdf);
+        let simulated = |name: &str| {
+            support
+                .iter()
+                .find(|x| x.instance == name)
+                .unwrap()
+                .simulated
+        };
+        assert!(simulated("SPI") && simulated("TWI") && simulated("EXINT") && simulated("PORTB"));
+        assert!(!simulated("USART0") && simulated("WDT") && simulated("FUSE"));
+        assert_eq!(peripherals.models.len(), 6);
+    }
+}
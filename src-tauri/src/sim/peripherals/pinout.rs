@@ -0,0 +1,157 @@
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::gpio::{Gpio, Pin};
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::device_peripherals::Signal;
+use serde::Serialize;
+
+/// a peripheral signal routed to a pad, e.g. OC0A of TC0
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinFunction {
+    pub name: String,
+    pub instance: &'static str,
+    pub alternate: bool, // only routed here through PORTMUX, e.g. CCL_ALT1
+}
+
+/// a physical pin of a package with its port bit and functions
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackagePin {
+    pub position: &'static str,
+    pub pad: &'static str, // PB0, VCC, AREF...
+    pub pin: Option<Pin>,  // None for supply and analog only pads
+    pub functions: Vec<PinFunction>,
+    pub output: Option<bool>,
+    pub level: Option<bool>,
+}
+
+/// one pinout of the device and the variants sold in it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Package {
+    pub name: &'static str,
+    pub caption: Option<&'static str>,
+    pub variants: Vec<&'static str>, // order codes
+    pub pins: Vec<PackagePin>,
+}
+
+/// trailing instance number, e.g. 0 for USART0
+fn instance_number(instance: &str) -> &str {
+    let digits = instance.trim_end_matches(|c: char| c.is_ascii_digit());
+    &instance[digits.len()..]
+}
+
+/// datasheet name of a signal: ADC3 and INT0 from their index, OC0A and TXD0 from the instance
+fn function_name(instance: &str, signal: &Signal) -> String {
+    if let Some(index) = signal.index {
+        return format!("{}{}", signal.group, index);
+    }
+    let number = instance_number(instance);
+    match signal.group.strip_prefix("OC") {
+        Some(channel) if channel.len() == 1 => format!("OC{}{}", number, channel),
+        _ if signal.group.ends_with(|c: char| c.is_ascii_digit()) => signal.group.to_string(),
+        _ => format!("{}{}", signal.group, number),
+    }
+}
+
+/// every peripheral signal on `pad`, the port bit itself is left out
+pub fn functions(atdf: &'static AvrDeviceFile, pad: &str) -> Vec<PinFunction> {
+    atdf.devices
+        .peripherals
+        .iter()
+        .filter(|x| x.name != "PORT")
+        .flat_map(|x| x.instances.iter())
+        .flat_map(|instance| {
+            instance
+                .signals
+                .unwrap_or_default()
+                .iter()
+                .filter(move |x| x.pad == pad)
+                .map(move |x| PinFunction {
+                    name: function_name(instance.name, x),
+                    instance: instance.name,
+                    alternate: x.function.is_some_and(|x| x.contains("ALT")),
+                })
+        })
+        .collect()
+}
+
+impl Package {
+    /// every pinout of the device, pins in the order of their position
+    pub fn all(atdf: &'static AvrDeviceFile) -> Vec<Package> {
+        atdf.pinouts
+            .unwrap_or_default()
+            .iter()
+            .map(|pinout| Package {
+                name: pinout.name,
+                caption: pinout.caption,
+                variants: atdf
+                    .variants
+                    .iter()
+                    .filter(|x| x.pinout == Some(pinout.name))
+                    .map(|x| x.order_code)
+                    .collect(),
+                pins: pinout
+                    .pins
+                    .iter()
+                    .map(|x| PackagePin {
+                        position: x.position,
+                        pad: x.pad,
+                        pin: x.pad.parse().ok(),
+                        functions: functions(atdf, x.pad),
+                        output: None,
+                        level: None,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// direction and level of every port pin the gpio model knows
+    pub fn update(&mut self, gpio: &Gpio, data: &DataMemory) {
+        for x in self.pins.iter_mut() {
+            let Some(pin) = x.pin else { continue };
+            x.output = gpio.is_output(data, pin).ok();
+            x.level = gpio.level(data, pin).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    #[test]
+    fn test_packages() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut packages = Package::all(atdf);
+        let names: Vec<&str> = packages.iter().map(|x| x.name).collect();
+        assert!(names.contains(&"PDIP28") && names.contains(&"TQFP32"));
+        let pdip = packages.iter_mut().find(|x| x.name == "PDIP28").unwrap();
+        assert!(pdip.variants.contains(&"ATmega328P-PU"));
+        assert_eq!(pdip.pins.len(), 28);
+
+        let pd6 = pdip.pins.iter().find(|x| x.pad == "PD6").unwrap();
+        let names: Vec<&str> = pd6.functions.iter().map(|x| x.name.as_str()).collect();
+        assert!(names.contains(&"OC0A") && names.contains(&"AIN0") && names.contains(&"PCINT22"));
+        let name = |pad: &str, instance: &str| {
+            functions(atdf, pad).into_iter().find(|x| x.instance == instance).unwrap().name
+        };
+        assert_eq!(name("PD1", "USART0"), "TXD0");
+        assert_eq!(name("PC3", "ADC"), "ADC3");
+        assert_eq!(name("PB0", "TC1"), "ICP1");
+        assert_eq!(name("PB6", "TC2"), "TOSC1");
+
+        // DDRB 0x24, PORTB 0x25
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        data[0x24] = 0x01;
+        data[0x25] = 0x01;
+        pdip.update(&Gpio::init(atdf), &data);
+        let pb0 = pdip.pins.iter().find(|x| x.pad == "PB0").unwrap();
+        assert_eq!((pb0.output, pb0.level), (Some(true), Some(true)));
+        let vcc = pdip.pins.iter().find(|x| x.pad == "VCC").unwrap();
+        assert_eq!((vcc.pin, vcc.level), (None, None));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::sim::memory::{AccessKind, DataMemory, MemAccess};
+use crate::sim::peripherals::{Bit, InstanceMap, read, write};
+use device_parser::AvrDeviceFile;
+use serde::Serialize;
+
+/// access to the registers of a module whose clock is stopped by PRR
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GatedAccess {
+    pub module: &'static str,
+    pub bit: &'static str, // e.g. PRSPI
+    pub kind: AccessKind,
+    pub address: u32,
+}
+
+#[derive(Debug)]
+struct Gate {
+    name: &'static str,
+    bit: Bit,
+    instances: Vec<&'static str>,
+    registers: Vec<u32>,
+    frozen: Vec<u8>, // register values when the clock was stopped
+    gated: bool,
+    reported: bool,
+}
+
+/// instance names a PRR bit stops, e.g. PRTIM1 is TC1 and PRSPI is SPI or SPI0
+fn instances(bit: &str) -> Vec<String> {
+    let module = bit.trim_start_matches("PR");
+    match module.strip_prefix("TIM") {
+        Some(index) => vec![format!("TC{}", index)],
+        None => match module.ends_with(|c: char| c.is_ascii_digit()) {
+            true => vec![module.to_string()],
+            false => vec![module.to_string(), format!("{}0", module)],
+        },
+    }
+}
+
+/// power reduction: PRR, PRR0, PRR1 and PRR2 stop the clock of single modules
+#[derive(Debug, Default)]
+pub struct Power {
+    gates: Vec<Gate>,
+    diagnostics: Vec<GatedAccess>,
+}
+
+impl Power {
+    pub fn init(atdf: &'static AvrDeviceFile) -> Power {
+        let Some(cpu) = InstanceMap::find(atdf, "CPU").into_iter().next() else {
+            return Power::default();
+        };
+        let maps: Vec<InstanceMap> = atdf
+            .devices
+            .peripherals
+            .iter()
+            .flat_map(|x| InstanceMap::find(atdf, x.name))
+            .collect();
+        let gates = ["PRR", "PRR0", "PRR1", "PRR2"]
+            .iter()
+            .filter_map(|name| Some((cpu.address(name)?, cpu.register(name)?.bitfields?)))
+            .flat_map(|(address, bitfields)| {
+                bitfields.iter().map(move |x| (address, x))
+            })
+            .filter_map(|(address, field)| {
+                let names = instances(field.name);
+                let gated: Vec<&InstanceMap> = maps
+                    .iter()
+                    .filter(|x| names.iter().any(|name| name == x.instance.name))
+                    .collect();
+                if gated.is_empty() {
+                    return None;
+                }
+                let mut registers: Vec<u32> = gated.iter().flat_map(|x| x.addresses()).collect();
+                registers.sort();
+                registers.dedup();
+                Some(Gate {
+                    name: field.name,
+                    bit: Bit {
+                        address,
+                        mask: field.mask as u8,
+                    },
+                    instances: gated.iter().map(|x| x.instance.name).collect(),
+                    frozen: vec![0; registers.len()],
+                    registers,
+                    gated: false,
+                    reported: false,
+                })
+            })
+            .collect();
+        Power {
+            gates,
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn is_modelled(&self) -> bool {
+        !self.gates.is_empty()
+    }
+
+    /// true while the clock of the module instance `name` is stopped
+    pub fn is_gated(&self, name: &str) -> bool {
+        self.gates
+            .iter()
+            .any(|x| x.gated && x.instances.contains(&name))
+    }
+
+    /// writes to stopped modules are dropped, the first access after stopping is reported
+    pub fn update(&mut self, data: &mut DataMemory, accesses: &[MemAccess]) {
+        for gate in &mut self.gates {
+            if gate.gated {
+                for access in accesses {
+                    let Some(index) = gate.registers.iter().position(|x| *x == access.address)
+                    else {
+                        continue;
+                    };
+                    if access.kind == AccessKind::Write {
+                        write(data, access.address, gate.frozen[index]);
+                    }
+                    if !std::mem::replace(&mut gate.reported, true) {
+                        self.diagnostics.push(GatedAccess {
+                            module: gate.instances[0],
+                            bit: gate.name,
+                            kind: access.kind,
+                            address: access.address,
+                        });
+                    }
+                }
+            }
+
+            let gated = gate.bit.get(data);
+            if gated && !gate.gated {
+                gate.frozen = gate.registers.iter().map(|x| read(data, *x)).collect();
+                gate.reported = false;
+            }
+            gate.gated = gated;
+        }
+    }
+
+    /// accesses to stopped modules since the last call
+    pub fn take_diagnostics(&mut self) -> Vec<GatedAccess> {
+        std::mem::take(&mut self.diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    #[test]
+    fn test_gated_spi() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        let mut power = Power::init(atdf);
+        assert_eq!(instances("PRTIM1"), vec!["TC1"]);
+        assert!(power.gates.iter().any(|x| x.name == "PRUSART0"));
+
+        // PRR 0x64, SPCR 0x4c
+        data[0x4c] = 0x50;
+        data[0x64] = 0x04;
+        power.update(&mut data, &[]);
+        assert!(power.is_gated("SPI"));
+        assert!(!power.is_gated("TWI"));
+
+        data[0x4c] = 0x00;
+        let store = MemAccess {
+            kind: AccessKind::Write,
+            address: 0x4c,
+            value: 0x00,
+            width: 1,
+            pc: 0,
+        };
+        power.update(&mut data, &[store, store]);
+        assert_eq!(data[0x4c], 0x50);
+        let diagnostics = power.take_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].module, diagnostics[0].bit), ("SPI", "PRSPI"));
+
+        data[0x64] = 0x00;
+        power.update(&mut data, &[]);
+        power.update(&mut data, &[store]);
+        assert!(!power.is_gated("SPI"));
+        assert!(power.take_diagnostics().is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::{Bit, InstanceMap, write};
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::device_property_group::PropertyValue;
+
+/// factory calibration of the RC oscillators, the middle of the OSCCAL range
+pub const CALIBRATION: u8 = 0x80;
+// TEMPSENSE0 gain and TEMPSENSE1 offset, ((ADC - offset) * gain) >> 8 gives 298 K for the
+// 0.314 V of the simulated sensor against the 1.1 V reference
+const TEMPSENSE_GAIN: u8 = 0xff;
+const TEMPSENSE_OFFSET: u8 = 0xf8;
+
+/// signature bytes and the production row of a device
+#[derive(Debug, Clone, Default)]
+pub struct Signature {
+    pub bytes: Vec<u8>,       // SIGNATURE0 to SIGNATURE2
+    pub calibration: Vec<u8>, // one byte per value of OSCCAL_VALUE_ADDRESSES
+    sigrd: Option<(Bit, Bit)>, // SIGRD and SPMEN of SPMCSR
+    sigrow: Option<InstanceMap>,
+    osccal: Vec<u32>,
+}
+
+impl Signature {
+    pub fn init(atdf: &'static AvrDeviceFile) -> Signature {
+        let bytes = atdf
+            .devices
+            .propery_groups
+            .iter()
+            .find(|x| x.name == "SIGNATURES")
+            .map(|group| {
+                (0..3)
+                    .filter_map(|i| {
+                        let name = format!("SIGNATURE{}", i);
+                        match group.properties.iter().find(|x| x.name == name)?.value {
+                            PropertyValue::Number(x) => Some(x as u8),
+                            _ => None,
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let cpu = InstanceMap::find(atdf, "CPU");
+        let calibration = cpu
+            .iter()
+            .find_map(|x| x.module.value_group("OSCCAL_VALUE_ADDRESSES"))
+            .map_or(vec![], |x| vec![CALIBRATION; x.values.len()]);
+        let sigrd = ["CPU", "BOOT_LOAD"]
+            .iter()
+            .flat_map(|x| InstanceMap::find(atdf, x))
+            .find_map(|x| {
+                let spmen = Bit::find(&x, "SPMEN").or_else(|| Bit::find(&x, "SELFPRGEN"))?;
+                Some((Bit::find(&x, "SIGRD")?, spmen))
+            });
+        Signature {
+            bytes,
+            calibration,
+            sigrd,
+            sigrow: InstanceMap::find(atdf, "SIGROW").into_iter().find(|x| x.is_data()),
+            osccal: cpu
+                .iter()
+                .filter_map(|x| x.address("OSCCAL").or_else(|| x.address("OSCCAL0")))
+                .collect(),
+        }
+    }
+
+    /// byte `index` of the production row, signature bytes are even and calibration bytes odd
+    fn row(&self, index: u32) -> u8 {
+        let bytes = match index % 2 {
+            0 => &self.bytes,
+            _ => &self.calibration,
+        };
+        bytes.get(index as usize / 2).copied().unwrap_or(0xff)
+    }
+
+    /// value of a SIGROW register on devices that map the signature row into data space
+    fn sigrow_value(&self, name: &str) -> Option<u8> {
+        let index = |prefix: &str| name.strip_prefix(prefix)?.parse::<usize>().ok();
+        if let Some(i) = index("DEVICEID") {
+            return self.bytes.get(i).copied();
+        }
+        if let Some(i) = index("SERNUM") {
+            return Some(i as u8);
+        }
+        match name {
+            "TEMPSENSE0" => Some(TEMPSENSE_GAIN),
+            "TEMPSENSE1" => Some(TEMPSENSE_OFFSET),
+            // the simulated oscillators run at their nominal frequency
+            x if x.starts_with("OSCCAL") => Some(CALIBRATION),
+            x if x.starts_with("OSC") && x.contains("ERR") => Some(0),
+            _ => None,
+        }
+    }
+
+    /// loads the calibration into OSCCAL and the signature row into SIGROW, after a reset
+    pub fn load(&self, data: &mut DataMemory) {
+        if let Some(&calibration) = self.calibration.first() {
+            self.osccal.iter().for_each(|x| write(data, *x, calibration));
+        }
+        for (address, register) in self.sigrow.iter().flat_map(|x| x.registers()) {
+            if let Some(value) = self.sigrow_value(register.name) {
+                write(data, address, value);
+            }
+        }
+    }
+
+    /// LPM right after setting SIGRD and SPMEN reads the signature row, both bits are cleared
+    pub fn lpm(&self, data: &mut DataMemory, z: u32) -> Option<u8> {
+        let (sigrd, spmen) = self.sigrd?;
+        if !sigrd.get(data) || !spmen.get(data) {
+            return None;
+        }
+        sigrd.set(data, false);
+        spmen.set(data, false);
+        Some(self.row(z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_parser::get_tree_map;
+
+    #[test]
+    fn test_signature() {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let signature = Signature::init(atdf);
+        assert_eq!((signature.bytes.clone(), signature.calibration.len()), (vec![0x1e, 0x95, 0x0f], 1));
+        let row: Vec<u8> = (0..6).map(|x| signature.row(x)).collect();
+        assert_eq!(row, vec![0x1e, CALIBRATION, 0x95, 0xff, 0x0f, 0xff]);
+
+        let mut data = DataMemory::default();
+        data.init(atdf).unwrap();
+        signature.load(&mut data);
+        assert_eq!(data[0x66], CALIBRATION);
+        // SPMCSR 0x57, SIGRD and SPMEN
+        assert_eq!(signature.lpm(&mut data, 4), None);
+        data[0x57] = 0x21;
+        assert_eq!(signature.lpm(&mut data, 4), Some(0x0f));
+        assert_eq!(data[0x57], 0x00);
+
+        let atdf = get_tree_map().get("atmega4809").unwrap();
+        let signature = Signature::init(atdf);
+        let mut data = DataMemory::default();
+        data.ram.resize(0x1140, 0);
+        signature.load(&mut data);
+        let row: Vec<u8> = (0x1100..0x1104).map(|x| data[x]).collect();
+        assert_eq!(row, vec![0x1e, 0x96, 0x51, 0x00]);
+        assert_eq!((data[0x1118], data[0x1120], data[0x1122]), (CALIBRATION, TEMPSENSE_GAIN, 0));
+    }
+}
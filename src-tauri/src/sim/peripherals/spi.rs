@@ -0,0 +1,393 @@
+use crate::error::Result;
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::gpio::{Gpio, Pin};
+use crate::sim::peripherals::{Context, InstanceMap, Peripheral, read, write};
+use anyhow::anyhow;
+use device_parser::AvrDeviceFile;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+const IF: u8 = 0x80; // SPIF
+const WCOL: u8 = 0x40;
+
+/// external slave on the bus, bytes are exchanged msb first as seen on the wire
+pub trait SpiDevice: std::fmt::Debug + Send {
+    /// the select line changed, `selected` is true while it is low
+    fn select(&mut self, _selected: bool) {}
+    /// one byte shifted out on MOSI, the returned byte is shifted in on MISO
+    fn transfer(&mut self, mosi: u8) -> u8;
+}
+
+/// returns every MOSI byte on MISO
+#[derive(Debug, Default)]
+pub struct Loopback;
+
+impl SpiDevice for Loopback {
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        mosi
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SpiDeviceKind {
+    Loopback,
+}
+
+impl SpiDeviceKind {
+    pub fn create(self) -> Box<dyn SpiDevice> {
+        match self {
+            SpiDeviceKind::Loopback => Box::new(Loopback),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Attached {
+    device: Box<dyn SpiDevice>,
+    select: Option<Pin>, // always selected without a select pin
+    selected: bool,
+}
+
+/// register layout, the bits of the status register are the same for all of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    Classic, // SPCR, SPSR, SPDR
+    Xmega,   // CTRL, INTCTRL, STATUS, DATA
+    AVRxt,   // CTRLA, CTRLB, INTCTRL, INTFLAGS, DATA; buffer mode is not modelled
+}
+
+#[derive(Debug)]
+pub struct Spi {
+    pub name: &'static str,
+    layout: Layout,
+    ctrl: u32,
+    status: u32,
+    data: u32,
+    intctrl: Option<u32>,
+    ctrlb: Option<u32>,
+    ss: Option<Pin>,
+    vector: Option<u32>,
+    flags: u8,
+    flag_read: bool, // status was read with IF set, the next data access clears the flags
+    transfer: Option<(u8, u64)>, // byte being shifted out and cycles left
+    devices: Vec<Attached>,
+}
+
+impl Spi {
+    pub fn new(atdf: &'static AvrDeviceFile, map: &InstanceMap) -> Option<Spi> {
+        let (layout, ctrl, status, data) = match map.address_prefixed("SPCR") {
+            Some(ctrl) => (
+                Layout::Classic,
+                ctrl,
+                map.address_prefixed("SPSR")?,
+                map.address_prefixed("SPDR")?,
+            ),
+            None => match map.address("CTRLA") {
+                Some(ctrl) => (
+                    Layout::AVRxt,
+                    ctrl,
+                    map.address("INTFLAGS")?,
+                    map.address("DATA")?,
+                ),
+                None => (
+                    Layout::Xmega,
+                    map.address("CTRL")?,
+                    map.address("STATUS")?,
+                    map.address("DATA")?,
+                ),
+            },
+        };
+        let vector = match layout {
+            Layout::AVRxt => map.vector(atdf, "INT"),
+            _ => map.vector(atdf, "STC").or_else(|| map.own_vector(atdf)),
+        };
+        Some(Spi {
+            name: map.instance.name,
+            layout,
+            ctrl,
+            status,
+            data,
+            intctrl: map.address("INTCTRL"),
+            ctrlb: map.address("CTRLB"),
+            ss: map.signal("SS"),
+            vector,
+            flags: 0,
+            flag_read: false,
+            transfer: None,
+            devices: vec![],
+        })
+    }
+
+    pub fn attach(&mut self, device: Box<dyn SpiDevice>, select: Option<Pin>) {
+        self.devices.push(Attached {
+            device,
+            select,
+            selected: select.is_none(),
+        });
+    }
+
+    fn enabled(&self, data: &DataMemory) -> bool {
+        let ctrl = read(data, self.ctrl);
+        match self.layout {
+            Layout::Classic | Layout::Xmega => ctrl & 0x40 != 0,
+            Layout::AVRxt => ctrl & 0x01 != 0,
+        }
+    }
+
+    fn master_mask(&self) -> u8 {
+        match self.layout {
+            Layout::AVRxt => 0x20,
+            _ => 0x10,
+        }
+    }
+
+    fn lsb_first(&self, data: &DataMemory) -> bool {
+        let ctrl = read(data, self.ctrl);
+        match self.layout {
+            Layout::AVRxt => ctrl & 0x40 != 0,
+            _ => ctrl & 0x20 != 0,
+        }
+    }
+
+    /// cpu cycles per SCK period
+    fn divider(&self, data: &DataMemory) -> u64 {
+        let ctrl = read(data, self.ctrl);
+        let (prescaler, double) = match self.layout {
+            Layout::Classic => (ctrl & 0x03, read(data, self.status) & 0x01 != 0),
+            Layout::Xmega => (ctrl & 0x03, ctrl & 0x80 != 0),
+            Layout::AVRxt => ((ctrl >> 1) & 0x03, ctrl & 0x10 != 0),
+        };
+        [4, 16, 64, 128][prescaler as usize] / (1 + double as u64)
+    }
+
+    fn interrupt_enabled(&self, data: &DataMemory) -> bool {
+        match (self.layout, self.intctrl) {
+            (Layout::Classic, _) => read(data, self.ctrl) & 0x80 != 0,
+            (Layout::Xmega, Some(intctrl)) => read(data, intctrl) & 0x03 != 0,
+            (Layout::AVRxt, Some(intctrl)) => read(data, intctrl) & 0x01 != 0,
+            _ => false,
+        }
+    }
+
+    /// the SS pin only has an effect in master mode when it is an input
+    fn ss_low(&self, data: &DataMemory, gpio: &Gpio) -> bool {
+        if let (Layout::AVRxt, Some(ctrlb)) = (self.layout, self.ctrlb)
+            && read(data, ctrlb) & 0x04 != 0
+        {
+            return false;
+        }
+        self.ss.is_some_and(|pin| {
+            !gpio.is_output(data, pin).unwrap_or(true) && !gpio.level(data, pin).unwrap_or(true)
+        })
+    }
+
+    fn exchange(&mut self, data: &DataMemory, gpio: &Gpio, byte: u8) -> u8 {
+        let lsb_first = self.lsb_first(data);
+        let mosi = match lsb_first {
+            true => byte.reverse_bits(),
+            false => byte,
+        };
+        // MISO is pulled high when no device drives it
+        let miso = self
+            .devices
+            .iter_mut()
+            .filter(|x| {
+                x.select
+                    .is_none_or(|pin| !gpio.level(data, pin).unwrap_or(true))
+            })
+            .fold(0xff, |acc, x| acc & x.device.transfer(mosi));
+        match lsb_first {
+            true => miso.reverse_bits(),
+            false => miso,
+        }
+    }
+
+    /// reading the status with IF set and then accessing the data register clears the flags
+    fn data_access(&mut self) {
+        if self.flag_read {
+            self.flags &= !(IF | WCOL);
+            self.flag_read = false;
+        }
+    }
+
+    /// a byte sent by an external master while the spi is a selected slave, returns MISO
+    pub fn receive(&mut self, data: &mut DataMemory, gpio: &Gpio, mosi: u8) -> Result<u8> {
+        let master = read(data, self.ctrl) & self.master_mask() != 0;
+        if !self.enabled(data) || master {
+            return Err(anyhow!("{} is not an enabled slave", self.name));
+        }
+        if self
+            .ss
+            .is_some_and(|pin| gpio.level(data, pin).unwrap_or(true))
+        {
+            return Err(anyhow!("{} is not selected", self.name));
+        }
+        let miso = read(data, self.data);
+        write(data, self.data, mosi);
+        self.flags |= IF;
+        write(data, self.status, read(data, self.status) | self.flags);
+        Ok(miso)
+    }
+}
+
+impl Peripheral for Spi {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn addresses(&self) -> Vec<u32> {
+        vec![self.status, self.data]
+    }
+
+    fn read(&mut self, ctx: &mut Context, address: u32) -> u8 {
+        let value = read(ctx.data, address);
+        if address == self.status {
+            self.flag_read = value & IF != 0;
+        } else if address == self.data {
+            self.data_access();
+        }
+        value
+    }
+
+    fn write(&mut self, ctx: &mut Context, address: u32, value: u8) {
+        if address == self.status {
+            // AVRxt flags are cleared by writing a one
+            if self.layout == Layout::AVRxt {
+                self.flags &= !value;
+            }
+        } else if address == self.data {
+            self.data_access();
+            let master = read(ctx.data, self.ctrl) & self.master_mask() != 0;
+            if self.enabled(ctx.data) && master {
+                match self.transfer {
+                    Some(_) => self.flags |= WCOL,
+                    None => self.transfer = Some((value, 8 * self.divider(ctx.data))),
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context, cycles: u64) {
+        let (data, gpio) = (&mut *ctx.data, ctx.gpio);
+        for attached in &mut self.devices {
+            if let Some(pin) = attached.select {
+                let selected = !gpio.level(data, pin).unwrap_or(true);
+                if selected != attached.selected {
+                    attached.selected = selected;
+                    attached.device.select(selected);
+                }
+            }
+        }
+
+        if self.enabled(data)
+            && read(data, self.ctrl) & self.master_mask() != 0
+            && self.ss_low(data, gpio)
+        {
+            // mode fault, another master pulled SS low
+            let ctrl = read(data, self.ctrl);
+            write(data, self.ctrl, ctrl & !self.master_mask());
+            self.transfer = None;
+            self.flags |= IF;
+        }
+
+        if let Some((byte, left)) = self.transfer {
+            match left.checked_sub(cycles).filter(|x| *x > 0) {
+                Some(left) => self.transfer = Some((byte, left)),
+                None => {
+                    self.transfer = None;
+                    let miso = self.exchange(data, gpio, byte);
+                    write(data, self.data, miso);
+                    self.flags |= IF;
+                }
+            }
+        }
+
+        let status = match self.layout {
+            Layout::Classic => read(data, self.status) & !(IF | WCOL),
+            _ => 0,
+        };
+        write(data, self.status, status | self.flags);
+    }
+
+    fn pending_interrupts(&self, data: &DataMemory) -> Vec<u32> {
+        match self.flags & IF != 0 && self.interrupt_enabled(data) {
+            true => self.vector.into_iter().collect(),
+            false => vec![],
+        }
+    }
+
+    fn acknowledge(&mut self, data: &mut DataMemory, vector: u32) {
+        if self.vector == Some(vector) {
+            self.flags &= !IF;
+            write(data, self.status, read(data, self.status) & !IF);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::peripherals::bench::{Bench, load, store};
+    use device_parser::get_tree_map;
+
+    #[derive(Debug, Default)]
+    struct Counter(u8);
+    impl SpiDevice for Counter {
+        fn transfer(&mut self, _mosi: u8) -> u8 {
+            self.0 += 1;
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_classic_master_transfer() {
+        let mut bench = Bench::new("atmega328p");
+        let mut spi = Spi::new(bench.atdf, &bench.map("SPI")).unwrap();
+        assert_eq!(spi.vector, Some(17));
+        spi.attach(Box::new(Counter::default()), None);
+        // SPCR 0x4c, SPSR 0x4d, SPDR 0x4e; SS (PB2) as output
+        bench.data[0x24] = 0x04;
+        bench.data[0x4c] = 0xd1; // SPIE, SPE, MSTR, fosc/16
+        bench.data[0x4e] = 0x55;
+        bench.run(&mut spi, &[store(0x4e, 0x55)], 1);
+        bench.run(&mut spi, &[store(0x4e, 0xaa)], 1);
+        assert_eq!(bench.data[0x4d], WCOL);
+        bench.run(&mut spi, &[], 8 * 16 - 3);
+        assert!(spi.pending_interrupts(&bench.data).is_empty());
+        bench.run(&mut spi, &[], 1);
+        assert_eq!(bench.data[0x4d], IF | WCOL);
+        assert_eq!(bench.data[0x4e], 1);
+        assert_eq!(spi.pending_interrupts(&bench.data), vec![17]);
+
+        bench.run(&mut spi, &[load(0x4d, IF | WCOL), load(0x4e, 1)], 1);
+        assert_eq!(bench.data[0x4d], 0);
+    }
+
+    #[test]
+    fn test_mode_fault() {
+        let mut bench = Bench::new("atmega328p");
+        let mut spi = Spi::new(bench.atdf, &bench.map("SPI")).unwrap();
+        bench.drive("PB2", Some(false));
+        bench.data[0x4c] = 0x50;
+        bench.run(&mut spi, &[], 1);
+        assert_eq!(bench.data[0x4c], 0x40);
+        assert_eq!(bench.data[0x4d], IF);
+        assert_eq!(spi.receive(&mut bench.data, &bench.gpio, 0x12).unwrap(), 0);
+        assert_eq!(bench.data[0x4e], 0x12);
+    }
+
+    #[test]
+    fn test_avrxt_layout() {
+        let atdf = get_tree_map().get("atmega4809").unwrap();
+        let spi = Spi::new(atdf, &InstanceMap::find(atdf, "SPI")[0]).unwrap();
+        assert_eq!(spi.layout, Layout::AVRxt);
+        assert_eq!((spi.ctrl, spi.status, spi.data), (0x8c0, 0x8c3, 0x8c4));
+        assert_eq!(spi.vector, Some(16));
+        assert_eq!(spi.ss, Some("PA7".parse().unwrap()));
+    }
+}
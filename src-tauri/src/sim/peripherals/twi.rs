@@ -0,0 +1,400 @@
+use crate::error::Result;
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::{Context, InstanceMap, Peripheral, read, write};
+use anyhow::anyhow;
+use device_parser::AvrDeviceFile;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::BTreeMap;
+
+const TWINT: u8 = 0x80;
+const TWEA: u8 = 0x40;
+const TWSTA: u8 = 0x20;
+const TWSTO: u8 = 0x10;
+const TWWC: u8 = 0x08;
+const TWEN: u8 = 0x04;
+const TWIE: u8 = 0x01;
+
+// TWSR status codes of the master modes
+const START: u8 = 0x08;
+const REP_START: u8 = 0x10;
+const MT_SLA_ACK: u8 = 0x18;
+const MT_SLA_NACK: u8 = 0x20;
+const MT_DATA_ACK: u8 = 0x28;
+const MT_DATA_NACK: u8 = 0x30;
+const MR_SLA_ACK: u8 = 0x40;
+const MR_SLA_NACK: u8 = 0x48;
+const MR_DATA_ACK: u8 = 0x50;
+const MR_DATA_NACK: u8 = 0x58;
+const IDLE: u8 = 0xf8;
+
+/// external slave on the bus
+pub trait I2cDevice: std::fmt::Debug + Send {
+    /// addressed after a start or repeated start, return false to NACK
+    fn start(&mut self, _read: bool) -> bool {
+        true
+    }
+    /// byte written by the master, return false to NACK
+    fn write(&mut self, data: u8) -> bool;
+    /// byte requested by the master, `ack` is false for the last one
+    fn read(&mut self, ack: bool) -> u8;
+    fn stop(&mut self) {}
+}
+
+/// 24Cxx style eeprom, the first bytes written after addressing set the memory address
+#[derive(Debug)]
+pub struct I2cEeprom {
+    data: Vec<u8>,
+    address_bytes: usize,
+    pointer: usize,
+    received: usize,
+}
+
+impl I2cEeprom {
+    pub fn new(size: usize) -> I2cEeprom {
+        I2cEeprom {
+            data: vec![0xff; size.max(1)],
+            address_bytes: if size > 0x100 { 2 } else { 1 },
+            pointer: 0,
+            received: 0,
+        }
+    }
+}
+
+impl I2cDevice for I2cEeprom {
+    fn start(&mut self, read: bool) -> bool {
+        if !read {
+            self.received = 0;
+        }
+        true
+    }
+    fn write(&mut self, data: u8) -> bool {
+        if self.received < self.address_bytes {
+            self.pointer = match self.received {
+                0 => data as usize,
+                _ => (self.pointer << 8) | data as usize,
+            } % self.data.len();
+            self.received += 1;
+        } else {
+            self.data[self.pointer] = data;
+            self.pointer = (self.pointer + 1) % self.data.len();
+        }
+        true
+    }
+    fn read(&mut self, _ack: bool) -> u8 {
+        let data = self.data[self.pointer];
+        self.pointer = (self.pointer + 1) % self.data.len();
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum I2cDeviceKind {
+    Eeprom(u32), // size in bytes
+}
+
+impl I2cDeviceKind {
+    pub fn create(self) -> Box<dyn I2cDevice> {
+        match self {
+            I2cDeviceKind::Eeprom(size) => Box::new(I2cEeprom::new(size as usize)),
+        }
+    }
+}
+
+/// slaves by their 7 bit address
+#[derive(Debug, Default)]
+pub struct I2cBus {
+    devices: BTreeMap<u8, Box<dyn I2cDevice>>,
+}
+
+impl I2cBus {
+    pub fn attach(&mut self, address: u8, device: Box<dyn I2cDevice>) -> Result<()> {
+        if address > 0x7f {
+            return Err(anyhow!("invalid i2c address:{:#x}", address));
+        }
+        if self.devices.contains_key(&address) {
+            return Err(anyhow!("i2c address in use:{:#x}", address));
+        }
+        self.devices.insert(address, device);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operation {
+    Start,
+    Stop,
+    Address(u8), // SLA+R/W
+    Write(u8),
+    Read(bool), // acknowledge the byte
+}
+
+/// classic TWI in master mode, being addressed as a slave is not modelled
+#[derive(Debug)]
+pub struct Twi {
+    pub name: &'static str,
+    twbr: u32,
+    twsr: u32,
+    twcr: u32,
+    twdr: u32,
+    vector: Option<u32>,
+    status: u8,
+    twint: bool,
+    twwc: bool,
+    data: u8,            // TWDR, writes while TWINT is low are dropped
+    owner: bool,         // a start was sent and no stop yet
+    target: Option<u8>,  // slave that acknowledged its address
+    pending: Option<(Operation, u64)>, // cycles left
+    pub bus: I2cBus,
+}
+
+impl Twi {
+    pub fn new(atdf: &'static AvrDeviceFile, map: &InstanceMap) -> Option<Twi> {
+        Some(Twi {
+            name: map.instance.name,
+            twbr: map.address_prefixed("TWBR")?,
+            twsr: map.address_prefixed("TWSR")?,
+            twcr: map.address_prefixed("TWCR")?,
+            twdr: map.address_prefixed("TWDR")?,
+            vector: map.own_vector(atdf),
+            status: IDLE,
+            twint: false,
+            twwc: false,
+            data: 0xff,
+            owner: false,
+            target: None,
+            pending: None,
+            bus: I2cBus::default(),
+        })
+    }
+
+    /// cpu cycles per SCL period
+    fn bit_period(&self, data: &DataMemory) -> u64 {
+        let prescaler = 4u64.pow((read(data, self.twsr) & 0x03) as u32);
+        16 + 2 * read(data, self.twbr) as u64 * prescaler
+    }
+
+    fn start_operation(&mut self, data: &DataMemory, twcr: u8) {
+        let operation = if twcr & TWSTO != 0 {
+            Operation::Stop
+        } else if twcr & TWSTA != 0 {
+            Operation::Start
+        } else {
+            match self.status {
+                START | REP_START => Operation::Address(self.data),
+                MT_SLA_ACK | MT_SLA_NACK | MT_DATA_ACK | MT_DATA_NACK => {
+                    Operation::Write(self.data)
+                }
+                MR_SLA_ACK | MR_DATA_ACK => Operation::Read(twcr & TWEA != 0),
+                _ => return,
+            }
+        };
+        let bits = match operation {
+            Operation::Start | Operation::Stop => 1,
+            _ => 9, // 8 data bits and the acknowledge
+        };
+        self.pending = Some((operation, bits * self.bit_period(data)));
+    }
+
+    fn finish(&mut self, data: &mut DataMemory, operation: Operation) {
+        let target = self.target.and_then(|x| self.bus.devices.get_mut(&x));
+        self.status = match operation {
+            Operation::Start => {
+                self.target = None;
+                match std::mem::replace(&mut self.owner, true) {
+                    true => REP_START,
+                    false => START,
+                }
+            }
+            Operation::Stop => {
+                if let Some(device) = target {
+                    device.stop();
+                }
+                self.target = None;
+                self.owner = false;
+                write(data, self.twcr, read(data, self.twcr) & !TWSTO);
+                self.status = IDLE;
+                // a stop does not set TWINT, a start requested with it follows
+                if read(data, self.twcr) & TWSTA != 0 {
+                    self.pending = Some((Operation::Start, self.bit_period(data)));
+                }
+                return;
+            }
+            Operation::Address(sla) => {
+                let read = sla & 0x01 != 0;
+                let ack = self
+                    .bus
+                    .devices
+                    .get_mut(&(sla >> 1))
+                    .is_some_and(|x| x.start(read));
+                self.target = ack.then_some(sla >> 1);
+                match (read, ack) {
+                    (false, true) => MT_SLA_ACK,
+                    (false, false) => MT_SLA_NACK,
+                    (true, true) => MR_SLA_ACK,
+                    (true, false) => MR_SLA_NACK,
+                }
+            }
+            Operation::Write(byte) => match target.is_some_and(|x| x.write(byte)) {
+                true => MT_DATA_ACK,
+                false => MT_DATA_NACK,
+            },
+            Operation::Read(ack) => {
+                // SDA stays high when nothing drives it
+                self.data = target.map(|x| x.read(ack)).unwrap_or(0xff);
+                write(data, self.twdr, self.data);
+                match ack {
+                    true => MR_DATA_ACK,
+                    false => MR_DATA_NACK,
+                }
+            }
+        };
+        self.twint = true;
+    }
+}
+
+impl Peripheral for Twi {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn addresses(&self) -> Vec<u32> {
+        vec![self.twdr, self.twcr]
+    }
+
+    fn write(&mut self, ctx: &mut Context, address: u32, value: u8) {
+        if address == self.twdr {
+            match self.twint {
+                true => {
+                    self.data = value;
+                    self.twwc = false;
+                }
+                false => {
+                    self.twwc = true;
+                    write(ctx.data, self.twdr, self.data);
+                }
+            }
+        } else if address == self.twcr {
+            if value & TWEN == 0 {
+                self.pending = None;
+                self.twint = false;
+                self.owner = false;
+                self.target = None;
+                self.status = IDLE;
+            } else if value & TWINT != 0 {
+                // TWINT is cleared by writing a one, which starts the next operation
+                self.twint = false;
+                self.start_operation(ctx.data, value);
+            }
+        }
+    }
+
+    fn tick(&mut self, ctx: &mut Context, cycles: u64) {
+        let data = &mut *ctx.data;
+        if let Some((operation, left)) = self.pending {
+            match left.checked_sub(cycles).filter(|x| *x > 0) {
+                Some(left) => self.pending = Some((operation, left)),
+                None => {
+                    self.pending = None;
+                    self.finish(data, operation);
+                }
+            }
+        }
+
+        let twcr = read(data, self.twcr) & !(TWINT | TWWC);
+        write(data, self.twcr, twcr | ((self.twint as u8) << 7) | ((self.twwc as u8) << 3));
+        write(data, self.twsr, self.status | (read(data, self.twsr) & 0x03));
+    }
+
+    fn pending_interrupts(&self, data: &DataMemory) -> Vec<u32> {
+        let twcr = read(data, self.twcr);
+        match self.twint && twcr & TWIE != 0 && twcr & TWEN != 0 {
+            true => self.vector.into_iter().collect(),
+            false => vec![],
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::peripherals::bench::{Bench, store};
+
+    // TWBR 0xb8, TWSR 0xb9, TWDR 0xbb, TWCR 0xbc on the atmega328p
+    /// writes a register like an instruction would and runs until TWINT is set
+    fn command(twi: &mut Twi, bench: &mut Bench, address: u32, value: u8) -> u64 {
+        bench.data[address as usize] = value;
+        bench.run(twi, &[store(address, value)], 1);
+        let mut cycles = 1;
+        while !twi.twint && twi.pending.is_some() {
+            bench.run(twi, &[], 1);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_eeprom_write_and_read() {
+        let mut bench = Bench::new("atmega328p");
+        let mut twi = Twi::new(bench.atdf, &bench.map("TWI")).unwrap();
+        assert_eq!(twi.vector, Some(24));
+        twi.bus.attach(0x50, I2cDeviceKind::Eeprom(256).create()).unwrap();
+        assert!(twi.bus.attach(0x50, Box::new(I2cEeprom::new(8))).is_err());
+        bench.data[0xb8] = 2;
+
+        let go = TWINT | TWEN;
+        // one bit is 16 + 2 * TWBR cycles
+        assert_eq!(command(&mut twi, &mut bench, 0xbc, go | TWSTA), 20);
+        assert_eq!(bench.data[0xb9], START);
+        assert_eq!(bench.data[0xbc] & TWINT, TWINT);
+        command(&mut twi, &mut bench, 0xbb, 0xa0);
+        assert_eq!(command(&mut twi, &mut bench, 0xbc, go), 9 * 20);
+        assert_eq!(bench.data[0xb9], MT_SLA_ACK);
+        for byte in [0x10, 0x42] {
+            command(&mut twi, &mut bench, 0xbb, byte);
+            command(&mut twi, &mut bench, 0xbc, go);
+            assert_eq!(bench.data[0xb9], MT_DATA_ACK);
+        }
+
+        command(&mut twi, &mut bench, 0xbc, go | TWSTA);
+        assert_eq!(bench.data[0xb9], REP_START);
+        command(&mut twi, &mut bench, 0xbb, 0xa0);
+        command(&mut twi, &mut bench, 0xbc, go);
+        command(&mut twi, &mut bench, 0xbb, 0x10);
+        command(&mut twi, &mut bench, 0xbc, go);
+        command(&mut twi, &mut bench, 0xbc, go | TWSTA);
+        command(&mut twi, &mut bench, 0xbb, 0xa1);
+        command(&mut twi, &mut bench, 0xbc, go);
+        assert_eq!(bench.data[0xb9], MR_SLA_ACK);
+        command(&mut twi, &mut bench, 0xbc, go);
+        assert_eq!((bench.data[0xb9], bench.data[0xbb]), (MR_DATA_NACK, 0x42));
+
+        command(&mut twi, &mut bench, 0xbc, go | TWSTO);
+        assert_eq!(bench.data[0xb9], IDLE);
+        assert_eq!(bench.data[0xbc] & (TWINT | TWSTO), 0);
+
+        // nobody answers at 0x51
+        command(&mut twi, &mut bench, 0xbc, go | TWSTA);
+        command(&mut twi, &mut bench, 0xbb, 0xa2);
+        command(&mut twi, &mut bench, 0xbc, go);
+        assert_eq!(bench.data[0xb9], MT_SLA_NACK);
+    }
+
+    #[test]
+    fn test_write_collision() {
+        let mut bench = Bench::new("atmega328p");
+        let mut twi = Twi::new(bench.atdf, &bench.map("TWI")).unwrap();
+        bench.data[0xbc] = TWEN | TWIE;
+        command(&mut twi, &mut bench, 0xbb, 0x12);
+        assert_eq!(bench.data[0xbc] & TWWC, TWWC);
+        assert_eq!(bench.data[0xbb], 0xff);
+        assert!(twi.pending_interrupts(&bench.data).is_empty());
+        command(&mut twi, &mut bench, 0xbc, TWINT | TWEN | TWIE | TWSTA);
+        assert_eq!(twi.pending_interrupts(&bench.data), vec![24]);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::sim::memory::DataMemory;
+use crate::sim::peripherals::{Bit, Context, InstanceMap, Peripheral, ResetCause, read, write};
+use device_parser::AvrDeviceFile;
+use device_parser::r#struct::module::{BitField, Module};
+use std::any::Any;
+
+const TIMED_CYCLES: u64 = 4;
+
+/// oscillator cycles of a prescaler value name, e.g. OSCILLATOR_CYCLES_2K
+fn parse_cycles(name: &str) -> Option<u64> {
+    let part = name.rsplit('_').next()?;
+    Some(part.strip_suffix('K')?.parse::<u64>().ok()? * 1024)
+}
+
+/// classic watchdog in WDTCSR or WDTCR, changes to WDE and WDP need the timed WDCE sequence
+#[derive(Debug)]
+pub struct Watchdog {
+    pub name: &'static str,
+    module: &'static Module,
+    control: u32,
+    wdp: &'static BitField,
+    wdce: u8,
+    wde: u8,
+    wdie: u8, // 0 on devices without the interrupt
+    wdif: u8,
+    wdrf: Option<Bit>, // a watchdog reset forces WDE until it is cleared
+    vector: Option<u32>,
+    oscillator: f64,
+    pub always_on: bool, // WDTON programmed, always in system reset mode
+    enabled: bool,
+    prescaler: u64,
+    window: u64, // cycles left to change WDE and WDP
+    start: f64,  // time of the last WDR or timeout
+    reset: Option<ResetCause>,
+}
+
+impl Watchdog {
+    pub fn new(atdf: &'static AvrDeviceFile, map: &InstanceMap) -> Option<Watchdog> {
+        let control = map.address("WDTCSR").or_else(|| map.address("WDTCR"))?;
+        let register = map.register("WDTCSR").or_else(|| map.register("WDTCR"))?;
+        let mask = |name: &str| map.bitfield(name).map_or(0, |(_, mask)| mask);
+        let wdp = register.bitfields?.iter().find(|x| x.name == "WDP")?;
+        let cpu = InstanceMap::find(atdf, "CPU").into_iter().next();
+        // the 3 bit prescaler of older devices counts a 1 MHz oscillator
+        let oscillator = match wdp.mask.count_ones() {
+            3 => 1_000_000.0,
+            _ => 128_000.0,
+        };
+        Some(Watchdog {
+            name: map.instance.name,
+            module: map.module,
+            control,
+            wdp,
+            wdce: map.bitfield("WDCE").or_else(|| map.bitfield("WDTOE"))?.1,
+            wde: mask("WDE"),
+            wdie: mask("WDIE"),
+            wdif: mask("WDIF"),
+            wdrf: cpu.and_then(|x| Bit::find(&x, "WDRF")),
+            vector: map.own_vector(atdf),
+            oscillator,
+            always_on: false,
+            enabled: false,
+            prescaler: 0,
+            window: 0,
+            start: 0.0,
+            reset: None,
+        })
+    }
+
+    fn forced(&self, data: &DataMemory) -> bool {
+        self.always_on || self.wdrf.is_some_and(|x| x.get(data))
+    }
+
+    /// seconds until a timeout with the current prescaler
+    fn period(&self) -> f64 {
+        let cycles = self
+            .wdp
+            .values
+            .and_then(|x| self.module.value_group(x))
+            .and_then(|x| x.decode(self.prescaler))
+            .and_then(|x| parse_cycles(x.name))
+            .unwrap_or(2048 << self.prescaler);
+        cycles as f64 / self.oscillator
+    }
+
+    /// WDR, the timeout starts again from `time`
+    pub fn restart(&mut self, time: f64) {
+        self.start = time;
+    }
+
+    fn store(&self, data: &mut DataMemory) {
+        let flags = read(data, self.control) & (self.wdif | self.wdie);
+        let wde = match self.enabled || self.forced(data) {
+            true => self.wde,
+            false => 0,
+        };
+        let wdce = match self.window > 0 {
+            true => self.wdce,
+            false => 0,
+        };
+        let wdp = self.wdp.insert(0, self.prescaler) as u8;
+        write(data, self.control, flags | wdce | wde | wdp);
+    }
+}
+
+impl Peripheral for Watchdog {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn addresses(&self) -> Vec<u32> {
+        vec![self.control]
+    }
+
+    fn write(&mut self, ctx: &mut Context, _address: u32, value: u8) {
+        let sequence = value & (self.wdce | self.wde) == self.wdce | self.wde;
+        if self.window > 0 && !sequence {
+            self.enabled = value & self.wde != 0;
+            self.prescaler = self.wdp.extract(value as u64);
+            self.window = 0;
+        } else {
+            // WDE can be set without the sequence but not cleared
+            self.enabled |= value & self.wde != 0;
+            if sequence {
+                self.window = TIMED_CYCLES;
+            }
+        }
+        self.store(ctx.data);
+    }
+
+    fn tick(&mut self, ctx: &mut Context, cycles: u64) {
+        let data = &mut *ctx.data;
+        if self.window > 0 {
+            self.window = self.window.saturating_sub(cycles);
+        }
+        let control = read(data, self.control);
+        let reset_mode = self.enabled || self.forced(data);
+        let interrupt_mode = control & self.wdie != 0 && !self.always_on;
+        if !reset_mode && !interrupt_mode {
+            self.start = ctx.time;
+        } else if ctx.time - self.start >= self.period() {
+            self.start = ctx.time;
+            if interrupt_mode {
+                write(data, self.control, control | self.wdif);
+            } else {
+                self.reset = Some(ResetCause::Watchdog);
+            }
+        }
+        self.store(data);
+    }
+
+    fn pending_interrupts(&self, data: &DataMemory) -> Vec<u32> {
+        let control = read(data, self.control);
+        match control & self.wdif != 0 && control & self.wdie != 0 {
+            true => self.vector.into_iter().collect(),
+            false => vec![],
+        }
+    }
+
+    /// in interrupt and system reset mode the next timeout resets
+    fn acknowledge(&mut self, data: &mut DataMemory, vector: u32) {
+        if self.vector == Some(vector) {
+            let mut control = read(data, self.control) & !self.wdif;
+            if self.enabled {
+                control &= !self.wdie;
+            }
+            write(data, self.control, control);
+        }
+    }
+
+    fn take_reset(&mut self) -> Option<ResetCause> {
+        self.reset.take()
+    }
+
+    /// WDE is cleared but stays forced while WDRF is set
+    fn reset(&mut self, ctx: &mut Context) {
+        self.enabled = false;
+        self.prescaler = 0;
+        self.window = 0;
+        self.start = ctx.time;
+        self.store(ctx.data);
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::peripherals::bench::{Bench, store};
+
+    /// WDTCSR 0x60, memory holds the written value before the model sees it
+    fn write_control(bench: &mut Bench, wdt: &mut Watchdog, value: u8, cycles: u64) {
+        bench.data[0x60] = value;
+        bench.run(wdt, &[store(0x60, value)], cycles);
+    }
+
+    #[test]
+    fn test_timed_sequence() {
+        let mut bench = Bench::new("atmega328p");
+        let map = bench.map("WDT");
+        let mut wdt = Watchdog::new(bench.atdf, &map).unwrap();
+        // WDE can not be cleared without WDCE
+        write_control(&mut bench, &mut wdt, 0x08, 1);
+        write_control(&mut bench, &mut wdt, 0x00, 1);
+        assert_eq!(bench.data[0x60], 0x08);
+
+        write_control(&mut bench, &mut wdt, 0x18, 1);
+        assert_eq!(bench.data[0x60], 0x18);
+        write_control(&mut bench, &mut wdt, 0x21, 1);
+        assert_eq!((bench.data[0x60], wdt.prescaler), (0x21, 9));
+        assert!((wdt.period() - 8.192).abs() < 1e-9);
+
+        // too late
+        write_control(&mut bench, &mut wdt, 0x18, 5);
+        write_control(&mut bench, &mut wdt, 0x00, 1);
+        assert_eq!(bench.data[0x60], 0x29);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut bench = Bench::new("atmega328p");
+        let map = bench.map("WDT");
+        let mut wdt = Watchdog::new(bench.atdf, &map).unwrap();
+        // interrupt mode, 16 ms
+        write_control(&mut bench, &mut wdt, 0x40, 1);
+        bench.time = 0.017;
+        bench.run(&mut wdt, &[], 1);
+        assert_eq!(wdt.pending_interrupts(&bench.data), vec![6]);
+        assert!(wdt.take_reset().is_none());
+        wdt.acknowledge(&mut bench.data, 6);
+        assert_eq!(bench.data[0x60], 0x40);
+
+        // WDTON ignores WDIE and resets
+        wdt.always_on = true;
+        bench.time = 0.034;
+        bench.run(&mut wdt, &[], 1);
+        assert_eq!(wdt.take_reset(), Some(ResetCause::Watchdog));
+        assert_eq!(bench.data[0x60] & 0x08, 0x08);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::sim::debug_info::DebugInfo;
+use opcode_gen::Opcode;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressProfile {
+    pub address: u32,
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionProfile {
+    pub entry: u32, // byte address the function was called at
+    pub name: Option<String>, // symbol name when an elf is loaded
+    pub calls: u64,
+    pub instructions: u64,
+    pub exclusive_cycles: u64,
+    pub inclusive_cycles: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileReport {
+    pub total_instructions: u64,
+    pub total_cycles: u64,
+    pub addresses: Vec<AddressProfile>,
+    pub functions: Vec<FunctionProfile>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: u32,
+    entered_at: u64,
+}
+
+/// per address and per function counters, functions are tracked through calls and returns
+#[derive(Debug, Default)]
+pub struct Profile {
+    addresses: HashMap<u32, AddressProfile>,
+    functions: HashMap<u32, FunctionProfile>,
+    stack: Vec<Frame>,
+}
+
+impl Profile {
+    pub fn reset(&mut self) {
+        *self = Profile::default();
+    }
+
+    /// `cycle` is the counter before the instruction at `pc` executed, `next_pc`/`now` after
+    pub fn record(&mut self, pc: u32, opcode: &Opcode, cycle: u64, now: u64, next_pc: u32) {
+        if self.stack.is_empty() {
+            self.enter(pc, cycle);
+        }
+        let cycles = now - cycle;
+        let address = self.addresses.entry(pc).or_insert(AddressProfile {
+            address: pc,
+            ..Default::default()
+        });
+        address.instructions += 1;
+        address.cycles += cycles;
+
+        let top = self.stack.last().unwrap().entry;
+        let function = self.functions.get_mut(&top).unwrap();
+        function.instructions += 1;
+        function.exclusive_cycles += cycles;
+
+        match opcode {
+            Opcode::CALL | Opcode::RCALL | Opcode::ICALL | Opcode::EICALL => {
+                self.enter(next_pc, now);
+            }
+            Opcode::RET | Opcode::RETI if self.stack.len() > 1 => {
+                let frame = self.stack.pop().unwrap();
+                // recursive frames are already covered by the outermost one
+                if !self.stack.iter().any(|x| x.entry == frame.entry) {
+                    self.functions.get_mut(&frame.entry).unwrap().inclusive_cycles +=
+                        now - frame.entered_at;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// entering a function without a call instruction, e.g. an interrupt vector
+    pub fn enter(&mut self, entry: u32, now: u64) {
+        self.functions
+            .entry(entry)
+            .or_insert(FunctionProfile {
+                entry,
+                ..Default::default()
+            })
+            .calls += 1;
+        self.stack.push(Frame {
+            entry,
+            entered_at: now,
+        });
+    }
+
+    /// functions still on the call stack count up to `now`
+    pub fn report(&self, now: u64, debug: Option<&DebugInfo>) -> ProfileReport {
+        let mut functions: BTreeMap<u32, FunctionProfile> = self
+            .functions
+            .iter()
+            .map(|(key, val)| (*key, val.clone()))
+            .collect();
+        for (i, frame) in self.stack.iter().enumerate() {
+            if self.stack[..i].iter().any(|x| x.entry == frame.entry) {
+                continue;
+            }
+            if let Some(function) = functions.get_mut(&frame.entry) {
+                function.inclusive_cycles += now - frame.entered_at;
+            }
+        }
+        if let Some(debug) = debug {
+            for function in functions.values_mut() {
+                function.name = debug.functions.get(&function.entry).cloned();
+            }
+        }
+        let mut addresses: Vec<AddressProfile> = self.addresses.values().copied().collect();
+        addresses.sort_by_key(|x| x.address);
+        ProfileReport {
+            total_instructions: addresses.iter().map(|x| x.instructions).sum(),
+            total_cycles: addresses.iter().map(|x| x.cycles).sum(),
+            addresses,
+            functions: functions.into_values().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_attribution() {
+        let mut profile = Profile::default();
+        // main: nop, call f, nop   f: nop, ret
+        profile.record(0x0, &Opcode::NOP, 0, 1, 0x2);
+        profile.record(0x2, &Opcode::CALL, 1, 5, 0x10);
+        profile.record(0x10, &Opcode::NOP, 5, 6, 0x12);
+        profile.record(0x12, &Opcode::RET, 6, 10, 0x6);
+        profile.record(0x6, &Opcode::NOP, 10, 11, 0x8);
+
+        let mut debug = DebugInfo::default();
+        debug.functions.insert(0x10, "f".to_string());
+        let report = profile.report(11, Some(&debug));
+        assert_eq!(report.total_instructions, 5);
+        assert_eq!(report.total_cycles, 11);
+        assert_eq!(report.addresses[1].cycles, 4);
+
+        let main = &report.functions[0];
+        assert_eq!((main.entry, main.calls), (0x0, 1));
+        assert_eq!(main.exclusive_cycles, 6);
+        assert_eq!(main.inclusive_cycles, 11);
+        let f = &report.functions[1];
+        assert_eq!((f.entry, f.calls, f.instructions), (0x10, 1, 2));
+        assert_eq!(f.name.as_deref(), Some("f"));
+        assert_eq!(f.exclusive_cycles, 5);
+        assert_eq!(f.inclusive_cycles, 5);
+    }
+
+    #[test]
+    fn test_recursion_counted_once() {
+        let mut profile = Profile::default();
+        profile.record(0x0, &Opcode::RCALL, 0, 3, 0x10);
+        profile.record(0x10, &Opcode::RCALL, 3, 6, 0x10);
+        profile.record(0x10, &Opcode::RET, 6, 10, 0x12);
+        profile.record(0x12, &Opcode::RET, 10, 14, 0x2);
+        let f = &profile.report(14, None).functions[1];
+        assert_eq!(f.calls, 2);
+        assert_eq!(f.inclusive_cycles, 11);
+    }
+}
@@ -2,11 +2,17 @@
 
 use crate::error::Result;
 use crate::project::Project;
+use crate::sim::core::Core;
 use crate::sim::instruction::Instruction;
-use crate::sim::memory::Memory;
+use crate::sim::memory::{AccessKind, MemAccess, Memory, MemorySpace};
+use crate::sim::peripherals::clock::Clock;
+use crate::sim::peripherals::fuse::Fuses;
+use crate::sim::peripherals::{Bit, InstanceMap, Peripherals, ResetCause};
+use crate::sim::timing;
 use anyhow::anyhow;
 use bin_expr_parser_macro::execute;
 use device_parser::r#struct::common_registers::Flags;
+use device_parser::capabilities::Capabilities;
 use device_parser::{AvrDeviceFile, CommonRegisters, get_common_registers};
 use opcode_gen::{CustomOpcodes, Opcode, RawInst};
 use std::sync::LazyLock;
@@ -19,6 +25,17 @@ pub struct Sim<'a> {
     registers: CommonRegisters,
     pub pc_len: u32,
     pub pc_bytesize: u32, //used for calls
+    pub core: Core,
+    pub cycles: u64,
+    pub accesses: Vec<MemAccess>, //data accesses of the last executed instruction
+    pub peripherals: Peripherals,
+    pub interrupted: Option<u32>, // vector address entered after the last instruction
+    pub reset: Option<ResetCause>, // reset caused by the last instruction
+    pub retired: (u32, u64),       // pc and cycles after the last instruction, before an interrupt or reset
+    atdf: Option<&'static AvrDeviceFile>,
+    vector_size: u32,             // bytes per interrupt vector
+    interrupt_delay: bool,        // the instruction after SEI and RETI runs before an interrupt
+    servicing: bool,              // AVRxt keeps I set, its level 0 interrupts do not nest
 }
 impl<'a> Default for Sim<'a> {
     fn default() -> Sim<'a> {
@@ -28,6 +45,17 @@ impl<'a> Default for Sim<'a> {
             registers: CommonRegisters::default(),
             pc_len: 0,
             pc_bytesize: 0,
+            core: Core::AVRe,
+            cycles: 0,
+            accesses: Vec::new(),
+            peripherals: Peripherals::default(),
+            interrupted: None,
+            reset: None,
+            retired: (0, 0),
+            atdf: None,
+            vector_size: 2,
+            interrupt_delay: false,
+            servicing: false,
         }
     }
 }
@@ -42,6 +70,54 @@ impl<'a> Sim<'a> {
         let inst = project.get_instruction_list()?;
         self.memory = unsafe { &mut *(memory as *mut Memory) };
         self.init_iner(atdf, inst, eeprom)?;
+        let state = project.get_state()?;
+        let fuses = Fuses::init(atdf, &state.fuses, &state.lockbits);
+        if !state.fuses.is_empty() {
+            self.set_fuses(atdf, fuses, state.freq);
+        } else {
+            if state.freq != 0 {
+                self.peripherals.clock = Clock::init(atdf, None, state.freq, &mut self.memory.data);
+            }
+            self.peripherals.set_fuses(fuses);
+        }
+        Ok(())
+    }
+    /// CKSEL and CKDIV8 pick the clock with `frequency` as the external clock or crystal,
+    /// BOOTRST moves the reset vector
+    pub fn set_fuses(&mut self, atdf: &'static AvrDeviceFile, fuses: Fuses, frequency: u32) {
+        let frequency = match frequency {
+            0 => Peripherals::DEFAULT_FREQUENCY,
+            x => x,
+        };
+        self.peripherals.clock = Clock::init(atdf, Some(&fuses.bytes), frequency, &mut self.memory.data);
+        self.memory.program_couter = fuses.reset_vector();
+        self.peripherals.set_fuses(fuses);
+    }
+    /// watchdog and brown-out reset: i/o registers return to their reset values, the reset flags
+    /// in MCUSR are kept and the cpu starts again at the reset vector
+    fn reset(&mut self, cause: ResetCause) -> Result<()> {
+        let atdf = self.atdf.ok_or(anyhow!("sim not initialized"))?;
+        let cpu = InstanceMap::find(atdf, "CPU").into_iter().next();
+        let mcusr = cpu.as_ref().and_then(|x| x.address("MCUSR"));
+        let flags = mcusr.map(|x| self.memory.data[x as usize]);
+        self.memory.data.reset(atdf);
+        if let (Some(address), Some(flags)) = (mcusr, flags) {
+            self.memory.data[address as usize] = flags;
+        }
+        if let Some(flag) = cpu.as_ref().and_then(|x| Bit::find(x, cause.flag())) {
+            flag.set(&mut self.memory.data, true);
+        }
+        self.peripherals.reset(atdf, &mut self.memory.data);
+        let ramend = (self.memory.data.len() - 1) as u16;
+        unsafe {
+            // devices without sram have a hardware stack and no SP
+            self.registers.spL.try_set((ramend & 0xff) as u8);
+            self.registers.spH.try_set((ramend >> 8) as u8);
+        }
+        self.memory.program_couter = self.peripherals.fuses.reset_vector();
+        self.reset = Some(cause);
+        self.interrupt_delay = false;
+        self.servicing = false;
         Ok(())
     }
     pub fn init_iner(
@@ -57,7 +133,7 @@ impl<'a> Sim<'a> {
                 .address_spaces
                 .iter()
                 .find(|x| x.id == "prog")
-                .unwrap()
+                .ok_or(anyhow!("no prog address space"))?
                 .size
                 / 2) as usize,
             Instruction::new("".to_string(), CustomOpcodes::EMPTY as usize, vec![], 0),
@@ -81,6 +157,13 @@ impl<'a> Sim<'a> {
             .ok_or(anyhow!("mcu not supported"))?);
         self.registers
             .init_regs(atdf, &mut self.memory.data.io.inner)?;
+        self.core = Core::from_atdf(atdf);
+        self.atdf = Some(atdf);
+        self.cycles = 0;
+        self.peripherals = Peripherals::init(atdf, &mut self.memory.data)?;
+        self.interrupted = None;
+        self.interrupt_delay = false;
+        self.servicing = false;
         let pc_size = atdf
             .devices
             .address_spaces
@@ -93,6 +176,7 @@ impl<'a> Sim<'a> {
         } else {
             Err(anyhow!("pc_size ==0"))?;
         }
+        self.vector_size = Capabilities::new(atdf).vector_size;
         if self.pc_len >= 8 && self.pc_len <= 15 {
             self.pc_bytesize = 2;
         } else if self.pc_len >= 16 && self.pc_len <= 17 {
@@ -110,6 +194,17 @@ impl<'a> Sim<'a> {
             registers: CommonRegisters::default(),
             pc_len: 0,
             pc_bytesize: 0,
+            core: Core::AVRe,
+            cycles: 0,
+            accesses: Vec::new(),
+            peripherals: Peripherals::default(),
+            interrupted: None,
+            reset: None,
+            retired: (0, 0),
+            atdf: None,
+            vector_size: 2,
+            interrupt_delay: false,
+            servicing: false,
         };
         s.init_iner(atdf, flash, vec![])?;
         Ok(s)
@@ -133,19 +228,130 @@ impl<'a> Sim<'a> {
     unsafe fn get_flag(&mut self, flags: Flags) -> bool {
         unsafe { self.registers.get_flag(flags) }
     }
+    pub unsafe fn get_sreg(&self) -> u8 {
+        unsafe { self.registers.sreg.try_get().unwrap_or(0) }
+    }
+    pub fn set_register(&mut self, index: u8, value: u8) -> Result<()> {
+        *self
+            .memory
+            .data
+            .registers
+            .get_mut(index as usize)
+            .ok_or(anyhow!("invalid register:r{}", index))? = value;
+        Ok(())
+    }
+    pub unsafe fn set_sreg_flag(&mut self, bit: u8, value: bool) -> Result<()> {
+        let flag = Flags::get_flag(bit)?;
+        unsafe { self.set_flag(flag, value) };
+        Ok(())
+    }
+    pub unsafe fn set_sp(&mut self, sp: u16) -> Result<()> {
+        if sp as usize >= self.memory.data.len() {
+            return Err(anyhow!("invalid stack pointer:{:#x}", sp));
+        }
+        unsafe {
+            if sp > 0xff && self.registers.spH.try_get().is_none() {
+                return Err(anyhow!("stack pointer has no high byte:{:#x}", sp));
+            }
+            self.registers
+                .spL
+                .try_set((sp & 0xff) as u8)
+                .ok_or(anyhow!("no stack pointer"))?;
+            self.registers.spH.try_set(((sp >> 8) & 0xff) as u8);
+        }
+        Ok(())
+    }
+    /// data space pokes reach the peripherals like a bus write, other memory is written directly
+    pub fn poke(&mut self, space: MemorySpace, address: u32, value: u8) -> Result<()> {
+        match space {
+            MemorySpace::Data if self.memory.data.mapped(address).is_none() && (address as usize) < self.memory.data.len() => {
+                self.peripherals.poke(&mut self.memory.data, address, value);
+                Ok(())
+            }
+            _ => self.memory.poke(space, address, value),
+        }
+    }
+    pub fn set_pc(&mut self, pc: u32) -> Result<()> {
+        if !pc.is_multiple_of(2) || pc as usize >= self.memory.flash.len() {
+            return Err(anyhow!("invalid pc:{:#x}", pc));
+        }
+        self.memory.program_couter = pc;
+        Ok(())
+    }
+    fn log_access(&mut self, kind: AccessKind, address: u32, value: u8, width: u8) {
+        self.accesses.push(MemAccess {
+            kind,
+            address,
+            value,
+            width,
+            pc: self.memory.program_couter,
+        });
+    }
+    /// data space read through the peripherals or from mapped memory, `width` is the size of the whole transfer
+    fn bus_read(&mut self, address: u32, width: u8) -> Result<u8> {
+        if let Some((space, offset)) = self.memory.data.mapped(address) {
+            let value = match space {
+                MemorySpace::Flash => (self.memory.flash_word(offset & !1) >> ((offset & 1) * 8)) as u8,
+                _ => self.memory.read(space, offset, 1).map_or(0xff, |x| x[0]),
+            };
+            self.log_access(AccessKind::Read, address, value, width);
+            return Ok(value);
+        }
+        if address as usize >= self.memory.data.len() {
+            return Err(anyhow!("invalid data address:{:#x}", address));
+        }
+        let value = self.peripherals.read(&mut self.memory.data, address);
+        self.log_access(AccessKind::Read, address, value, width);
+        Ok(value)
+    }
+    /// writes to mapped eeprom and flash need the nvm controller and are dropped
+    fn bus_write(&mut self, address: u32, width: u8, value: u8) -> Result<()> {
+        if self.memory.data.mapped(address).is_some() {
+            self.log_access(AccessKind::Write, address, value, width);
+            return Ok(());
+        }
+        if address as usize >= self.memory.data.len() {
+            return Err(anyhow!("invalid data address:{:#x}", address));
+        }
+        self.peripherals.write(&mut self.memory.data, address, value);
+        self.log_access(AccessKind::Write, address, value, width);
+        Ok(())
+    }
+    /// SBI and CBI on the io register `index`; older devices write back the whole byte,
+    /// so flags that are set next to the addressed bit are cleared as well
+    fn bus_write_bit(&mut self, index: usize, bit: u8, set: bool) -> Result<()> {
+        let address = self.io_address(index);
+        let old = self.bus_read(address, 1)?;
+        let value = self.peripherals.access.bit_write(address, old, 1 << bit, set);
+        self.bus_write(address, 1, value)
+    }
+    /// pushes the pc and jumps to the interrupt `vector`
+    unsafe fn interrupt(&mut self, vector: u32) -> Result<()> {
+        unsafe {
+            self.push(self.memory.program_couter, self.pc_bytesize)?;
+            match self.core {
+                Core::AVRxt => self.servicing = true,
+                _ => self.set_flag(Flags::I, false),
+            }
+            self.peripherals.acknowledge(&mut self.memory.data, vector);
+            self.memory.program_couter = vector * self.vector_size;
+            self.interrupted = Some(self.memory.program_couter);
+            self.cycles += 2 + self.pc_bytesize as u64;
+        }
+        Ok(())
+    }
+    fn io_address(&self, index: usize) -> u32 {
+        (index + self.memory.data.io.reg_size) as u32
+    }
 
     unsafe fn push(&mut self, data: u32, len: u32) -> Result<()> {
         unsafe {
             let mut sp: u16 = ((self.registers.spH.try_get().or(Some(0)).unwrap() as u16) << 8)
-                + (self.registers.spL.get_data() as u16);
+                + (self.registers.spL.try_get().ok_or(anyhow!("no stack pointer"))? as u16);
             //sp &= 2u16.pow(self.pc_len)-1;
             for i in 0..(len as u16) {
-                *self
-                    .memory
-                    .data
-                    .get_mut(sp as usize - i as usize)
-                    .ok_or(anyhow!("invalid ram offset: {}", sp))? =
-                    ((data >> (8 * i)) & 0xff) as u8;
+                let value = ((data >> (8 * i)) & 0xff) as u8;
+                self.bus_write((sp - i) as u32, len as u8, value)?;
             }
             sp -= len as u16;
             self.registers.spL.set_data((sp & 0xff) as u8);
@@ -156,25 +362,27 @@ impl<'a> Sim<'a> {
     unsafe fn pop(&mut self, len: u32) -> Result<u32> {
         unsafe {
             let mut sp: u16 = ((self.registers.spH.try_get().or(Some(0)).unwrap() as u16) << 8)
-                + (self.registers.spL.get_data() as u16);
+                + (self.registers.spL.try_get().ok_or(anyhow!("no stack pointer"))? as u16);
             //sp &= 2u16.pow(self.pc_len)-1;
             let mut data: u32 = 0;
             for i in 0..(len as u16) {
                 data = data << 8;
-                data += *self
-                    .memory
-                    .data
-                    .get((sp + i) as usize)
-                    .ok_or(anyhow!("invalid ram offset: {}", sp))? as u32;
+                let value = self.bus_read((sp + i) as u32, len as u8)?;
+                data += value as u32;
             }
             sp += len as u16;
             self.registers.spL.set_data((sp & 0xff) as u8);
-            self.registers.spH.set_data(((sp >> 8) & 0xff) as u8);
+            self.registers.spH.try_set(((sp >> 8) & 0xff) as u8);
             Ok(data)
         }
     }
     pub unsafe fn execute_inst(&mut self) -> Result<()> {
         unsafe {
+            self.accesses.clear();
+            self.interrupted = None;
+            self.reset = None;
+            self.interrupt_delay = false;
+            let start = self.cycles;
             let instruction = self
                 .memory
                 .flash
@@ -528,7 +736,7 @@ impl<'a> Sim<'a> {
                     Ok(false)
                 }
                 Opcode::CBI => {
-                    self.memory.data.io[op1 as usize] &= 0xff - (1 << (op2 as u8));
+                    self.bus_write_bit(ind1, op2 as u8, false)?;
                     Ok(true)
                 }
                 Opcode::CBR => {
@@ -711,14 +919,14 @@ impl<'a> Sim<'a> {
                     Ok(false)
                 }
                 Opcode::ELPM => {
-                    //todo might have issues
                     let mut ptr = (reg[30] as u32)
                         + ((reg[31] as u32) << 8)
-                        + ((self.registers.rampz.get_data() as u32) << 16)
-                        >> 1;
-                    let data: u16 = self.memory.flash[(ptr >> 1) as usize].raw_opcode as u16;
-
-                    reg[op1 as usize] = (data >> (8 * (ptr & 1))) as u8;
+                        + ((self.registers.rampz.get_data() as u32) << 16);
+                    let z = (reg[30] as u32) + ((reg[31] as u32) << 8);
+                    reg[op1 as usize] = match self.peripherals.lpm(&mut self.memory.data, z) {
+                        Some(x) => x,
+                        None => (self.memory.flash_word(ptr & !1) >> (8 * (ptr & 1))) as u8,
+                    };
                     if op2 != 0 {
                         ptr += 1;
                         reg[30] = (ptr & 0xff) as u8;
@@ -787,7 +995,7 @@ impl<'a> Sim<'a> {
                     Ok(false)
                 }
                 Opcode::IN => {
-                    reg[ind1] = self.memory.data.io[ind2];
+                    reg[ind1] = self.bus_read(self.io_address(ind2), 1)?;
                     Ok(true)
                 }
                 Opcode::INC => {
@@ -807,22 +1015,22 @@ impl<'a> Sim<'a> {
                 }
                 Opcode::LAC => {
                     let ptr = (reg[30] as u16) + ((reg[31] as u16) << 8);
-                    let tmp = self.memory.data.ram[ptr as usize];
-                    self.memory.data.ram[ptr as usize] &= 0xff - *ra?;
+                    let tmp = self.bus_read(ptr as u32, 1)?;
+                    self.bus_write(ptr as u32, 1, tmp & (0xff - *ra?))?;
                     reg[ind1] = tmp;
                     Ok(true)
                 }
                 Opcode::LAS => {
                     let ptr = (reg[30] as u16) + ((reg[31] as u16) << 8);
-                    let tmp = self.memory.data.ram[ptr as usize];
-                    self.memory.data.ram[ptr as usize] |= *ra?;
+                    let tmp = self.bus_read(ptr as u32, 1)?;
+                    self.bus_write(ptr as u32, 1, tmp | *ra?)?;
                     reg[ind1] = tmp;
                     Ok(true)
                 }
                 Opcode::LAT => {
                     let ptr = (reg[30] as u16) + ((reg[31] as u16) << 8);
-                    let tmp = self.memory.data.ram[ptr as usize];
-                    self.memory.data.ram[ptr as usize] = !self.memory.data.ram[ptr as usize] & *ra?;
+                    let tmp = self.bus_read(ptr as u32, 1)?;
+                    self.bus_write(ptr as u32, 1, !tmp & *ra?)?;
                     reg[ind1] = tmp;
                     Ok(true)
                 }
@@ -847,7 +1055,7 @@ impl<'a> Sim<'a> {
                         ptr -= 1;
                     }
 
-                    reg[ind1] = self.memory.data[ptr as usize];
+                    reg[ind1] = self.bus_read(ptr, 1)?;
 
                     if op3 == 1 {
                         ptr += 1;
@@ -898,7 +1106,7 @@ impl<'a> Sim<'a> {
                         }
                         _ => Err(anyhow!("invalid opcode")),
                     }?;
-                    reg[ind1] = self.memory.data[ptr as usize];
+                    reg[ind1] = self.bus_read(ptr, 1)?;
                     Ok(true)
                 }
                 Opcode::LDI => {
@@ -906,15 +1114,15 @@ impl<'a> Sim<'a> {
                     Ok(true)
                 }
                 Opcode::LDS => {
-                    reg[ind1] = self.memory.data[ind2];
+                    reg[ind1] = self.bus_read(ind2 as u32, 1)?;
                     Ok(true)
                 }
                 Opcode::LPM => {
-                    //todo might have issues
                     let mut ptr = (reg[30] as u16) + ((reg[31] as u16) << 8);
-                    let data: u16 = self.memory.flash[(ptr >> 1) as usize].raw_opcode as u16;
-
-                    reg[op1 as usize] = (data >> (8 * (ptr & 1))) as u8;
+                    reg[op1 as usize] = match self.peripherals.lpm(&mut self.memory.data, ptr as u32) {
+                        Some(x) => x,
+                        None => (self.memory.flash_word(ptr as u32 & !1) >> (8 * (ptr & 1))) as u8,
+                    };
                     if op2 != 0 {
                         ptr += 1;
                         reg[30] = (ptr & 0xff) as u8;
@@ -1032,7 +1240,7 @@ impl<'a> Sim<'a> {
                     Ok(true)
                 }
                 Opcode::OUT => {
-                    self.memory.data.io[ind1] = *rb?;
+                    self.bus_write(self.io_address(ind1), 1, *rb?)?;
                     Ok(true)
                 }
                 Opcode::POP => {
@@ -1061,7 +1269,11 @@ impl<'a> Sim<'a> {
                 }
                 Opcode::RETI => {
                     self.memory.program_couter = self.pop(self.pc_bytesize)?;
-                    self.set_flag(Flags::I, true);
+                    match self.core {
+                        Core::AVRxt => self.servicing = false,
+                        _ => self.set_flag(Flags::I, true),
+                    }
+                    self.interrupt_delay = true;
                     Ok(false)
                 }
                 Opcode::RJMP => {
@@ -1154,12 +1366,11 @@ impl<'a> Sim<'a> {
                     Ok(true)
                 }
                 Opcode::SBI => {
-                    self.memory.data.io[ind1] |= 1 << op2;
-
+                    self.bus_write_bit(ind1, op2 as u8, true)?;
                     Ok(true)
                 }
                 Opcode::SBIC => {
-                    if ((self.memory.data.io[ind1] >> op2) & 1) == 0 {
+                    if ((self.bus_read(self.io_address(ind1), 1)? >> op2) & 1) == 0 {
                         self.memory.program_couter += self.memory.flash
                             [(self.memory.program_couter + 1) as usize]
                             .get_raw_inst()?
@@ -1168,7 +1379,7 @@ impl<'a> Sim<'a> {
                     Ok(true)
                 }
                 Opcode::SBIS => {
-                    if ((self.memory.data.io[ind1] >> op2) & 1) == 1 {
+                    if ((self.bus_read(self.io_address(ind1), 1)? >> op2) & 1) == 1 {
                         self.memory.program_couter += self.memory.flash
                             [(self.memory.program_couter + 1) as usize]
                             .get_raw_inst()?
@@ -1230,6 +1441,7 @@ impl<'a> Sim<'a> {
                     Ok(true)
                 }
                 Opcode::SEI => {
+                    self.interrupt_delay = !self.get_flag(Flags::I);
                     self.set_flag(Flags::I, true);
                     Ok(true)
                 }
@@ -1296,7 +1508,7 @@ impl<'a> Sim<'a> {
                         ptr -= 1;
                     }
 
-                    self.memory.data[ptr as usize] = reg[ind3];
+                    self.bus_write(ptr, 1, reg[ind3])?;
 
                     if op2 == 1 {
                         ptr += 1;
@@ -1347,11 +1559,11 @@ impl<'a> Sim<'a> {
                         }
                         x => Err(anyhow!("invalid opcode {}", x)),
                     }?;
-                    self.memory.data[ptr as usize] = reg[ind3];
+                    self.bus_write(ptr, 1, reg[ind3])?;
                     Ok(true)
                 }
                 Opcode::STS => {
-                    self.memory.data[ind1] = *rb?;
+                    self.bus_write(ind1 as u32, 1, *rb?)?;
                     Ok(true)
                 }
                 Opcode::SUB => {
@@ -1405,12 +1617,13 @@ impl<'a> Sim<'a> {
                     Ok(true)
                 }
                 Opcode::WDR => {
-                    todo!();
+                    self.peripherals.watchdog_restart();
+                    Ok(true)
                 }
                 Opcode::XCH => {
                     let ptr = reg[30] as u16 + (reg[31] as u16) << 8;
-                    let data = self.memory.data[ptr as usize];
-                    self.memory.data[ptr as usize] = *ra?;
+                    let data = self.bus_read(ptr as u32, 1)?;
+                    self.bus_write(ptr as u32, 1, *ra?)?;
                     reg[ind1] = data;
                     Ok(true)
                 }
@@ -1419,6 +1632,22 @@ impl<'a> Sim<'a> {
                 self.memory.program_couter += (instruction.get_raw_inst()?.len * 2) as u32;
             }
             self.memory.data.registers = reg;
+            // instructions the timing table does not know for this core still take a cycle
+            self.cycles += timing::get_time(&self.core, &instruction, self).unwrap_or(1) as u64;
+
+            self.retired = (self.memory.program_couter, self.cycles);
+            self.peripherals
+                .update(&mut self.memory.data, &self.accesses, self.cycles - start);
+            if let Some(cause) = self.peripherals.take_reset() {
+                return self.reset(cause);
+            }
+            if !self.interrupt_delay
+                && !self.servicing
+                && self.get_flag(Flags::I)
+                && let Some(vector) = self.peripherals.pending_interrupt(&self.memory.data)
+            {
+                self.interrupt(vector)?;
+            }
             Ok(())
         }
     }
@@ -1869,3 +2098,240 @@ mod opcode_tests {
 
     }
 }
+
+#[cfg(test)]
+mod interrupt_tests {
+    use super::*;
+    use crate::sim::operand::Operand;
+    use crate::sim::peripherals::find;
+    use crate::sim::peripherals::spi::Spi;
+    use device_parser::get_tree_map;
+
+    pub(super) fn inst(opcode: Opcode, operands: &[i64], address: u32) -> Instruction {
+        let operands = operands
+            .iter()
+            .map(|x| {
+                let mut operand = Operand::default();
+                operand.value = *x;
+                operand
+            })
+            .collect();
+        Instruction::new("".to_string(), RawInst::get_inst_id_from_opcode(opcode).unwrap(), operands, address)
+    }
+
+    #[test]
+    fn test_spi_interrupt_after_sei() -> Result<()> {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let flash = vec![inst(Opcode::SEI, &[], 0), inst(Opcode::NOP, &[], 2), inst(Opcode::NOP, &[], 4)];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        unsafe { s.debug_init_stack()? };
+        // enabled slave with SPIE, SS floats low
+        s.memory.data[0x4c] = 0xc0;
+        let Sim { memory, peripherals, .. } = &mut s;
+        let spi: &mut Spi = find(&mut peripherals.models, "SPI").unwrap();
+        spi.receive(&mut memory.data, &peripherals.gpio, 0x5a)?;
+
+        s.exec_debug()?;
+        assert_eq!((s.memory.program_couter, s.interrupted), (2, None));
+        s.exec_debug()?;
+        // SPI_STC is vector 17, two words per vector
+        assert_eq!(s.interrupted, Some(0x44));
+        assert_eq!(s.memory.program_couter, 0x44);
+        // the NOP fell through to 4, the entry cycles come after it
+        assert_eq!(s.retired, (4, 2));
+        assert_eq!(s.cycles, 6);
+        unsafe { assert!(!s.get_flag(Flags::I)) };
+        assert_eq!(s.memory.data[0x4d] & 0x80, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bus_accesses() -> Result<()> {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        // SPSR 0x2d, SPDR 0x2e in io space
+        let flash = vec![
+            inst(Opcode::IN, &[16, 0x2d], 0),
+            inst(Opcode::IN, &[17, 0x2e], 2),
+            inst(Opcode::CALL, &[0x10], 4),
+        ];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        unsafe { s.debug_init_stack()? };
+        s.memory.data[0x4c] = 0x40;
+        let Sim { memory, peripherals, .. } = &mut s;
+        let spi: &mut Spi = find(&mut peripherals.models, "SPI").unwrap();
+        spi.receive(&mut memory.data, &peripherals.gpio, 0x5a)?;
+
+        // reading SPDR after SPSR clears SPIF
+        s.exec_debug()?;
+        assert_eq!(s.memory.data.registers[16] & 0x80, 0x80);
+        s.exec_debug()?;
+        assert_eq!(s.memory.data.registers[17], 0x5a);
+        assert_eq!(s.memory.data[0x4d] & 0x80, 0);
+        assert_eq!((s.accesses[0].address, s.accesses[0].pc), (0x4e, 2));
+
+        s.exec_debug()?;
+        assert_eq!(s.accesses.len(), 2);
+        assert!(s.accesses.iter().all(|x| x.kind == AccessKind::Write && x.width == 2 && x.pc == 4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_poke() -> Result<()> {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let flash = vec![inst(Opcode::NOP, &[], 0), inst(Opcode::NOP, &[], 2), inst(Opcode::RJMP, &[-4], 4)];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        // TIFR1 0x36 is stored as is, writing a one does not clear the flags
+        s.poke(MemorySpace::Data, 0x36, 0x05)?;
+        assert_eq!(s.memory.data[0x36], 0x05);
+
+        // SS output, SPCR 0x4c master; poking SPDR 0x4e starts a transfer
+        s.memory.data[0x24] = 0x04;
+        s.memory.data[0x4c] = 0x50;
+        s.poke(MemorySpace::Data, 0x4e, 0xa5)?;
+        while s.cycles < 64 {
+            s.exec_debug()?;
+        }
+        assert_eq!(s.memory.data[0x4d] & 0x80, 0x80);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lpm_flash_constant() -> Result<()> {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let flash = vec![inst(Opcode::LPM, &[16, 1], 0), inst(Opcode::LPM, &[17, 0], 2)];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        s.memory.set_flash_word(0x100, 0x1234)?;
+        s.memory.data.registers[31] = 0x01;
+        s.exec_debug()?;
+        s.exec_debug()?;
+        assert_eq!((s.memory.data.registers[16], s.memory.data.registers[17]), (0x34, 0x12));
+        assert_eq!(s.memory.data.registers[30], 0x01);
+
+        // RAMPZ 0x5b selects the upper 64KB
+        let atdf = get_tree_map().get("atmega2560").unwrap();
+        let flash = vec![inst(Opcode::ELPM, &[16, 1], 0), inst(Opcode::ELPM, &[17, 1], 2)];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        s.memory.set_flash_word(0x1fffe, 0x5678)?;
+        s.memory.set_flash_word(0x20000, 0x9abc)?;
+        s.memory.data[0x5b] = 0x01;
+        s.memory.data.registers[30] = 0xff;
+        s.memory.data.registers[31] = 0xff;
+        s.exec_debug()?;
+        s.exec_debug()?;
+        assert_eq!((s.memory.data.registers[16], s.memory.data.registers[17]), (0x56, 0xbc));
+        assert_eq!(s.memory.data[0x5b], 0x02);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mapped_flash_read() -> Result<()> {
+        // 48KB of flash mapped from 0x4000, the upper half included
+        let atdf = get_tree_map().get("atmega4809").unwrap();
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, vec![], &mut memory)?;
+        s.memory.set_flash_word(0x7e00, 0xef0f)?;
+        assert_eq!((s.bus_read(0xbe00, 1)?, s.bus_read(0xbe01, 1)?), (0x0f, 0xef));
+        assert_eq!(s.bus_read(0xfffe, 1)?, 0xff);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sbi_on_flag_register() -> Result<()> {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        // TIFR1 is io 0x16, OCF1B and TOV1 are pending
+        let flash = vec![inst(Opcode::SBI, &[0x16, 1], 0), inst(Opcode::OUT, &[0x16, 16], 2)];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        s.memory.data[0x36] = 0x05;
+        // only the addressed bit is written, the pending flags stay set
+        s.exec_debug()?;
+        assert_eq!(s.memory.data[0x36], 0x05);
+
+        s.memory.data[0x36] = 0x05;
+        s.memory.data.registers[16] = 0xc4;
+        s.exec_debug()?;
+        assert_eq!(s.memory.data[0x36], 0x01);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuses() -> Result<()> {
+        let atdf = get_tree_map().get("atmega328p").unwrap();
+        let flash = vec![
+            inst(Opcode::LPM, &[16, 0], 0),
+            inst(Opcode::NOP, &[], 2),
+            inst(Opcode::RJMP, &[-4], 4),
+        ];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        let mut fuses = Fuses::init(atdf, &[], &[]);
+        fuses.set("BOOTRST", "0")?;
+        fuses.set("BOOTSZ", "256W_3F00")?;
+        fuses.set("WDTON", "0")?;
+        s.set_fuses(atdf, fuses, 16_000_000);
+        assert_eq!(s.memory.program_couter, 0x7e00);
+        assert_eq!(s.peripherals.clock.frequency(), 1_000_000);
+        s.set_pc(0x7e00)?;
+        assert!(s.set_pc(0x8000).is_err());
+
+        // SPMCSR 0x57 with BLBSET and SPMEN, Z 3 reads the high fuse
+        s.set_pc(0)?;
+        s.memory.data[0x57] = 0x09;
+        s.memory.data.registers[30] = 3;
+        s.exec_debug()?;
+        assert_eq!((s.memory.data.registers[16], s.memory.data[0x57]), (0xce, 0x00));
+
+        // WDTON resets after 16 ms of the 1 MHz clock
+        while s.reset.is_none() && s.cycles < 20_000 {
+            s.exec_debug()?;
+        }
+        assert_eq!(s.reset, Some(ResetCause::Watchdog));
+        assert!(s.cycles > 16_000);
+        assert_eq!(s.memory.program_couter, 0x7e00);
+        // MCUSR 0x54 WDRF, WDTCSR 0x60 WDE
+        assert_eq!(s.memory.data[0x54] & 0x08, 0x08);
+        assert_eq!(s.memory.data[0x60] & 0x08, 0x08);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod compatibility_tests {
+    use super::*;
+    use super::interrupt_tests::inst;
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    /// NOP; loop: NOP; RJMP loop, run for a few iterations
+    fn nop_loop(atdf: &'static AvrDeviceFile) -> Result<()> {
+        let flash = vec![inst(Opcode::NOP, &[], 0), inst(Opcode::NOP, &[], 2), inst(Opcode::RJMP, &[-4], 4)];
+        let mut memory = Memory::default();
+        let mut s = Sim::init_debug(atdf, flash, &mut memory)?;
+        for _ in 0..8 {
+            s.exec_debug()?;
+            if s.memory.program_couter > 4 {
+                return Err(anyhow!("left the loop at {:#x}", s.memory.program_couter));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compatibility_matrix() {
+        let failed: Vec<(&str, String)> = device_parser::MCU_LIST
+            .iter()
+            .filter_map(|mcu| {
+                let atdf = device_parser::get_device(mcu).unwrap();
+                match catch_unwind(AssertUnwindSafe(|| nop_loop(atdf))) {
+                    Ok(x) => x.err().map(|e| (*mcu, e.to_string())),
+                    Err(_) => Some((*mcu, "panicked".to_string())),
+                }
+            })
+            .collect();
+        assert!(failed.is_empty(), "{:?}", failed);
+    }
+}
@@ -5,9 +5,11 @@ use crate::sim::sim::Sim;
 use anyhow::anyhow;
 use opcode_gen::Opcode;
 
-#[allow(dead_code)]
-fn get_time(core: Core, inst: Instruction, sim: Sim) -> Result<u8> {
+/// cycles taken by `inst`, evaluated after it executed (skips and branches look at the new pc)
+pub fn get_time(core: &Core, inst: &Instruction, sim: &Sim) -> Result<u8> {
     let err = Err(anyhow!("not supperted on this core"));
+    // pc and addresses are in bytes
+    let words_moved = sim.memory.program_couter.wrapping_sub(inst.address) / 2;
     match inst.get_raw_inst()?.name {
         Opcode::ADD | Opcode::ADC | Opcode::SUB | Opcode::SUBI | Opcode::SBC | Opcode::SBCI => {
             Ok(1)
@@ -41,7 +43,7 @@ fn get_time(core: Core, inst: Instruction, sim: Sim) -> Result<u8> {
             _ => Ok(2),
         },
         Opcode::DES => match core {
-            Core::AVRxm => match sim.memory.flash.get((inst.address - 1) as usize) {
+            Core::AVRxm => match sim.memory.flash.get(inst.address.wrapping_sub(2) as usize) {
                 None => Ok(2),
                 Some(i) => {
                     if i.get_raw_inst()?.name == Opcode::DES {
@@ -112,7 +114,7 @@ fn get_time(core: Core, inst: Instruction, sim: Sim) -> Result<u8> {
         },
 
         Opcode::CPSE | Opcode::SBRC | Opcode::SBRS | Opcode::SBIC | Opcode::SBIS => {
-            match sim.memory.program_couter - inst.address {
+            match words_moved {
                 1 => Ok(1),
                 2 => Ok(2),
                 3 => match core {
@@ -143,7 +145,7 @@ fn get_time(core: Core, inst: Instruction, sim: Sim) -> Result<u8> {
         | Opcode::BRVC
         | Opcode::BRIE
         | Opcode::BRID => {
-            if sim.memory.program_couter - inst.address == 1 {
+            if words_moved == 1 {
                 // todo k=0
                 Ok(1)
             } else {
@@ -164,7 +166,7 @@ fn get_time(core: Core, inst: Instruction, sim: Sim) -> Result<u8> {
             }
         }
         Opcode::LD => {
-            match inst.operands.unwrap()[2].value {
+            match inst.operands.as_ref().ok_or(anyhow!("missing operands"))?[2].value {
                 0 => {
                     match core {
                         Core::AVR | Core::AVRe | Core::AVRep => Ok(2),
@@ -211,7 +213,7 @@ fn get_time(core: Core, inst: Instruction, sim: Sim) -> Result<u8> {
         Opcode::ST => match core {
             Core::AVR | Core::AVRe | Core::AVRep => Ok(2),
             Core::AVRxt => Ok(1),
-            Core::AVRxm | Core::AVRrc => match inst.operands.unwrap()[2].value {
+            Core::AVRxm | Core::AVRrc => match inst.operands.as_ref().ok_or(anyhow!("missing operands"))?[2].value {
                 2 => Ok(2),
                 _ => Ok(1),
             },
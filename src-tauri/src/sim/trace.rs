@@ -0,0 +1,192 @@
+use crate::error::Result;
+use crate::sim::memory::{AccessKind, MemAccess};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+
+const BINARY_MAGIC: &[u8; 4] = b"AVRT";
+const BINARY_VERSION: u8 = 1;
+const SREG_FLAGS: &[u8; 8] = b"ITHSVNZC";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TraceFormat {
+    Text,   // simavr -t like listing
+    Binary, // see Trace::to_binary
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TraceEntry {
+    pub cycle: u64,
+    pub pc: u32,
+    pub opcode: u32,
+    pub mnemonic: String,
+    pub registers: Vec<(u8, u8)>, // (register, new value) of every register written
+    pub sreg_before: u8,
+    pub sreg_after: u8,
+    pub accesses: Vec<MemAccess>,
+}
+
+/// ring buffer of the last executed instructions, a capacity of 0 disables recording
+#[derive(Debug, Default)]
+pub struct Trace {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl Trace {
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+    pub fn push(&mut self, entry: TraceEntry) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    fn sreg_to_string(sreg: u8) -> String {
+        SREG_FLAGS
+            .iter()
+            .enumerate()
+            .map(|(i, flag)| {
+                if sreg & (0x80 >> i) != 0 {
+                    *flag as char
+                } else {
+                    '.'
+                }
+            })
+            .collect()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for entry in &self.entries {
+            let _ = write!(
+                out,
+                "{:>10} {:04x}: {:<25}",
+                entry.cycle, entry.pc, entry.mnemonic
+            );
+            for (reg, val) in &entry.registers {
+                let _ = write!(out, " ->[r{}={:02x}]", reg, val);
+            }
+            let _ = write!(out, " SREG={}", Self::sreg_to_string(entry.sreg_before));
+            if entry.sreg_before != entry.sreg_after {
+                let _ = write!(out, "->{}", Self::sreg_to_string(entry.sreg_after));
+            }
+            for access in &entry.accesses {
+                let kind = match access.kind {
+                    AccessKind::Read => 'R',
+                    AccessKind::Write => 'W',
+                };
+                let _ = write!(out, " {}[{:#06x}]={:#04x}", kind, access.address, access.value);
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// "AVRT", version u8, entry count u32, then per entry (little endian):
+    /// cycle u64, pc u32, opcode u32, sreg before u8, sreg after u8,
+    /// register count u8 + (register u8, value u8)*, access count u8 + (kind u8, address u16, value u8)*
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(BINARY_MAGIC);
+        out.push(BINARY_VERSION);
+        out.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for entry in &self.entries {
+            out.extend_from_slice(&entry.cycle.to_le_bytes());
+            out.extend_from_slice(&entry.pc.to_le_bytes());
+            out.extend_from_slice(&entry.opcode.to_le_bytes());
+            out.push(entry.sreg_before);
+            out.push(entry.sreg_after);
+            out.push(entry.registers.len() as u8);
+            for (reg, val) in &entry.registers {
+                out.push(*reg);
+                out.push(*val);
+            }
+            out.push(entry.accesses.len() as u8);
+            for access in &entry.accesses {
+                out.push(access.kind as u8);
+                out.extend_from_slice(&(access.address as u16).to_le_bytes());
+                out.push(access.value);
+            }
+        }
+        out
+    }
+
+    pub fn export(&self, path: &str, format: TraceFormat) -> Result<()> {
+        match format {
+            TraceFormat::Text => std::fs::write(path, self.to_text())?,
+            TraceFormat::Binary => std::fs::write(path, self.to_binary())?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(cycle: u64) -> TraceEntry {
+        TraceEntry {
+            cycle,
+            pc: 0x12,
+            opcode: 0xef0f,
+            mnemonic: "ldi r16, 0xff".to_string(),
+            registers: vec![(16, 0xff)],
+            sreg_before: 0x02,
+            sreg_after: 0x02,
+            accesses: vec![],
+        }
+    }
+
+    #[test]
+    fn test_ring_bound() {
+        let mut trace = Trace::default();
+        trace.push(entry(0));
+        assert_eq!(trace.entries.len(), 0);
+        trace.set_capacity(2);
+        (0..5).for_each(|x| trace.push(entry(x)));
+        assert_eq!(trace.entries.len(), 2);
+        assert_eq!(trace.entries[0].cycle, 3);
+        trace.set_capacity(1);
+        assert_eq!(trace.entries[0].cycle, 4);
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut trace = Trace::default();
+        trace.set_capacity(4);
+        let mut e = entry(7);
+        e.sreg_after = 0x03;
+        e.accesses.push(MemAccess {
+            kind: AccessKind::Write,
+            address: 0x100,
+            value: 5,
+            width: 1,
+            pc: 0,
+        });
+        trace.push(e);
+        assert_eq!(
+            trace.to_text(),
+            format!(
+                "{:>10} 0012: {:<25} ->[r16=ff] SREG=......Z.->......ZC W[0x0100]=0x05\n",
+                7, "ldi r16, 0xff"
+            )
+        );
+        let bin = trace.to_binary();
+        assert_eq!(&bin[0..5], b"AVRT\x01");
+        assert_eq!(&bin[5..9], &1u32.to_le_bytes());
+        assert_eq!(bin.len(), 9 + 8 + 4 + 4 + 2 + 1 + 2 + 1 + 4);
+    }
+}
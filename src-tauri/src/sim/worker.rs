@@ -1,11 +1,21 @@
 use crate::emit;
 use crate::project::PROJECT;
 use crate::sim::controller::Action;
+use crate::sim::coverage::Coverage;
+use crate::sim::debug_info::DebugInfo;
 use crate::sim::instruction::Instruction;
-use crate::sim::memory::Memory;
+use crate::sim::inspector::{self, MemoryRange};
+use crate::sim::memory::{Memory, MemorySnapshot};
+use crate::sim::peripherals::analog::AnalogSource;
+use crate::sim::peripherals::pinout::Package;
+use crate::sim::peripherals::spi::Spi;
+use crate::sim::peripherals::twi::Twi;
+use crate::sim::peripherals::{self, Peripherals};
+use crate::sim::profile::Profile;
 use crate::sim::sim::Sim;
+use crate::sim::trace::{Trace, TraceEntry};
 use anyhow::anyhow;
-use device_parser::{AvrDeviceFile, Register};
+use device_parser::{AvrDeviceFile, RegisterMap};
 use opcode_gen::Opcode;
 use std::collections::HashMap;
 use std::sync::mpsc::{Receiver, Sender};
@@ -17,7 +27,7 @@ use std::{thread, time};
 #[derive(Default)]
 pub struct Worker<'a> {
     atdf: &'static AvrDeviceFile,
-    reg_map: Option<&'static phf::Map<u64, &'static Register>>,
+    reg_map: Option<RegisterMap>,
     memory: Memory,
     sim: Sim<'a>,
     action: Action,
@@ -26,6 +36,11 @@ pub struct Worker<'a> {
     breakpoints: Vec<u32>,
     watch_list: HashMap<String, u32>,
     update_watch_list: bool,
+    trace: Trace,
+    profile: Profile,
+    coverage: Coverage,
+    debug_info: Option<DebugInfo>,
+    snapshot: MemorySnapshot, // memory when the simulation was last resumed
     rx: Option<Receiver<Action>>,
     tx: Option<Sender<crate::sim::controller::Response>>,
 }
@@ -37,9 +52,7 @@ impl<'a> Worker<'a> {
         let f = || -> crate::error::Result<()> {
             let mut project_lock = PROJECT.lock().map_err(|e| anyhow!("Poison Error:{}", e))?;
             let mcu = project_lock.get_state()?.mcu.clone();
-            self.atdf = device_parser::get_tree_map()
-                .get(&*mcu)
-                .ok_or(anyhow!("invalid mcu"))?;
+            self.atdf = device_parser::get_device(&mcu).ok_or(anyhow!("invalid mcu"))?;
 
 
             self.sim
@@ -69,18 +82,109 @@ impl<'a> Worker<'a> {
             .is_some()
     }
 
+    /// executes one instruction, updating the profile and the trace when it is enabled
+    unsafe fn step(&mut self) -> crate::error::Result<()> {
+        let pc = self.memory.program_couter;
+        let cycle = self.sim.cycles;
+        let instruction = self
+            .memory
+            .flash
+            .get(pc as usize)
+            .ok_or(anyhow!("invalid address:{}", pc))?;
+        let raw_inst = instruction.get_raw_inst()?;
+        let (name, len) = (raw_inst.name.clone(), raw_inst.len);
+        let traced = match self.trace.is_enabled() {
+            true => Some((
+                instruction.raw_opcode,
+                instruction.mnemonic()?,
+                self.memory.data.registers.clone(),
+                unsafe { self.sim.get_sreg() },
+            )),
+            false => None,
+        };
+        unsafe { self.sim.execute_inst()? };
+        // an interrupt or reset is not part of the instruction
+        let (next_pc, end) = self.sim.retired;
+        self.profile.record(pc, &name, cycle, end, next_pc);
+        self.coverage.record(pc, &name, len, next_pc);
+        if let Some(vector) = self.sim.interrupted {
+            self.profile.enter(vector, end);
+        }
+        if let Some(cause) = self.sim.reset {
+            let event = (pc, cause);
+            emit!("sim-reset", &event);
+        }
+        for access in self.sim.peripherals.power.take_diagnostics() {
+            let event = (pc, access);
+            emit!("sim-gated-access", &event);
+        }
+        for write in self.sim.peripherals.access.take_diagnostics() {
+            let event = (pc, write);
+            emit!("sim-reserved-write", &event);
+        }
+
+        if let Some((opcode, mnemonic, registers, sreg_before)) = traced {
+            self.trace.push(TraceEntry {
+                cycle,
+                pc,
+                opcode,
+                mnemonic,
+                registers: self
+                    .memory
+                    .data
+                    .registers
+                    .iter()
+                    .zip(registers.iter())
+                    .enumerate()
+                    .filter(|(_, (new, old))| new != old)
+                    .map(|(i, (new, _))| (i as u8, *new))
+                    .collect(),
+                sreg_before,
+                sreg_after: unsafe { self.sim.get_sreg() },
+                accesses: self.sim.accesses.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn register_map(&mut self) -> crate::error::Result<RegisterMap> {
+        if self.reg_map.is_none() {
+            self.reg_map = device_parser::get_register_map(
+                &PROJECT
+                    .lock()
+                    .map_err(|e| anyhow!("poison error:{}", e))?
+                    .get_state()?
+                    .mcu,
+            );
+        }
+        self.reg_map.ok_or(anyhow!("could not get register map"))
+    }
+
+    fn spi(&mut self, name: &str) -> crate::error::Result<&mut Spi> {
+        peripherals::find(&mut self.sim.peripherals.models, name)
+            .ok_or(anyhow!("invalid spi:{}", name))
+    }
+
+    /// state edits are only allowed while the simulation is paused
+    fn check_paused(&self) -> crate::error::Result<()> {
+        if self.action_prev != Action::Pause {
+            return Err(anyhow!("simulation is not paused"));
+        }
+        Ok(())
+    }
+
     unsafe fn iner(&mut self) -> crate::error::Result<bool> { // true terminates
-        match self.action {
+        match self.action.clone() {
             Action::Run => {
                 if self.action_prev != Action::Run {
                     self.action_prev = Action::Run;
                     emit!("sim-status", Action::Run);
-                    unsafe { self.sim.execute_inst()? };
+                    unsafe { self.step()? };
                 }
                 if self.check_brekpoint() {
                     self.action = Action::Pause;
                 } else {
-                    unsafe { self.sim.execute_inst()? }
+                    unsafe { self.step()? }
                 }
                 if self.update_watch_list && self.memory.data.io.write_status {
                     self.memory.data.io.write_status = false;
@@ -138,12 +242,12 @@ impl<'a> Worker<'a> {
                 } else {
                     self.breakpoints.push(address);
                 }
-                self.action = self.action_prev;
+                self.action = self.action_prev.clone();
                 emit!("breakpoints-update", self.breakpoints.clone());
                 Ok(false)
             }
             Action::Next => {
-                unsafe { self.sim.execute_inst()? }
+                unsafe { self.step()? }
                 self.action = Action::Pause;
                 Ok(false)
             }
@@ -154,8 +258,9 @@ impl<'a> Worker<'a> {
                         .flash
                         .get(self.memory.program_couter as usize)
                         .ok_or(anyhow!("invalid address:{}", self.memory.program_couter))?;
-                    unsafe { self.sim.execute_inst()? };
-                    match i.get_raw_inst()?.name {
+                    let name = i.get_raw_inst()?.name.clone();
+                    unsafe { self.step()? };
+                    match name {
                         Opcode::CALL | Opcode::ICALL | Opcode::EICALL | Opcode::RCALL => {
                             unsafe { self.iner()? };
                         }
@@ -169,15 +274,7 @@ impl<'a> Worker<'a> {
                 Ok(false)
             }
             Action::Watch(data) => {
-                if self.reg_map.is_none() {
-                    self.reg_map = device_parser::get_register_map(
-                        &PROJECT
-                            .lock()
-                            .map_err(|e| anyhow!("poison error:{}", e))?
-                            .get_state()?
-                            .mcu,
-                    );
-                }
+                let reg_map = self.register_map()?;
                 let mut name: String = String::new();
                 data.iter().for_each(|x1| {
                     if *x1 > 0 {
@@ -185,10 +282,8 @@ impl<'a> Worker<'a> {
                     }
                 });
                 name = name.to_uppercase();
-                let address: u32 = *self
-                    .reg_map
-                    .ok_or(anyhow!("could not get common regs"))?
-                    .into_iter()
+                let address: u32 = reg_map
+                    .entries()
                     .find(|(_, reg)| reg.name == name)
                     .ok_or(anyhow!("invalid register"))?
                     .0 as u32;
@@ -197,7 +292,7 @@ impl<'a> Worker<'a> {
                 } else {
                     self.watch_list.insert(name, address);
                 }
-                self.action = self.action_prev;
+                self.action = self.action_prev.clone();
                 self.memory.data.io.watchlist = self
                     .watch_list
                     .iter()
@@ -221,11 +316,173 @@ impl<'a> Worker<'a> {
                 Ok(false)
             }
             Action::WatchUpdate(data) => {
-                self.action = self.action_prev;
+                self.action = self.action_prev.clone();
                 self.update_watch_list = data;
                 emit!("auto_update_status", self.update_watch_list);
                 Ok(false)
             }
+            Action::Trace(size) => {
+                self.action = self.action_prev.clone();
+                self.trace.set_capacity(size as usize);
+                emit!("sim-trace-status", size);
+                Ok(false)
+            }
+            Action::TraceExport(path, format) => {
+                self.action = self.action_prev.clone();
+                self.trace.export(&path, format)?;
+                Ok(false)
+            }
+            Action::SetRegister(index, value) => {
+                self.action = self.action_prev.clone();
+                self.check_paused()?;
+                self.sim.set_register(index, value)?;
+                Ok(false)
+            }
+            Action::SetFlag(bit, value) => {
+                self.action = self.action_prev.clone();
+                self.check_paused()?;
+                unsafe { self.sim.set_sreg_flag(bit, value)? };
+                Ok(false)
+            }
+            Action::SetSp(sp) => {
+                self.action = self.action_prev.clone();
+                self.check_paused()?;
+                unsafe { self.sim.set_sp(sp)? };
+                Ok(false)
+            }
+            Action::SetPc(pc) => {
+                self.action = self.action_prev.clone();
+                self.check_paused()?;
+                self.sim.set_pc(pc)?;
+                Ok(false)
+            }
+            Action::Poke(space, address, value) => {
+                self.action = self.action_prev.clone();
+                self.check_paused()?;
+                self.sim.poke(space, address, value)?;
+                Ok(false)
+            }
+            Action::ReadMemory(space, address, len) => {
+                self.action = self.action_prev.clone();
+                let data = self.memory.read(space, address, len)?;
+                let range = MemoryRange {
+                    space,
+                    address,
+                    changed: self.memory.changed(&self.snapshot, space, address, &data),
+                    data,
+                };
+                emit!("sim-memory", range);
+                Ok(false)
+            }
+            Action::IoView => {
+                self.action = self.action_prev.clone();
+                let reg_map = self.register_map()?;
+                emit!("sim-io-view", inspector::io_view(reg_map, &self.memory));
+                Ok(false)
+            }
+            Action::DrivePin(pad, level) => {
+                self.action = self.action_prev.clone();
+                let gpio = &mut self.sim.peripherals.gpio;
+                gpio.drive(pad.parse()?, level)?;
+                gpio.update(&mut self.memory.data, &[]);
+                Ok(false)
+            }
+            Action::PeripheralSupport => {
+                self.action = self.action_prev.clone();
+                let support = self.sim.peripherals.support(self.atdf);
+                emit!("sim-peripheral-support", &support);
+                Ok(false)
+            }
+            Action::Pinout => {
+                self.action = self.action_prev.clone();
+                let mut packages = Package::all(self.atdf);
+                for x in packages.iter_mut() {
+                    x.update(&self.sim.peripherals.gpio, &self.memory.data);
+                }
+                emit!("sim-pinout", &packages);
+                Ok(false)
+            }
+            Action::ReservedWarnings(enabled) => {
+                self.action = self.action_prev.clone();
+                self.sim.peripherals.access.warnings = enabled;
+                Ok(false)
+            }
+            Action::DrivePinAt(pad, level, cycle) => {
+                self.action = self.action_prev.clone();
+                self.sim.peripherals.gpio.schedule(pad.parse()?, level, cycle)?;
+                Ok(false)
+            }
+            Action::SpiAttach(name, kind, select) => {
+                self.action = self.action_prev.clone();
+                let select = select.map(|x| x.parse()).transpose()?;
+                self.spi(&name)?.attach(kind.create(), select);
+                Ok(false)
+            }
+            Action::SpiTransfer(name, mosi) => {
+                self.action = self.action_prev.clone();
+                let Peripherals { models, gpio, .. } = &mut self.sim.peripherals;
+                let spi: &mut Spi =
+                    peripherals::find(models, &name).ok_or(anyhow!("invalid spi:{}", name))?;
+                let miso = spi.receive(&mut self.memory.data, gpio, mosi)?;
+                emit!("sim-spi-transfer", miso);
+                Ok(false)
+            }
+            Action::I2cAttach(name, address, kind) => {
+                self.action = self.action_prev.clone();
+                peripherals::find::<Twi>(&mut self.sim.peripherals.models, &name)
+                    .ok_or(anyhow!("invalid twi:{}", name))?
+                    .bus
+                    .attach(address, kind.create())?;
+                Ok(false)
+            }
+            Action::SetAnalog(net, source) => {
+                self.action = self.action_prev.clone();
+                self.sim.peripherals.analog.set(net, source);
+                Ok(false)
+            }
+            Action::LoadAnalogCsv(net, path) => {
+                self.action = self.action_prev.clone();
+                let source = AnalogSource::from_csv(&std::fs::read_to_string(path)?)?;
+                self.sim.peripherals.analog.set(net, source);
+                Ok(false)
+            }
+            Action::ProfileGet => {
+                self.action = self.action_prev.clone();
+                emit!(
+                    "sim-profile",
+                    self.profile.report(self.sim.cycles, self.debug_info.as_ref())
+                );
+                Ok(false)
+            }
+            Action::LoadElf(path) => {
+                self.action = self.action_prev.clone();
+                self.debug_info = Some(DebugInfo::load(&path)?);
+                Ok(false)
+            }
+            Action::CoverageExport(path, format) => {
+                self.action = self.action_prev.clone();
+                self.coverage.export(
+                    &path,
+                    format,
+                    &self.memory.flash,
+                    self.debug_info.as_ref(),
+                )?;
+                Ok(false)
+            }
+            Action::CoverageReset => {
+                self.action = self.action_prev.clone();
+                self.coverage.reset();
+                Ok(false)
+            }
+            Action::ProfileReset => {
+                self.action = self.action_prev.clone();
+                self.profile.reset();
+                emit!(
+                    "sim-profile",
+                    self.profile.report(self.sim.cycles, self.debug_info.as_ref())
+                );
+                Ok(false)
+            }
         }
     }
     pub fn thread_run(&mut self) ->bool {
@@ -233,7 +490,12 @@ impl<'a> Worker<'a> {
         if let Some(rx) = self.rx.as_ref() {
             match rx.try_recv() {
                 Ok(action) => {
-                    self.action_prev = self.action;
+                    if matches!(action, Action::Run | Action::Next | Action::Skip)
+                        && self.action != Action::Run
+                    {
+                        self.snapshot = self.memory.snapshot().unwrap_or_default();
+                    }
+                    self.action_prev = self.action.clone();
                     self.action = action;
                     self.action_executed = false;
                     return_res = true